@@ -1,16 +1,75 @@
+use sdl2::image::ImageRWops;
+use sdl2::pixels::PixelFormatEnum;
 use sdl2::render::{Texture, TextureCreator};
-use sdl2::image::LoadTexture;
+use sdl2::rwops::RWops;
+use sdl2::surface::Surface;
 use sdl2::ttf::{Font, Sdl2TtfContext};
 
+use crate::error::Error;
+use crate::loading::Background;
+use crate::vfs::Vfs;
+
 use std::borrow::Borrow;
 use std::collections::HashMap;
+use std::fs;
 use std::hash::Hash;
+use std::path::PathBuf;
 use std::rc::Rc;
+use std::time::{Duration, Instant};
+
 
+const PLACEHOLDER_SIZE: u32 = 512;
+const PLACEHOLDER_CHECK: u32 = 4;
+const DEFAULT_FONT: &[u8] = include_bytes!("../assets/fonts/Pono_188.ttf");
 
 pub type TextureManager<'l, T> = ResourceManager<'l, String, Texture<'l>, TextureCreator<T>>;
 pub type FontManager<'l> = ResourceManager<'l, FontDetails, Font<'l, 'static>, Sdl2TtfContext>;
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadState {
+    Pending,
+    Loaded,
+    Failed(String),
+}
+
+// What happens when something can't be loaded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fallback {
+    // the error goes back to whoever asked
+    Strict,
+    // the error is logged and a stand-in that's obviously wrong on screen
+    // is used instead, so one bad file doesn't stop the game
+    Placeholder,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub entries: usize,
+    // a rough estimate, see ResourceLoader::size
+    pub bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub pending: usize,
+    pub failed: usize,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f32 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            return 0.0;
+        }
+        self.hits as f32 / total as f32
+    }
+}
+
+struct Cached<R> {
+    resource: Rc<R>,
+    bytes: usize,
+    // the manager's use count when it was last handed out, oldest goes first
+    used: u64,
+}
+
 // Generic struct to cache any resource loaded by a ResourceLoader
 pub struct ResourceManager<'l, K, R, L>
 where
@@ -18,67 +77,415 @@ where
     L: 'l + ResourceLoader<'l, R>,
 {
     loader: &'l L,
-    cache: HashMap<K, Rc<R>>,
+    // where paths are looked up, so mods can replace what's loaded
+    vfs: &'l Vfs,
+    cache: HashMap<K, Cached<R>>,
+    // asked for with request() and still being read
+    pending: Vec<K>,
+    // requests unloaded before they arrived, whose results get thrown away
+    cancelled: Vec<K>,
+    failed: HashMap<K, String>,
+    // total of every cached entry's bytes
+    bytes: usize,
+    // started by the first request()
+    background: Option<Background<K, L::Data>>,
+    uses: u64,
+    hits: u64,
+    misses: u64,
+    // when set, the least recently used entries nothing else holds are
+    // dropped to stay under this many bytes
+    pub budget: Option<usize>,
+    pub fallback: Fallback,
 }
 
 impl<'l, K, R, L> ResourceManager<'l, K, R, L>
 where
-    K: Hash + Eq,
+    K: Hash + Eq + Clone,
     L: ResourceLoader<'l, R>,
 {
-    pub fn new(loader: &'l L) -> Self {
+    pub fn new(loader: &'l L, vfs: &'l Vfs) -> Self {
         ResourceManager {
             cache: HashMap::new(),
             loader: loader,
+            vfs: vfs,
+            pending: Vec::new(),
+            cancelled: Vec::new(),
+            failed: HashMap::new(),
+            bytes: 0,
+            background: None,
+            uses: 0,
+            hits: 0,
+            misses: 0,
+            budget: None,
+            fallback: Fallback::Strict,
         }
     }
 
     // Generics magic to allow a HashMap to use String as a key
     // while allowing it to use &str for gets
-    pub fn load<D>(&mut self, details: &D) -> Result<Rc<R>, String>
+    pub fn load<D>(&mut self, details: &D) -> Result<Rc<R>, Error>
     where
         L: ResourceLoader<'l, R, Args = D>,
         D: Eq + Hash + ?Sized,
         K: Borrow<D> + for<'a> From<&'a D>,
     {
-        self.cache.get(details).cloned().map_or_else(
-            || {
-                let resource = Rc::new(self.loader.load(details)?);
-                self.cache.insert(details.into(), resource.clone());
+        self.uses += 1;
+        if let Some(cached) = self.cache.get_mut(details) {
+            self.hits += 1;
+            cached.used = self.uses;
+            return Ok(cached.resource.clone());
+        }
+        self.misses += 1;
+        if self.pending.iter().any(|k| k.borrow() == details) {
+            // already on its way, so wait for it rather than read it twice
+            while let Some((key, result)) = self.background.as_ref().and_then(Background::recv) {
+                let wanted = key.borrow() == details;
+                match self.arrived(key, result) {
+                    Some(loaded) if wanted => return loaded,
+                    _ => {}
+                }
+            }
+        }
+        let result = L::read(self.vfs, details);
+        self.finish(details.into(), result)
+    }
+
+    // Starts reading `details` on the background thread without waiting for
+    // it; poll() picks it up when it's ready
+    pub fn request<D>(&mut self, details: &D)
+    where
+        L: ResourceLoader<'l, R, Args = D>,
+        D: Eq + Hash + ?Sized,
+        K: Borrow<D> + for<'a> From<&'a D> + Send + 'static,
+    {
+        if self.cache.contains_key(details) || self.pending.iter().any(|k| k.borrow() == details) {
+            return;
+        }
+        self.misses += 1;
+        self.failed.remove(details);
+        let key: K = details.into();
+        let read: fn(&Vfs, &K) -> Result<L::Data, Error> = |vfs, key| L::read(vfs, key.borrow());
+        let vfs = self.vfs;
+        self.background.get_or_insert_with(|| Background::start(vfs.clone(), read)).send(key.clone());
+        self.pending.push(key);
+    }
+
+    // Finishes whatever the background thread has read, for up to `budget`
+    // so a frame isn't held up. Returns how many were finished.
+    pub fn poll(&mut self, budget: Duration) -> usize
+    where
+        K: Borrow<L::Args>,
+    {
+        let start = Instant::now();
+        let mut finished = 0;
+        while finished == 0 || start.elapsed() < budget {
+            match self.background.as_ref().and_then(Background::try_recv) {
+                Some((key, result)) => {
+                    let _ = self.arrived(key, result);
+                    finished += 1;
+                }
+                None => break,
+            }
+        }
+        finished
+    }
+
+    // A result from the background thread, or None if its request was
+    // cancelled. Results come back in the order they were asked for, so the
+    // first one for a cancelled key is the one to drop.
+    fn arrived(&mut self, key: K, result: Result<L::Data, Error>) -> Option<Result<Rc<R>, Error>>
+    where
+        K: Borrow<L::Args>,
+    {
+        if let Some(i) = self.cancelled.iter().position(|k| *k == key) {
+            self.cancelled.remove(i);
+            return None;
+        }
+        Some(self.finish(key, result))
+    }
+
+    fn finish(&mut self, key: K, result: Result<L::Data, Error>) -> Result<Rc<R>, Error>
+    where
+        K: Borrow<L::Args>,
+    {
+        self.pending.retain(|k| *k != key);
+        match result.and_then(|data| self.insert(key.clone(), data)) {
+            Ok(resource) => {
+                self.failed.remove::<K>(&key);
                 Ok(resource)
-            },
-            Ok,
-        )
+            }
+            Err(e) => self.fail(key, e),
+        }
+    }
+
+    // Remembers why `key` couldn't be loaded and, unless strict, caches a
+    // placeholder in its place
+    fn fail(&mut self, key: K, e: Error) -> Result<Rc<R>, Error>
+    where
+        K: Borrow<L::Args>,
+    {
+        self.failed.insert(key.clone(), e.to_string());
+        if self.fallback == Fallback::Strict {
+            warn!("{}", e);
+            return Err(e);
+        }
+        warn!("{}; using a placeholder", e);
+        let placeholder = Rc::new(self.loader.placeholder(key.borrow())?);
+        self.uses += 1;
+        self.cache_insert(key, Cached { resource: placeholder.clone(), bytes: 0, used: self.uses });
+        Ok(placeholder)
+    }
+
+    fn insert(&mut self, key: K, data: L::Data) -> Result<Rc<R>, Error> {
+        let bytes = L::size(&data);
+        let resource = Rc::new(self.loader.create(data)?);
+        self.uses += 1;
+        self.cache_insert(key, Cached { resource: resource.clone(), bytes, used: self.uses });
+        if let Some(budget) = self.budget {
+            self.trim(budget);
+        }
+        Ok(resource)
+    }
+
+    // None for anything never asked for
+    pub fn state<D>(&self, details: &D) -> Option<LoadState>
+    where
+        D: Eq + Hash + ?Sized,
+        K: Borrow<D>,
+    {
+        // a placeholder may be cached for it, but it still failed
+        if let Some(e) = self.failed.get(details) {
+            Some(LoadState::Failed(e.clone()))
+        }
+        else if self.cache.contains_key(details) {
+            Some(LoadState::Loaded)
+        }
+        else if self.pending.iter().any(|k| k.borrow() == details) {
+            Some(LoadState::Pending)
+        }
+        else {
+            None
+        }
+    }
+
+    pub fn contains<D>(&self, details: &D) -> bool
+    where
+        D: Eq + Hash + ?Sized,
+        K: Borrow<D>,
+    {
+        self.cache.contains_key(details)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.cache.keys()
+    }
+
+    // Forgets `details`, including a request still being read. Anything
+    // still holding it keeps its copy; the next load() reads it again.
+    pub fn unload<D>(&mut self, details: &D) -> bool
+    where
+        D: Eq + Hash + ?Sized,
+        K: Borrow<D>,
+    {
+        self.failed.remove(details);
+        if let Some(i) = self.pending.iter().position(|k| k.borrow() == details) {
+            let key = self.pending.remove(i);
+            self.cancelled.push(key);
+        }
+        match self.cache.remove(details) {
+            Some(cached) => {
+                self.bytes -= cached.bytes;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Reads `details` again, e.g. after the file changed. Only what's
+    // loaded from here on gets the new one.
+    pub fn reload<D>(&mut self, details: &D) -> Result<Rc<R>, Error>
+    where
+        L: ResourceLoader<'l, R, Args = D>,
+        D: Eq + Hash + ?Sized,
+        K: Borrow<D> + for<'a> From<&'a D>,
+    {
+        self.unload(details);
+        self.load(details)
+    }
+
+    pub fn clear(&mut self) {
+        self.cache.clear();
+        self.failed.clear();
+        self.bytes = 0;
+        self.cancelled.append(&mut self.pending);
+    }
+
+    // Drops everything only the cache is holding on to, e.g. between
+    // levels. Returns how many went.
+    pub fn evict_unused(&mut self) -> usize {
+        let (before, mut freed) = (self.cache.len(), 0);
+        self.cache.retain(|_, cached| {
+            let keep = Rc::strong_count(&cached.resource) > 1;
+            if !keep {
+                freed += cached.bytes;
+            }
+            keep
+        });
+        self.bytes -= freed;
+        before - self.cache.len()
+    }
+
+    // Drops the least recently used entries nothing else holds until the
+    // cache is under `budget` bytes, or nothing more can go
+    pub fn trim(&mut self, budget: usize) -> usize {
+        if self.bytes <= budget {
+            return 0;
+        }
+        let mut unused: Vec<(u64, K)> = self
+            .cache
+            .iter()
+            .filter(|(_, cached)| Rc::strong_count(&cached.resource) == 1)
+            .map(|(key, cached)| (cached.used, key.clone()))
+            .collect();
+        unused.sort_by_key(|(used, _)| *used);
+        let mut evicted = 0;
+        for (_, key) in unused {
+            if self.bytes <= budget {
+                break;
+            }
+            if let Some(cached) = self.cache.remove(&key) {
+                self.bytes -= cached.bytes;
+                evicted += 1;
+            }
+        }
+        evicted
+    }
+
+    fn cache_insert(&mut self, key: K, cached: Cached<R>) {
+        self.bytes += cached.bytes;
+        if let Some(old) = self.cache.insert(key, cached) {
+            self.bytes -= old.bytes;
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.cache.len(),
+            bytes: self.bytes,
+            hits: self.hits,
+            misses: self.misses,
+            pending: self.pending.len(),
+            failed: self.failed.len(),
+        }
+    }
+
+    // What's in the cache and how many handles to each are still out
+    pub fn cached(&self) -> Vec<(&K, usize)> {
+        self.cache.iter().map(|(k, cached)| (k, Rc::strong_count(&cached.resource))).collect()
+    }
+}
+
+// An image decoded off the main thread, waiting to become a texture
+pub struct Pixels {
+    pub width: u32,
+    pub height: u32,
+    pitch: u32,
+    data: Vec<u8>,
+}
+
+impl Pixels {
+    // Decodes the image at `path`; needs no window or renderer
+    pub fn read(vfs: &Vfs, path: &str) -> Result<Pixels, Error> {
+        let bytes = vfs.read(path)?;
+        let fail = |e: String| Error::decode(vfs.resolve(path), e);
+        let surface = RWops::from_bytes(&bytes).and_then(|rw| rw.load()).map_err(fail)?;
+        let surface = surface.convert_format(PixelFormatEnum::RGBA32).map_err(fail)?;
+        Ok(Pixels {
+            width: surface.width(),
+            height: surface.height(),
+            pitch: surface.pitch(),
+            data: surface.with_lock(|pixels| pixels.to_vec()),
+        })
     }
 }
 
 // TextureCreator knows how to load Textures
 impl<'l, T> ResourceLoader<'l, Texture<'l>> for TextureCreator<T> {
     type Args = str;
-    fn load(&'l self, path: &str) -> Result<Texture, String> {
-        println!("LOADED A TEXTURE");
-        self.load_texture(path)
+    type Data = Pixels;
+    fn read(vfs: &Vfs, path: &str) -> Result<Pixels, Error> {
+        debug!("loading texture {}", vfs.resolve(path));
+        Pixels::read(vfs, path)
+    }
+
+    // as uploaded, near enough
+    fn size(pixels: &Pixels) -> usize {
+        pixels.data.len()
+    }
+
+    fn create(&'l self, mut pixels: Pixels) -> Result<Texture<'l>, Error> {
+        let surface = Surface::from_data(&mut pixels.data, pixels.width, pixels.height, pixels.pitch, PixelFormatEnum::RGBA32).map_err(Error::render)?;
+        self.create_texture_from_surface(&surface).map_err(Error::render)
+    }
+
+    // A magenta and black checkerboard, big enough that any sprite rect
+    // lands on it
+    fn placeholder(&'l self, _: &str) -> Result<Texture<'l>, Error> {
+        let (size, check) = (PLACEHOLDER_SIZE, PLACEHOLDER_CHECK);
+        let mut data = Vec::with_capacity((size * size * 4) as usize);
+        for y in 0..size {
+            for x in 0..size {
+                let magenta = (x / check + y / check) % 2 == 0;
+                data.extend_from_slice(if magenta { &[255, 0, 255, 255] } else { &[0, 0, 0, 255] });
+            }
+        }
+        self.create(Pixels { width: size, height: size, pitch: size * 4, data })
     }
 }
 
 // Font Context knows how to load Fonts
 impl<'l> ResourceLoader<'l, Font<'l, 'static>> for Sdl2TtfContext {
     type Args = FontDetails;
-    fn load(&'l self, details: &FontDetails) -> Result<Font<'l, 'static>, String> {
-        println!("LOADED A FONT");
-        self.load_font(&details.path, details.size)
+    // SDL_ttf reads glyphs from the file for as long as the font lives, so
+    // all that can be done ahead is getting it a real file
+    type Data = (PathBuf, FontDetails);
+    fn read(vfs: &Vfs, details: &FontDetails) -> Result<Self::Data, Error> {
+        debug!("loading font {} at {}pt", vfs.resolve(&details.path), details.size);
+        Ok((vfs.file(&details.path)?, details.clone()))
+    }
+
+    fn size((file, _): &Self::Data) -> usize {
+        fs::metadata(file).map_or(0, |m| m.len() as usize)
+    }
+
+    fn create(&'l self, (file, details): Self::Data) -> Result<Font<'l, 'static>, Error> {
+        self.load_font(&file, details.size).map_err(|e| Error::decode(&details.path, e))
+    }
+
+    // The game's own font, built in so it's there whatever's on disk
+    fn placeholder(&'l self, details: &FontDetails) -> Result<Font<'l, 'static>, Error> {
+        let fail = |e: String| Error::decode("built-in font", e);
+        self.load_font_from_rwops(RWops::from_bytes(DEFAULT_FONT).map_err(fail)?, details.size).map_err(fail)
     }
 }
 
-// Generic trait to Load any Resource Kind
+// Generic trait to Load any Resource Kind, in two halves: read() can run on
+// any thread and does the slow part, create() runs where the resource is
+// used
 pub trait ResourceLoader<'l, R> {
     type Args: ?Sized;
-    fn load(&'l self, data: &Self::Args) -> Result<R, String>;
-    //fn get(&'l self, data: &Self::Args) -> Result<R, String>;
+    type Data: Send + 'static;
+    fn read(vfs: &Vfs, data: &Self::Args) -> Result<Self::Data, Error>;
+    // roughly how many bytes what's made from `data` will take up
+    fn size(data: &Self::Data) -> usize;
+    fn create(&'l self, data: Self::Data) -> Result<R, Error>;
+    // what to use instead under Fallback::Placeholder
+    fn placeholder(&'l self, data: &Self::Args) -> Result<R, Error>;
+    //fn get(&'l self, data: &Self::Args) -> Result<R, Error>;
 }
 
 // Information needed to load a Font
-#[derive(PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct FontDetails {
     pub path: String,
     pub size: u16,
@@ -91,4 +498,4 @@ impl<'a> From<&'a FontDetails> for FontDetails {
             size: details.size,
         }
     }
-}
\ No newline at end of file
+}
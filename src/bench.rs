@@ -0,0 +1,338 @@
+//bench module - runs the simulation on its own, as fast as it will go, so
+//runs from the same seed can be compared, and soaks it for hours to catch
+//anything that keeps growing
+//
+//  game-rs --bench 10000 --seed 1 --stress-enemies 500 --stress-bullets 5000
+//  game-rs --soak 180 --bench-render
+//
+//Results are printed to stdout as JSON lines, so they can be appended to a
+//file and tracked over time; the profiler's scopes are the phases timed.
+
+use crate::asset_manager::TextureManager;
+use crate::config::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::error::Error;
+use crate::events::GameEvent;
+use crate::input::PlayerInput;
+use crate::json::{num, object, string, Json};
+use crate::particles::{ParticleSystem, PARTICLES_PATH};
+use crate::profiler::{self, Frame};
+use crate::render::RenderQueue;
+use crate::rollback::TICK_MS;
+use crate::vfs::Vfs;
+use crate::world::World;
+use crate::{draw_world, WorldSheets};
+
+use sdl2::image::InitFlag;
+use sdl2::render::WindowCanvas;
+use sdl2::video::WindowContext;
+
+use std::fs;
+use std::time::{Duration, Instant};
+
+// what --bench plays when not given --seed
+pub const SEED: u64 = 1;
+const DEFAULT_TICKS: u32 = 10_000;
+// ticks between soak samples, a little under three minutes of game time
+const SOAK_SAMPLE_TICKS: u32 = 10_000;
+// samples needed before the soak will say whether anything is growing
+const SOAK_MIN_SAMPLES: usize = 5;
+// A value is growing when the second half of a soak took it this far past
+// anything seen in the first half, plus the slack so that small counts
+// moving around don't count
+const GROWTH: f64 = 1.25;
+const GROWTH_SLACK: u64 = 64;
+
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    pub ticks: u32,
+    // run for this long instead, watching for growth
+    pub soak_minutes: Option<u32>,
+    // kept topped up to at least this many every tick
+    pub enemies: u32,
+    pub bullets: u32,
+    // also draw every tick into a hidden window
+    pub render: bool,
+}
+
+impl BenchOptions {
+    pub fn new() -> Self {
+        BenchOptions {
+            ticks: DEFAULT_TICKS,
+            soak_minutes: None,
+            enemies: 0,
+            bullets: 0,
+            render: false,
+        }
+    }
+}
+
+// Per-tick microseconds for each profiler scope, in the order they first ran
+struct Timings {
+    phases: Vec<(&'static str, Vec<u32>)>,
+}
+
+impl Timings {
+    fn record(&mut self, frame: &Frame) {
+        let spans = frame.spans.iter().map(|s| (s.name, s.duration));
+        for (name, duration) in std::iter::once(("tick", frame.duration)).chain(spans) {
+            match self.phases.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, samples)) => samples.push(duration),
+                None => self.phases.push((name, vec![duration])),
+            }
+        }
+    }
+
+    // keeps the names and the memory, so a soak's timings don't grow
+    fn clear(&mut self) {
+        for (_, samples) in &mut self.phases {
+            samples.clear();
+        }
+    }
+
+    fn json(&self) -> Json {
+        let ms = |us: f64| num(format!("{:.4}", us / 1000.0));
+        let phases = self.phases.iter().filter(|(_, samples)| !samples.is_empty()).map(|(name, samples)| {
+            let mut sorted = samples.clone();
+            sorted.sort_unstable();
+            let at = |fraction: f64| sorted[((sorted.len() - 1) as f64 * fraction) as usize] as f64;
+            let mean = sorted.iter().map(|&us| us as f64).sum::<f64>() / sorted.len() as f64;
+            let stats = object(vec![
+                ("mean_ms", ms(mean)),
+                ("p50_ms", ms(at(0.5))),
+                ("p99_ms", ms(at(0.99))),
+                ("max_ms", ms(at(1.0))),
+            ]);
+            (*name, stats)
+        });
+        object(phases.collect())
+    }
+}
+
+// Draws the world the way the game does, into a window nobody sees
+struct Renderer<'a> {
+    canvas: WindowCanvas,
+    textures: TextureManager<'a, WindowContext>,
+    sheets: WorldSheets<'a>,
+    particles: ParticleSystem,
+}
+
+impl<'a> Renderer<'a> {
+    fn draw(&mut self, world: &World, events: &[GameEvent]) -> Result<(), Error> {
+        for event in events {
+            match event {
+                GameEvent::EnemyKilled { pos, .. } | GameEvent::PlayerKilled { pos, .. } => self.particles.burst("explosion", *pos),
+                GameEvent::PowerupCollected { pos, .. } | GameEvent::PlayerRevived { pos, .. } => self.particles.burst("pickup_sparkle", *pos),
+                _ => {}
+            }
+        }
+        {
+            profile!("particles");
+            self.particles.update(TICK_MS as f32 / 1000.0);
+        }
+        profile!("render");
+        let mut queue = RenderQueue::new();
+        draw_world(&mut queue, &self.sheets, world, &self.particles);
+        self.canvas.clear();
+        queue.flush(&mut self.canvas)
+    }
+}
+
+struct Bench<'r, 'a> {
+    world: World,
+    options: BenchOptions,
+    renderer: Option<&'r mut Renderer<'a>>,
+    inputs: Vec<PlayerInput>,
+    events: Vec<GameEvent>,
+    timings: Timings,
+    ticks: u64,
+}
+
+impl<'r, 'a> Bench<'r, 'a> {
+    fn new(mut world: World, options: &BenchOptions, renderer: Option<&'r mut Renderer<'a>>) -> Self {
+        // idle players would die and end the game in seconds
+        world.god = true;
+        Bench {
+            inputs: vec![PlayerInput::new(); world.players.len()],
+            world,
+            options: options.clone(),
+            renderer,
+            events: Vec::new(),
+            timings: Timings { phases: Vec::new() },
+            ticks: 0,
+        }
+    }
+
+    fn run(&mut self, seed: u64) -> Result<(), Error> {
+        match self.options.soak_minutes {
+            Some(minutes) => self.soak(seed, minutes),
+            None => self.bench(seed),
+        }
+    }
+
+    fn tick(&mut self) -> Result<(), Error> {
+        {
+            profile!("stress");
+            let (enemies, bullets) = (self.world.enemies.len() as u32, self.world.bullets.len() as u32);
+            self.world.spawn_enemies(self.options.enemies.saturating_sub(enemies));
+            self.world.spawn_bullets(self.options.bullets.saturating_sub(bullets));
+        }
+        self.world.update(&self.inputs, TICK_MS, &mut self.events);
+        if let Some(r) = &mut self.renderer {
+            r.draw(&self.world, &self.events)?;
+        }
+        self.events.clear();
+        self.ticks += 1;
+        profiler::begin_frame();
+        if let Some(frame) = profiler::last_frame() {
+            self.timings.record(&frame);
+        }
+        Ok(())
+    }
+
+    // What's worth watching for growth, as it stands now
+    fn sample(&self) -> Vec<(&'static str, Option<u64>)> {
+        let world = &self.world;
+        let renderer = self.renderer.as_deref();
+        let textures = renderer.map(|r| r.textures.stats());
+        vec![
+            ("enemies", Some(world.enemies.len() as u64)),
+            ("bullets", Some(world.bullets.len() as u64)),
+            ("powerups", Some(world.powerups.len() as u64)),
+            ("enemies_capacity", Some(world.enemies.capacity() as u64)),
+            ("bullets_capacity", Some(world.bullets.capacity() as u64)),
+            ("powerups_capacity", Some(world.powerups.capacity() as u64)),
+            ("particles", renderer.map(|r| r.particles.len() as u64)),
+            ("textures", textures.map(|t| t.entries as u64)),
+            ("texture_bytes", textures.map(|t| t.bytes as u64)),
+            ("resident_kb", resident_kb()),
+        ]
+    }
+
+    fn header(&self, mode: &str, seed: u64) -> Vec<(&'static str, Json)> {
+        vec![
+            ("mode", string(mode)),
+            ("seed", num(seed)),
+            ("stress_enemies", num(self.options.enemies)),
+            ("stress_bullets", num(self.options.bullets)),
+            ("render", Json::Bool(self.renderer.is_some())),
+        ]
+    }
+
+    fn bench(&mut self, seed: u64) -> Result<(), Error> {
+        let start = Instant::now();
+        for _ in 0..self.options.ticks {
+            self.tick()?;
+        }
+        let seconds = start.elapsed().as_secs_f64();
+        info!("{} ticks in {:.3} s, {:.0} ticks a second", self.ticks, seconds, self.ticks as f64 / seconds.max(f64::EPSILON));
+
+        let mut result = self.header("bench", seed);
+        result.push(("ticks", num(self.ticks)));
+        result.push(("seconds", num(format!("{:.3}", seconds))));
+        result.push(("phases", self.timings.json()));
+        result.extend(self.sample().into_iter().map(|(name, value)| (name, value.map_or(Json::Null, num))));
+        result.push(("checksum", num(self.world.checksum())));
+        println!("{}", object(result));
+        Ok(())
+    }
+
+    fn soak(&mut self, seed: u64, minutes: u32) -> Result<(), Error> {
+        let start = Instant::now();
+        let end = start + Duration::from_secs(minutes as u64 * 60);
+        let mut samples: Vec<Vec<(&'static str, Option<u64>)>> = Vec::new();
+        while Instant::now() < end {
+            for _ in 0..SOAK_SAMPLE_TICKS {
+                self.tick()?;
+            }
+            let sample = self.sample();
+            let mut line = vec![
+                ("mode", string("soak_sample")),
+                ("minutes", num(format!("{:.2}", start.elapsed().as_secs_f64() / 60.0))),
+                ("ticks", num(self.ticks)),
+                ("phases", self.timings.json()),
+            ];
+            line.extend(sample.iter().map(|&(name, value)| (name, value.map_or(Json::Null, num))));
+            println!("{}", object(line));
+            self.timings.clear();
+            samples.push(sample);
+        }
+
+        // the first sample is taken while everything is still filling up
+        let settled = samples.get(1..).unwrap_or_default();
+        let inconclusive = settled.len() < SOAK_MIN_SAMPLES;
+        let growing: Vec<&str> = match settled.first() {
+            Some(first) if !inconclusive => first
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| {
+                    let values: Vec<u64> = settled.iter().filter_map(|s| s[*i].1).collect();
+                    growing(&values)
+                })
+                .map(|(_, (name, _))| *name)
+                .collect(),
+            _ => Vec::new(),
+        };
+        let mut result = self.header("soak", seed);
+        result.push(("minutes", num(minutes)));
+        result.push(("ticks", num(self.ticks)));
+        result.push(("samples", num(samples.len())));
+        result.push(("inconclusive", Json::Bool(inconclusive)));
+        result.push(("growing", Json::Array(growing.iter().map(|name| string(name)).collect())));
+        result.push(("checksum", num(self.world.checksum())));
+        println!("{}", object(result));
+        if inconclusive {
+            Err(Error::Other(format!(
+                "only {} samples after the first, too few to tell whether anything grows; soak for longer",
+                settled.len()
+            )))
+        }
+        else if growing.is_empty() {
+            Ok(())
+        }
+        else {
+            Err(Error::Other(format!("kept growing during the soak: {}", growing.join(", "))))
+        }
+    }
+}
+
+fn growing(values: &[u64]) -> bool {
+    let (first, second) = values.split_at(values.len() / 2);
+    let before = first.iter().copied().max().unwrap_or(0);
+    let after = second.iter().copied().max().unwrap_or(0);
+    after as f64 > before as f64 * GROWTH + GROWTH_SLACK as f64
+}
+
+// Resident memory from /proc, where there is one. VmRSS is already in kB,
+// whatever the page size.
+fn resident_kb() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+// `world` is set up as the game would set it up from `seed`; nobody plays it
+pub fn run(world: World, seed: u64, options: &BenchOptions, vfs: &Vfs) -> Result<(), Error> {
+    profiler::set_enabled(true);
+    profiler::begin_frame();
+    if !options.render {
+        return Bench::new(world, options, None).run(seed);
+    }
+
+    // no display needed, the same as --headless
+    sdl2::hint::set("SDL_VIDEODRIVER", "dummy");
+    let sdl_context = sdl2::init().map_err(|e| Error::init("SDL", e))?;
+    let video_subsystem = sdl_context.video().map_err(|e| Error::init("video", e))?;
+    let _image_context = sdl2::image::init(InitFlag::PNG).map_err(|e| Error::init("SDL_image", e))?;
+    let window = video_subsystem
+        .window("bench", SCREEN_WIDTH, SCREEN_HEIGHT)
+        .hidden()
+        .build()
+        .map_err(|e| Error::init("the window", e))?;
+    let canvas = window.into_canvas().software().build().map_err(|e| Error::init("the renderer", e))?;
+    let texture_creator = canvas.texture_creator();
+    let mut textures = TextureManager::new(&texture_creator, vfs);
+    let sheets = WorldSheets::load(&mut textures, vfs)?;
+    let particles = ParticleSystem::load(vfs, PARTICLES_PATH).map_err(|e| e.context("loading particle presets"))?;
+    let mut renderer = Renderer { canvas, textures, sheets, particles };
+    Bench::new(world, options, Some(&mut renderer)).run(seed)
+}
@@ -0,0 +1,54 @@
+//pack - builds assets.pak from the assets directory, for shipping the game
+//as the executable plus one file
+//
+//  cargo run --bin pack -- [--store] [ROOT] [OUTPUT]
+//
+//ROOT is the directory holding assets/ (default .), OUTPUT defaults to
+//ROOT/assets.pak and --store leaves everything uncompressed.
+
+// the game's half of these modules, reading, mostly goes unused here
+#[allow(dead_code)]
+#[path = "../error.rs"]
+mod error;
+#[allow(dead_code)]
+#[path = "../pack.rs"]
+mod pack;
+
+use error::Error;
+
+use std::env;
+use std::path::PathBuf;
+
+const ASSETS_DIR: &str = "assets";
+
+fn main() -> Result<(), Error> {
+    let mut compress = true;
+    let mut paths = Vec::new();
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--store" => compress = false,
+            "-h" | "--help" => {
+                println!("usage: pack [--store] [ROOT] [OUTPUT]");
+                return Ok(());
+            }
+            _ if arg.starts_with('-') => return Err(Error::Other(format!("unknown option {}", arg))),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    if paths.len() > 2 {
+        return Err(Error::Other("usage: pack [--store] [ROOT] [OUTPUT]".to_string()));
+    }
+    let root = paths.first().cloned().unwrap_or_else(|| PathBuf::from("."));
+    let out = paths.get(1).cloned().unwrap_or_else(|| root.join(pack::PACK_PATH));
+
+    let count = pack::write(&root, ASSETS_DIR, &out, compress)?;
+    // read it back so a bad archive is caught here rather than by players
+    let archive = pack::Archive::open(&out)?;
+    let mut size = 0;
+    for name in archive.names() {
+        size += archive.read(name)?.len() as u64;
+    }
+    let packed = std::fs::metadata(&out).map_err(|e| Error::io(out.display(), e))?.len();
+    println!("packed {} files into {}: {} -> {} bytes", count, out.display(), size, packed);
+    Ok(())
+}
@@ -0,0 +1,294 @@
+//capture module - screenshots and frame-by-frame gameplay capture
+
+use crate::display::letterbox;
+use crate::error::Error;
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sdl2::image::SaveSurface;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::WindowCanvas;
+use sdl2::surface::Surface;
+
+pub const SCREENSHOT_DIR: &str = "screenshots";
+
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+// Grabs the game area of the back buffer, without the letterbox bars, as
+// RGBA bytes. Has to be called after drawing and before present().
+pub fn grab_frame(canvas: &WindowCanvas) -> Result<Frame, Error> {
+    let area = letterbox(canvas)?;
+    let pixels = canvas.read_pixels(area, PixelFormatEnum::RGBA32).map_err(Error::render)?;
+    Ok(Frame {
+        width: area.width(),
+        height: area.height(),
+        pixels,
+    })
+}
+
+pub fn save_png(frame: &mut Frame, path: &str) -> Result<(), Error> {
+    let pitch = frame.width * 4;
+    let surface = Surface::from_data(&mut frame.pixels, frame.width, frame.height, pitch, PixelFormatEnum::RGBA32).map_err(Error::render)?;
+    surface.save(path).map_err(|e| Error::io(path, io::Error::other(e)))
+}
+
+pub fn screenshot(canvas: &WindowCanvas) -> Result<String, Error> {
+    fs::create_dir_all(SCREENSHOT_DIR).map_err(|e| Error::io(SCREENSHOT_DIR, e))?;
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let path = format!("{}/screenshot-{}.png", SCREENSHOT_DIR, stamp);
+    let mut frame = grab_frame(canvas)?;
+    save_png(&mut frame, &path)?;
+    Ok(path)
+}
+
+pub struct CaptureOptions {
+    // a path ending in .png is written as one animated PNG, anything else
+    // is treated as a directory for a numbered image sequence
+    pub path: String,
+    pub fps: u32,
+    pub frames: Option<u32>,
+}
+
+enum CaptureOutput {
+    Sequence(String),
+    Apng(ApngWriter),
+}
+
+// While capturing the game steps at a fixed 1/fps so the output plays back
+// at the right speed no matter how long each frame took to render and save.
+pub struct Capture {
+    output: CaptureOutput,
+    fps: u32,
+    max_frames: Option<u32>,
+    frame: u32,
+}
+
+impl Capture {
+    pub fn start(options: CaptureOptions) -> Result<Self, Error> {
+        let output = if options.path.ends_with(".png") {
+            CaptureOutput::Apng(ApngWriter::create(&options.path, options.fps)?)
+        } else {
+            fs::create_dir_all(&options.path).map_err(|e| Error::io(&options.path, e))?;
+            CaptureOutput::Sequence(options.path)
+        };
+        Ok(Capture {
+            output,
+            fps: options.fps.max(1),
+            max_frames: options.frames,
+            frame: 0,
+        })
+    }
+
+    // Milliseconds of game time until the next frame is recorded. 1000/fps
+    // rarely divides evenly, so each frame takes the whole milliseconds that
+    // fall in its share of the second and the remainders never build up.
+    pub fn frame_ms(&self) -> u32 {
+        let at = |frame: u32| frame as u64 * 1000 / self.fps as u64;
+        (at(self.frame + 1) - at(self.frame)) as u32
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.max_frames.is_some_and(|max| self.frame >= max)
+    }
+
+    pub fn record(&mut self, canvas: &WindowCanvas) -> Result<(), Error> {
+        let mut frame = grab_frame(canvas)?;
+        match &mut self.output {
+            CaptureOutput::Sequence(dir) => {
+                save_png(&mut frame, &format!("{}/frame_{:05}.png", dir, self.frame))?;
+            }
+            CaptureOutput::Apng(apng) => apng.add_frame(&frame)?,
+        }
+        self.frame += 1;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<u32, Error> {
+        if let CaptureOutput::Apng(apng) = self.output {
+            apng.finish()?;
+        }
+        Ok(self.frame)
+    }
+}
+
+// Minimal animated PNG encoder. Image data goes into stored (uncompressed)
+// deflate blocks, so files are big but need nothing beyond std to write.
+pub struct ApngWriter {
+    out: BufWriter<File>,
+    path: String,
+    fps: u32,
+    size: Option<(u32, u32)>,
+    frames: u32,
+    sequence: u32,
+    actl_offset: u64,
+}
+
+impl ApngWriter {
+    pub fn create(path: &str, fps: u32) -> Result<Self, Error> {
+        let file = File::create(path).map_err(|e| Error::io(path, e))?;
+        Ok(ApngWriter {
+            out: BufWriter::new(file),
+            path: path.to_string(),
+            fps: fps.max(1),
+            size: None,
+            frames: 0,
+            sequence: 0,
+            actl_offset: 0,
+        })
+    }
+
+    pub fn add_frame(&mut self, frame: &Frame) -> Result<(), Error> {
+        let path = self.path.clone();
+        let io = |e: io::Error| Error::io(&path, e);
+        match self.size {
+            None => {
+                self.size = Some((frame.width, frame.height));
+                self.out.write_all(b"\x89PNG\r\n\x1a\n").map_err(io)?;
+                let mut ihdr = Vec::new();
+                ihdr.extend_from_slice(&frame.width.to_be_bytes());
+                ihdr.extend_from_slice(&frame.height.to_be_bytes());
+                // 8 bit RGBA, default compression/filter, no interlace
+                ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+                self.chunk(b"IHDR", &ihdr)?;
+                // frame count is patched in by finish()
+                self.out.flush().map_err(io)?;
+                self.actl_offset = self.out.stream_position().map_err(io)?;
+                self.chunk(b"acTL", &[0, 0, 0, 0, 0, 0, 0, 0])?;
+            }
+            Some(size) if size != (frame.width, frame.height) => {
+                return Err(Error::Other(format!("{}: frame size changed during capture", self.path)));
+            }
+            _ => {}
+        }
+
+        let mut fctl = Vec::new();
+        fctl.extend_from_slice(&self.sequence.to_be_bytes());
+        fctl.extend_from_slice(&frame.width.to_be_bytes());
+        fctl.extend_from_slice(&frame.height.to_be_bytes());
+        fctl.extend_from_slice(&[0; 8]);
+        fctl.extend_from_slice(&1u16.to_be_bytes());
+        fctl.extend_from_slice(&(self.fps as u16).to_be_bytes());
+        fctl.extend_from_slice(&[0, 0]);
+        self.sequence += 1;
+        self.chunk(b"fcTL", &fctl)?;
+
+        let data = zlib_stored(&scanlines(frame));
+        if self.frames == 0 {
+            self.chunk(b"IDAT", &data)?;
+        } else {
+            let mut fdat = self.sequence.to_be_bytes().to_vec();
+            fdat.extend_from_slice(&data);
+            self.sequence += 1;
+            self.chunk(b"fdAT", &fdat)?;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), Error> {
+        let path = self.path.clone();
+        let io = |e: io::Error| Error::io(&path, e);
+        if self.frames == 0 {
+            return Err(Error::Other(format!("{}: no frames captured", self.path)));
+        }
+        self.chunk(b"IEND", &[])?;
+        let mut actl = self.frames.to_be_bytes().to_vec();
+        actl.extend_from_slice(&0u32.to_be_bytes());
+        self.out.seek(SeekFrom::Start(self.actl_offset)).map_err(io)?;
+        self.chunk(b"acTL", &actl)?;
+        self.out.flush().map_err(io)
+    }
+
+    fn chunk(&mut self, kind: &[u8; 4], data: &[u8]) -> Result<(), Error> {
+        let mut crc = Crc32::new();
+        crc.update(kind);
+        crc.update(data);
+        let out = &mut self.out;
+        out.write_all(&(data.len() as u32).to_be_bytes())
+            .and_then(|_| out.write_all(kind))
+            .and_then(|_| out.write_all(data))
+            .and_then(|_| out.write_all(&crc.finish().to_be_bytes()))
+            .map_err(|e| Error::io(&self.path, e))
+    }
+}
+
+// each row of pixels prefixed with filter type 0 (none)
+fn scanlines(frame: &Frame) -> Vec<u8> {
+    let row = frame.width as usize * 4;
+    let mut out = Vec::with_capacity((row + 1) * frame.height as usize);
+    for line in frame.pixels.chunks(row).take(frame.height as usize) {
+        out.push(0);
+        out.extend_from_slice(line);
+    }
+    out
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(if blocks.peek().is_none() { 1 } else { 0 });
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    out.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    out
+}
+
+// lookup table for the CRC-32 every PNG chunk ends with, built at compile time
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            bit += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+struct Crc32 {
+    crc: u32,
+}
+
+impl Crc32 {
+    fn new() -> Self {
+        Crc32 { crc: 0xffffffff }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.crc = CRC_TABLE[((self.crc ^ *byte as u32) & 0xff) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    fn finish(&self) -> u32 {
+        self.crc ^ 0xffffffff
+    }
+}
@@ -0,0 +1,301 @@
+//cli module - the game's command line, checked up front so a bad option
+//fails before any window opens
+
+use crate::bench::BenchOptions;
+use crate::capture::CaptureOptions;
+use crate::config::Difficulty;
+use crate::net::{LinkConditions, NetRole};
+use crate::rollback::MAX_DELAY;
+use crate::settings::{WindowMode, SETTINGS_PATH};
+use crate::telemetry;
+
+use std::path::PathBuf;
+
+// past this a frame of game time is under a millisecond
+const MAX_CAPTURE_FPS: u32 = 1000;
+
+pub const USAGE: &str = "\
+usage: game-rs [OPTIONS]
+
+display:
+  --window windowed|borderless    window mode for this run
+  --resolution WxH                window size for this run, e.g. 1280x720
+  --headless                      no window or audio; needs --capture and either
+                                  --capture-frames or --replay
+
+game:
+  --seed N                        start every game from this seed
+  --scene NAME                    load assets/scenes/NAME.ini up front and skip the title
+  --difficulty easy|normal|hard   scale enemy speed, spawn rate and aggro radius
+  --mute                          no sound effects this run
+  --config PATH                   settings file to use (default settings.ini)
+  --assets DIR                    directory holding assets/ or assets.pak
+  --strict-assets                 fail on missing assets instead of using stand-ins
+  --validate-assets               check every asset and exit
+
+replays and capture:
+  --record PATH                   record this run's games as a replay
+  --replay PATH                   play back a replay
+  --capture PATH                  record frames to PATH.png or a directory
+  --capture-fps N                 frames per second of game time to capture, 1 to 1000 (default 30)
+  --capture-frames N              stop after N frames
+
+online:
+  --host PORT | --join ADDRESS    play online
+  --input-delay TICKS             online input delay, 0 to 8 (default 2)
+  --net-latency MS --net-jitter MS --net-loss PERCENT
+                                  simulate a bad connection, losing 0 to 100% of packets
+  --stream PORT                   serve the game to spectators on localhost
+  --spectate ADDRESS              watch a --stream
+
+tools:
+  --bench TICKS                   run the simulation alone for TICKS ticks, print timings as JSON and exit
+  --soak MINUTES                  run it for MINUTES instead, failing if anything keeps growing
+  --stress-enemies N              keep at least N enemies alive while benchmarking
+  --stress-bullets N              keep at least N bullets in flight while benchmarking
+  --bench-render                  draw every tick into a hidden window as well
+  --log FILTER                    log level, or level,module=level,...
+  --log-file PATH                 also write the log to PATH
+  --telemetry jsonl|csv           record a session's events for analysis
+  --profile TRACE.json            save a profile of the last few seconds on exit
+  -h, --help                      show this and exit
+";
+
+pub struct Args {
+    pub help: bool,
+    pub window_mode: Option<WindowMode>,
+    pub resolution: Option<(u32, u32)>,
+    pub seed: Option<u64>,
+    pub scene: Option<String>,
+    pub difficulty: Difficulty,
+    pub mute: bool,
+    pub config: String,
+    pub assets: Option<PathBuf>,
+    pub record: Option<String>,
+    pub replay: Option<String>,
+    pub bench: Option<BenchOptions>,
+    pub capture: Option<CaptureOptions>,
+    pub headless: bool,
+    pub net_role: Option<NetRole>,
+    pub net_delay: u32,
+    pub link: LinkConditions,
+    pub stream_port: Option<u16>,
+    pub spectate: Option<String>,
+    pub log: Option<String>,
+    pub log_file: Option<String>,
+    pub telemetry: Option<telemetry::Format>,
+    pub profile: Option<String>,
+    pub strict_assets: bool,
+    pub validate_assets: bool,
+}
+
+impl Args {
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Args, String> {
+        let mut parsed = Args {
+            help: false,
+            window_mode: None,
+            resolution: None,
+            seed: None,
+            scene: None,
+            difficulty: Difficulty::Normal,
+            mute: false,
+            config: SETTINGS_PATH.to_string(),
+            assets: None,
+            record: None,
+            replay: None,
+            bench: None,
+            capture: None,
+            headless: false,
+            net_role: None,
+            net_delay: 2,
+            link: LinkConditions::new(),
+            stream_port: None,
+            spectate: None,
+            log: None,
+            log_file: None,
+            telemetry: None,
+            profile: None,
+            strict_assets: false,
+            validate_assets: false,
+        };
+        // options that refine another are kept until the end, so they can
+        // come before the one they belong to
+        let (mut stress_enemies, mut stress_bullets, mut bench_render) = (None, None, false);
+        let (mut capture_fps, mut capture_frames) = (None, None);
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "-h" | "--help" => parsed.help = true,
+                "--window" => {
+                    parsed.window_mode = match value()?.as_str() {
+                        "windowed" => Some(WindowMode::Windowed),
+                        "borderless" | "fullscreen" => Some(WindowMode::Borderless),
+                        other => return Err(format!("unknown window mode '{}'", other)),
+                    }
+                }
+                "--resolution" => {
+                    let text = value()?;
+                    let size = text.split_once('x').and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)));
+                    match size {
+                        Some((w, h)) if w > 0 && h > 0 => parsed.resolution = Some((w, h)),
+                        _ => return Err(format!("--resolution needs WIDTHxHEIGHT, not '{}'", text)),
+                    }
+                }
+                "--seed" => parsed.seed = Some(value()?.parse().map_err(|_| format!("{} needs a number", arg))?),
+                "--scene" => parsed.scene = Some(value()?),
+                "--difficulty" => {
+                    let name = value()?;
+                    parsed.difficulty = Difficulty::from_name(&name).ok_or(format!("unknown difficulty '{}'", name))?;
+                }
+                "--mute" => parsed.mute = true,
+                "--config" => parsed.config = value()?,
+                "--assets" => parsed.assets = Some(PathBuf::from(value()?)),
+                "--record" => parsed.record = Some(value()?),
+                "--replay" => parsed.replay = Some(value()?),
+                "--bench" | "--soak" => {
+                    let n: u32 = value()?.parse().map_err(|_| format!("{} needs a number", arg))?;
+                    let options = parsed.bench.get_or_insert_with(BenchOptions::new);
+                    if arg == "--bench" { options.ticks = n; } else { options.soak_minutes = Some(n); }
+                }
+                "--stress-enemies" | "--stress-bullets" => {
+                    let n: u32 = value()?.parse().map_err(|_| format!("{} needs a number", arg))?;
+                    if arg == "--stress-enemies" { stress_enemies = Some(n); } else { stress_bullets = Some(n); }
+                }
+                "--bench-render" => bench_render = true,
+                "--capture" => parsed.capture = Some(CaptureOptions { path: value()?, fps: 30, frames: None }),
+                "--capture-fps" | "--capture-frames" => {
+                    let n: u32 = value()?.parse().map_err(|_| format!("{} needs a number", arg))?;
+                    if arg == "--capture-fps" {
+                        if !(1..=MAX_CAPTURE_FPS).contains(&n) {
+                            return Err(format!("--capture-fps needs 1 to {}, not {}", MAX_CAPTURE_FPS, n));
+                        }
+                        capture_fps = Some(n);
+                    } else {
+                        capture_frames = Some(n);
+                    }
+                }
+                "--headless" => parsed.headless = true,
+                "--host" => {
+                    let port = value()?.parse().map_err(|_| format!("{} needs a port number", arg))?;
+                    parsed.net_role = Some(NetRole::Host(port));
+                }
+                "--join" => parsed.net_role = Some(NetRole::Join(value()?)),
+                "--stream" => {
+                    let port = value()?.parse().map_err(|_| format!("{} needs a port number", arg))?;
+                    parsed.stream_port = Some(port);
+                }
+                "--spectate" => parsed.spectate = Some(value()?),
+                "--log" => parsed.log = Some(value()?),
+                "--log-file" => parsed.log_file = Some(value()?),
+                "--profile" => parsed.profile = Some(value()?),
+                "--strict-assets" => parsed.strict_assets = true,
+                "--validate-assets" => parsed.validate_assets = true,
+                "--telemetry" => {
+                    let name = value()?;
+                    let format = telemetry::Format::from_name(&name).ok_or(format!("unknown telemetry format '{}'", name))?;
+                    parsed.telemetry = Some(format);
+                }
+                "--input-delay" | "--net-latency" | "--net-jitter" | "--net-loss" => {
+                    let n: u32 = value()?.parse().map_err(|_| format!("{} needs a number", arg))?;
+                    match arg.as_str() {
+                        "--input-delay" if n > MAX_DELAY => return Err(format!("--input-delay is at most {} ticks", MAX_DELAY)),
+                        "--input-delay" => parsed.net_delay = n,
+                        "--net-latency" => parsed.link.latency_ms = n,
+                        "--net-jitter" => parsed.link.jitter_ms = n,
+                        _ if n > 100 => return Err(format!("--net-loss is a percentage, not {}", n)),
+                        _ => parsed.link.loss = n as f32 / 100.0,
+                    }
+                }
+                _ => return Err(format!("unknown argument '{}', see --help", arg)),
+            }
+        }
+        if parsed.help {
+            return Ok(parsed);
+        }
+        if stress_enemies.is_some() || stress_bullets.is_some() || bench_render {
+            let options = parsed.bench.as_mut().ok_or("--stress-enemies, --stress-bullets and --bench-render need --bench or --soak")?;
+            options.enemies = stress_enemies.unwrap_or(options.enemies);
+            options.bullets = stress_bullets.unwrap_or(options.bullets);
+            options.render = bench_render;
+        }
+        if capture_fps.is_some() || capture_frames.is_some() {
+            let options = parsed.capture.as_mut().ok_or("--capture-fps and --capture-frames need --capture")?;
+            options.fps = capture_fps.unwrap_or(options.fps);
+            options.frames = capture_frames;
+        }
+        parsed.check()?;
+        Ok(parsed)
+    }
+
+    // Options that can't be used together
+    fn check(&self) -> Result<(), String> {
+        let online = self.net_role.is_some();
+        if self.spectate.is_some() && (online || self.stream_port.is_some()) {
+            return Err("--spectate can't be combined with --host, --join or --stream".to_string());
+        }
+        if self.replay.is_some() && (online || self.spectate.is_some() || self.record.is_some()) {
+            return Err("--replay can't be combined with --host, --join, --spectate or --record".to_string());
+        }
+        if self.record.is_some() && self.spectate.is_some() {
+            return Err("--record can't be combined with --spectate".to_string());
+        }
+        if self.bench.is_some() && (online || self.spectate.is_some() || self.replay.is_some() || self.capture.is_some()) {
+            return Err("--bench and --soak run on their own, without --host, --join, --spectate, --replay or --capture".to_string());
+        }
+        if self.scene.is_some() && (online || self.spectate.is_some() || self.replay.is_some()) {
+            return Err("--scene is for local games".to_string());
+        }
+        if let Some(dir) = self.assets.as_ref().filter(|dir| !dir.is_dir()) {
+            return Err(format!("--assets: {} is not a directory", dir.display()));
+        }
+        // a capture of a replay ends with the replay
+        let ends = |c: &CaptureOptions| c.frames.is_some() || self.replay.is_some();
+        if self.headless && !self.capture.as_ref().is_some_and(ends) {
+            return Err("--headless needs --capture and either --capture-frames or --replay".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<Args, String> {
+        Args::parse(line.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn refining_flags_can_come_before_their_parent() {
+        let args = parse("--stress-enemies 50 --bench-render --bench 100").unwrap();
+        let bench = args.bench.unwrap();
+        assert_eq!((bench.ticks, bench.enemies, bench.render), (100, 50, true));
+
+        let args = parse("--capture-fps 60 --capture-frames 10 --capture out.png").unwrap();
+        let capture = args.capture.unwrap();
+        assert_eq!((capture.path.as_str(), capture.fps, capture.frames), ("out.png", 60, Some(10)));
+
+        assert!(parse("--stress-bullets 10").is_err());
+        assert!(parse("--capture-frames 10").is_err());
+    }
+
+    #[test]
+    fn rejects_values_out_of_range() {
+        assert!(parse("--capture out --capture-fps 0").is_err());
+        assert!(parse("--capture out --capture-fps 1001").is_err());
+        assert!(parse("--capture out --capture-fps 1000").is_ok());
+        assert!(parse("--net-loss 101").is_err());
+        assert_eq!(parse("--net-loss 100").unwrap().link.loss, 1.0);
+        assert!(parse("--input-delay 9").is_err());
+    }
+
+    #[test]
+    fn rejects_options_that_dont_go_together() {
+        assert!(parse("--headless").is_err());
+        assert!(parse("--headless --capture out").is_err());
+        assert!(parse("--headless --capture out --capture-frames 10").is_ok());
+        assert!(parse("--headless --capture out --replay game.jsonl").is_ok());
+        assert!(parse("--replay a.jsonl --record b.jsonl").is_err());
+    }
+}
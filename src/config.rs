@@ -4,4 +4,131 @@ pub const SCREEN_WIDTH: u32 = 800;
 pub const SCREEN_HEIGHT: u32 = 600;
 pub const SPAWN_RATE: u32 = 2000;
 pub const POWERUP_RATE: u32 = 10000;
-pub const PLAYER_SPEED: f32 = 100.0;
\ No newline at end of file
+pub const PLAYER_SPEED: f32 = 100.0;
+pub const TRAUMA_DECAY: f32 = 1.5;
+pub const SHAKE_MAX_OFFSET: f32 = 12.0;
+pub const FLASH_TIME: f32 = 0.12;
+pub const HIT_STOP_TIME: f32 = 0.06;
+pub const VIGNETTE_TIME: f32 = 0.4;
+pub const STICK_DEADZONE: f32 = 0.2;
+pub const AIM_DEADZONE: f32 = 0.3;
+pub const AUTO_FIRE_THRESHOLD: f32 = 0.6;
+
+pub const MAX_PLAYERS: usize = 4;
+pub const REVIVE_RADIUS: f32 = 48.0;
+pub const REVIVE_TIME: u32 = 3000;
+pub const REVIVE_LIFE: i32 = 2;
+// a bomb spends a shield charge to clear enemies and their shots this close
+pub const BOMB_RADIUS: f32 = 150.0;
+
+// the sheets the game scene draws from, also listed in assets/scenes/game.ini
+pub const BACKGROUND_SHEET: &str = "assets/SpaceShooterAssetPack_BackGrounds.png";
+pub const SHIP_SHEET: &str = "assets/SpaceShooterAssetPack_Ships.png";
+pub const PROJECTILE_SHEET: &str = "assets/SpaceShooterAssetPack_Projectiles.png";
+pub const MISC_SHEET: &str = "assets/SpaceShooterAssetPack_Miscellaneous.png";
+pub const FONT_PATH: &str = "assets/fonts/Pono_188.ttf";
+pub const FONT_SIZE: u16 = 24;
+
+// textures nothing is using are let go past this many bytes
+pub const TEXTURE_CACHE_BYTES: usize = 256 * 1024 * 1024;
+
+// enemies this close to a player turn to face them and open fire
+pub const AGGRO_RADIUS: f32 = 200.0;
+// the most the console, mods or a stream may set the tuning to
+pub const MAX_TUNING_SPEED: f32 = 5000.0;
+pub const MAX_TUNING_RATE_MS: f32 = 3_600_000.0;
+pub const MAX_TUNING_RADIUS: f32 = 5000.0;
+
+// The constants above that can be changed while the game runs, from the
+// debug console. A World carries its own copy so replays stay exact.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tuning {
+    pub enemy_speed: f32,
+    pub bullet_speed: f32,
+    pub player_speed: f32,
+    pub spawn_rate: u32,
+    pub powerup_rate: u32,
+    pub aggro_radius: f32,
+}
+
+pub const TUNING_NAMES: [&str; 6] = ["enemy_speed", "bullet_speed", "player_speed", "spawn_rate", "powerup_rate", "aggro_radius"];
+
+impl Tuning {
+    pub fn new() -> Self {
+        Tuning {
+            enemy_speed: ENEMY_SPEED,
+            bullet_speed: BULLET_SPEED,
+            player_speed: PLAYER_SPEED,
+            spawn_rate: SPAWN_RATE,
+            powerup_rate: POWERUP_RATE,
+            aggro_radius: AGGRO_RADIUS,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<String> {
+        match name {
+            "enemy_speed" => Some(self.enemy_speed.to_string()),
+            "bullet_speed" => Some(self.bullet_speed.to_string()),
+            "player_speed" => Some(self.player_speed.to_string()),
+            "spawn_rate" => Some(self.spawn_rate.to_string()),
+            "powerup_rate" => Some(self.powerup_rate.to_string()),
+            "aggro_radius" => Some(self.aggro_radius.to_string()),
+            _ => None,
+        }
+    }
+
+    // Nothing here makes sense negative, and past the limits the game
+    // breaks, e.g. an infinite speed can't be rolled for
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let max = match name {
+            "enemy_speed" | "bullet_speed" | "player_speed" => MAX_TUNING_SPEED,
+            "spawn_rate" | "powerup_rate" => MAX_TUNING_RATE_MS,
+            "aggro_radius" => MAX_TUNING_RADIUS,
+            _ => return Err(format!("no setting called {}", name)),
+        };
+        let number: f32 = match value.parse() {
+            Ok(n) if (0.0..=max).contains(&n) => n,
+            _ => return Err(format!("{}: invalid value '{}', must be 0 to {}", name, value, max)),
+        };
+        match name {
+            "enemy_speed" => self.enemy_speed = number,
+            "bullet_speed" => self.bullet_speed = number,
+            "player_speed" => self.player_speed = number,
+            "spawn_rate" => self.spawn_rate = number as u32,
+            "powerup_rate" => self.powerup_rate = number as u32,
+            _ => self.aggro_radius = number,
+        }
+        Ok(())
+    }
+}
+
+// Scales the tuning a game starts with; mods' [config] is applied first
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    pub fn from_name(name: &str) -> Option<Difficulty> {
+        match name {
+            "easy" => Some(Difficulty::Easy),
+            "normal" => Some(Difficulty::Normal),
+            "hard" => Some(Difficulty::Hard),
+            _ => None,
+        }
+    }
+
+    pub fn apply(&self, tuning: &mut Tuning) {
+        // enemy speed, time between spawns, aggro radius
+        let (speed, spawn, aggro) = match self {
+            Difficulty::Easy => (0.75, 1.5, 0.75),
+            Difficulty::Normal => return,
+            Difficulty::Hard => (1.3, 0.6, 1.25),
+        };
+        tuning.enemy_speed = (tuning.enemy_speed * speed).min(MAX_TUNING_SPEED);
+        tuning.spawn_rate = (tuning.spawn_rate as f32 * spawn).min(MAX_TUNING_RATE_MS) as u32;
+        tuning.aggro_radius = (tuning.aggro_radius * aggro).min(MAX_TUNING_RADIUS);
+    }
+}
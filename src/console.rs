@@ -0,0 +1,204 @@
+//console module - drop-down command line for tuning and testing in game
+
+use crate::config::{SCREEN_WIDTH, TUNING_NAMES};
+use crate::display::Anchor;
+use crate::entity::EntityType;
+use crate::profiler::{self, TRACE_PATH};
+use crate::ui::{Ui, PADDING, SPACING};
+use crate::world::World;
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+
+const MAX_LINES: usize = 200;
+const SHOWN_LINES: usize = 8;
+const MAX_INPUT: usize = 80;
+
+const HELP: [&str; 8] = [
+    "spawn enemy [count]",
+    "give [powerup] health|shield|bullet_speed|nuke",
+    "god",
+    "set [config.]name [value]",
+    "timescale [scale]",
+    "profile on|off|save [path]",
+    "clear",
+    "help",
+];
+
+pub struct Console {
+    pub open: bool,
+    input: String,
+    lines: Vec<String>,
+    history: Vec<String>,
+    // where up/down is in the history, None while typing a new line
+    recall: Option<usize>,
+    entered: Vec<String>,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Console {
+            open: false,
+            input: String::new(),
+            lines: Vec::new(),
+            history: Vec::new(),
+            recall: None,
+            entered: Vec::new(),
+        }
+    }
+
+    pub fn print(&mut self, line: &str) {
+        self.lines.push(line.to_string());
+        if self.lines.len() > MAX_LINES {
+            self.lines.remove(0);
+        }
+    }
+
+    // Takes every event while open
+    pub fn handle_event(&mut self, event: &Event) {
+        match event {
+            Event::TextInput { text, .. } => {
+                // the key that opens the console types one of these too
+                for c in text.chars().filter(|c| *c != '`' && !c.is_control()) {
+                    if self.input.len() < MAX_INPUT {
+                        self.input.push(c);
+                    }
+                }
+            }
+            Event::KeyDown { keycode: Some(key), .. } => match key {
+                Keycode::Backspace => {
+                    self.input.pop();
+                }
+                Keycode::Return | Keycode::KpEnter => {
+                    let line = std::mem::take(&mut self.input);
+                    if !line.trim().is_empty() {
+                        self.history.push(line.clone());
+                        self.entered.push(line);
+                    }
+                    self.recall = None;
+                }
+                Keycode::Up if !self.history.is_empty() => {
+                    let i = self.recall.map_or(self.history.len() - 1, |i| i.saturating_sub(1));
+                    self.input = self.history[i].clone();
+                    self.recall = Some(i);
+                }
+                Keycode::Down => {
+                    self.recall = self.recall.map(|i| i + 1).filter(|i| *i < self.history.len());
+                    self.input = self.recall.map_or(String::new(), |i| self.history[i].clone());
+                }
+                Keycode::Escape => self.open = false,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    // Lines entered since the last call, to be passed to execute
+    pub fn take_entered(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.entered)
+    }
+
+    // `shared` is set when the world isn't ours alone to change: online,
+    // watching or serving a stream, or recording a replay
+    pub fn execute(&mut self, line: &str, world: &mut World, time_scale: &mut f32, shared: bool) {
+        self.print(&format!("> {}", line));
+        match self.run(line, world, time_scale, shared) {
+            Ok(reply) => {
+                for l in reply.lines() {
+                    self.print(l);
+                }
+            }
+            Err(e) => self.print(&format!("error: {}", e)),
+        }
+    }
+
+    fn run(&mut self, line: &str, world: &mut World, time_scale: &mut f32, shared: bool) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["help"] => return Ok(HELP.join("\n")),
+            ["clear"] => {
+                self.lines.clear();
+                return Ok(String::new());
+            }
+            ["profile", "on"] | ["profile", "off"] => {
+                profiler::set_enabled(words[1] == "on");
+                return Ok(format!("profiler {}", words[1]));
+            }
+            ["profile", "save"] | ["profile", "save", _] => {
+                let path = words.get(2).copied().unwrap_or(TRACE_PATH);
+                let frames = profiler::save_trace(path)?;
+                return Ok(format!("saved {} frames to {}", frames, path));
+            }
+            _ => {}
+        }
+        if shared {
+            return Err("not while the game is online, streamed or recorded".to_string());
+        }
+        match words[..] {
+            ["spawn", "enemy"] => {
+                world.spawn_enemies(1);
+                Ok("spawned 1 enemy".to_string())
+            }
+            ["spawn", "enemy", count] => {
+                let count: u32 = count.parse().map_err(|_| format!("not a count: {}", count))?;
+                world.spawn_enemies(count);
+                Ok(format!("spawned {} enemies", count))
+            }
+            ["give", name] | ["give", "powerup", name] => {
+                let typ = EntityType::powerup(name).ok_or_else(|| format!("no powerup called {}", name))?;
+                world.give(typ);
+                Ok(format!("gave {}", name))
+            }
+            ["god"] => {
+                world.god = !world.god;
+                Ok(format!("god mode {}", if world.god { "on" } else { "off" }))
+            }
+            ["set"] => Ok(TUNING_NAMES
+                .iter()
+                .map(|name| format!("config.{} = {}", name, world.tuning.get(name).unwrap_or_default()))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            ["set", name] => {
+                let name = name.trim_start_matches("config.");
+                let value = world.tuning.get(name).ok_or_else(|| format!("no setting called {}", name))?;
+                Ok(format!("config.{} = {}", name, value))
+            }
+            ["set", name, value] => {
+                let name = name.trim_start_matches("config.");
+                world.tuning.set(name, value)?;
+                Ok(format!("config.{} = {}", name, value))
+            }
+            ["timescale"] => Ok(format!("timescale {}", time_scale)),
+            ["timescale", scale] => {
+                *time_scale = match scale.parse::<f32>() {
+                    Ok(s) if s >= 0.0 => s,
+                    _ => return Err(format!("not a time scale: {}", scale)),
+                };
+                Ok(format!("timescale {}", time_scale))
+            }
+            _ => Err(format!("unknown command '{}', try help", line.trim())),
+        }
+    }
+
+    pub fn build(&self, ui: &mut Ui) {
+        if !self.open {
+            return;
+        }
+        let line_h = ui.text_size("> ").1;
+        let height = (line_h + SPACING as u32) * (SHOWN_LINES as u32 + 1) + PADDING as u32 * 2;
+        ui.begin_overlay();
+        ui.begin_area(Anchor::Top, 0, 0, SCREEN_WIDTH, height, true);
+        let dim = ui.theme.text_dim;
+        let start = self.lines.len().saturating_sub(SHOWN_LINES);
+        // output sits just above the prompt, like a terminal
+        for _ in self.lines.len() - start..SHOWN_LINES {
+            ui.space(line_h);
+        }
+        for line in &self.lines[start..] {
+            ui.label_colored(line, dim);
+        }
+        ui.label(&format!("> {}_", self.input));
+        ui.end();
+        ui.end_overlay();
+    }
+}
@@ -0,0 +1,93 @@
+//controls module - screen for rebinding actions
+
+use crate::display::Anchor;
+use crate::input::{binding_from_event, Binding, Bindings, Input, ACTIONS, BINDINGS_PATH};
+use crate::ui::{Align, Ui};
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+
+pub struct ControlsMenu {
+    pub open: bool,
+    selected: usize,
+    // the next key or button pressed gets bound to the selected action
+    pub waiting: bool,
+}
+
+impl ControlsMenu {
+    pub fn new() -> Self {
+        ControlsMenu {
+            open: false,
+            selected: 0,
+            waiting: false,
+        }
+    }
+
+    // While waiting for a binding every input event comes here instead of
+    // going to the UI
+    pub fn capture(&mut self, event: &Event, input: &mut Input) {
+        match binding_from_event(event) {
+            Some(Binding::Key(Keycode::Escape)) => self.waiting = false,
+            Some(binding) => {
+                input.bindings.rebind(ACTIONS[self.selected], binding);
+                self.changed(input);
+                self.waiting = false;
+            }
+            None => {}
+        }
+    }
+
+    // Navigated with the UI's fixed keys and buttons, so it can't be locked
+    // out by a bad binding
+    pub fn run(&mut self, ui: &mut Ui, input: &mut Input) {
+        let items: Vec<(String, String)> = ACTIONS
+            .iter()
+            .enumerate()
+            .map(|(i, action)| {
+                let bound = if i == self.selected && self.waiting {
+                    "PRESS A KEY OR BUTTON".to_string()
+                } else {
+                    let labels: Vec<String> = input.bindings.for_action(*action).iter().map(|b| b.label()).collect();
+                    if labels.is_empty() { "-".to_string() } else { labels.join(" ") }
+                };
+                (action.name().to_uppercase().replace('_', " "), bound)
+            })
+            .collect();
+
+        ui.begin_menu("controls");
+        ui.begin_area(Anchor::Top, 0, 10, 760, 530, true);
+        ui.align(Align::Center);
+        ui.label("CONTROLS");
+        if let Some(i) = ui.list(&items, &mut self.selected) {
+            self.selected = i;
+            self.waiting = true;
+        }
+        ui.begin_row();
+        let defaults = ui.button("RESET TO DEFAULTS");
+        let back = ui.button("BACK");
+        ui.end();
+        ui.end();
+
+        ui.begin_area(Anchor::Bottom, 0, 0, 600, 50, false);
+        ui.align(Align::Center);
+        let hint = ui.theme.text_dim;
+        ui.label_colored("ENTER REBIND  ESC BACK", hint);
+        ui.end();
+        ui.end_menu();
+
+        if defaults {
+            input.bindings = Bindings::defaults();
+            self.changed(input);
+        }
+        if back || ui.back() {
+            self.open = false;
+        }
+    }
+
+    fn changed(&self, input: &mut Input) {
+        input.release_all();
+        if let Err(e) = input.bindings.save(BINDINGS_PATH) {
+            warn!("could not save bindings: {}", e);
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//debug module - developer overlay with frame timings, entity counts and
+//the collision shapes the simulation is working with
+
+use crate::asset_manager::CacheStats;
+use crate::display::Anchor;
+use crate::entity::{Entity, EntityType};
+use crate::error::Error;
+use crate::profiler;
+use crate::ui::Ui;
+use crate::world::World;
+
+use std::collections::VecDeque;
+
+use sdl2::gfx::primitives::DrawRenderer;
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+use sdl2::render::{BlendMode, WindowCanvas};
+
+const HISTORY: usize = 120;
+// the frame time graph tops out at this many milliseconds
+const GRAPH_MAX_MS: f32 = 50.0;
+// velocity lines show where things will be this many seconds from now
+const VELOCITY_SECONDS: f32 = 0.25;
+const FLAME_WIDTH: u32 = 236;
+const FLAME_ROW: u32 = 8;
+const FLAME_ROWS: u32 = 4;
+
+// a steady colour per scope name so the bar doesn't flicker between frames
+fn span_color(name: &str) -> Color {
+    let hash = name.bytes().fold(2166136261u32, |h, b| (h ^ b as u32).wrapping_mul(16777619));
+    Color::RGB(120 + (hash % 136) as u8, 80 + (hash >> 8) as u8 % 120, 60 + (hash >> 16) as u8 % 80)
+}
+
+const ENTITY_TYPES: [EntityType; 8] = [
+    EntityType::Player,
+    EntityType::Enemy,
+    EntityType::Bullet,
+    EntityType::EnemyBullet,
+    EntityType::PowerupHealth,
+    EntityType::PowerupShield,
+    EntityType::PowerupBulletSpeed,
+    EntityType::PowerupNuke,
+];
+
+fn box_color(typ: &EntityType) -> Color {
+    match typ {
+        EntityType::Player => Color::RGB(80, 255, 80),
+        EntityType::Enemy => Color::RGB(255, 80, 80),
+        EntityType::Bullet => Color::RGB(255, 255, 80),
+        EntityType::EnemyBullet => Color::RGB(255, 160, 40),
+        _ => Color::RGB(80, 200, 255),
+    }
+}
+
+pub struct DebugOverlay {
+    pub open: bool,
+    frame_times: VecDeque<u32>,
+}
+
+impl DebugOverlay {
+    pub fn new() -> Self {
+        DebugOverlay {
+            open: false,
+            frame_times: VecDeque::with_capacity(HISTORY),
+        }
+    }
+
+    // Kept even while closed so the graph is full as soon as it opens
+    pub fn record_frame(&mut self, frame_ms: u32) {
+        if self.frame_times.len() == HISTORY {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_ms);
+    }
+
+    // Hitboxes, velocities and enemy aggro radii, in world coordinates, so
+    // call it while the shaken viewport is still set
+    pub fn draw_world(&self, canvas: &mut WindowCanvas, world: &World) -> Result<(), Error> {
+        if !self.open {
+            return Ok(());
+        }
+        canvas.set_blend_mode(BlendMode::Blend);
+        let ships = world.players.iter().map(|p| &p.ship);
+        for e in ships.chain(&world.enemies).chain(&world.bullets).chain(&world.powerups) {
+            draw_entity(canvas, e)?;
+        }
+        let radius = world.tuning.aggro_radius.min(i16::MAX as f32) as i16;
+        for e in &world.enemies {
+            let c = e.trans.center();
+            canvas.circle(c.x() as i16, c.y() as i16, radius, Color::RGBA(255, 80, 80, 90)).map_err(Error::render)?;
+        }
+        canvas.set_blend_mode(BlendMode::None);
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        Ok(())
+    }
+
+    // `textures` is the texture cache: path and number of handles held
+    pub fn build(&self, ui: &mut Ui, world: &World, textures: &[(&String, usize)], stats: &CacheStats, time_scale: f32) {
+        if !self.open {
+            return;
+        }
+        let times: Vec<f32> = self.frame_times.iter().map(|t| *t as f32).collect();
+        let average = if times.is_empty() { 0.0 } else { times.iter().sum::<f32>() / times.len() as f32 };
+        let fps = if average > 0.0 { 1000.0 / average } else { 0.0 };
+
+        ui.begin_overlay();
+        ui.begin_area(Anchor::Right, 0, 0, 260, 560, true);
+        let dim = ui.theme.text_dim;
+        ui.label(&format!("{:.0} FPS {:.1} MS", fps, average));
+        ui.graph(&times, GRAPH_MAX_MS, 236, 40, Color::RGB(80, 220, 80));
+        flame_bar(ui);
+        if time_scale != 1.0 {
+            ui.label(&format!("TIMESCALE {}", time_scale));
+        }
+        if world.god {
+            ui.label("GOD MODE");
+        }
+        ui.space(4);
+        let ships = world.players.iter().map(|p| &p.ship);
+        let all: Vec<&Entity> = ships.chain(&world.enemies).chain(&world.bullets).chain(&world.powerups).collect();
+        for typ in ENTITY_TYPES.iter() {
+            let count = all.iter().filter(|e| e.typ == *typ).count();
+            ui.label_colored(&format!("{:?} {}", typ, count), dim);
+        }
+        ui.space(4);
+        ui.label(&format!("TEXTURES {} {:.1} MB", stats.entries, stats.bytes as f32 / (1024.0 * 1024.0)));
+        ui.label_colored(&format!("HITS {} MISSES {} ({:.0}%)", stats.hits, stats.misses, stats.hit_rate() * 100.0), dim);
+        if stats.pending > 0 || stats.failed > 0 {
+            ui.label_colored(&format!("LOADING {} FAILED {}", stats.pending, stats.failed), dim);
+        }
+        for (path, handles) in textures {
+            let name = path.rsplit('/').next().unwrap_or("");
+            let name = name.trim_start_matches("SpaceShooterAssetPack_");
+            ui.label_colored(&format!("{} x{}", name, handles), dim);
+        }
+        ui.end();
+        ui.end_overlay();
+    }
+}
+
+fn draw_entity(canvas: &mut WindowCanvas, e: &Entity) -> Result<(), Error> {
+    canvas.set_draw_color(box_color(&e.typ));
+    canvas.draw_rect(e.get_rect()).map_err(Error::render)?;
+    let from = e.trans.center();
+    let to = from + e.trans.vel * VELOCITY_SECONDS;
+    canvas.set_draw_color(Color::RGBA(255, 255, 255, 160));
+    canvas.draw_line(Point::new(from.x() as i32, from.y() as i32), Point::new(to.x() as i32, to.y() as i32)).map_err(Error::render)?;
+    Ok(())
+}
+
+// Last frame's scopes laid out across its length, nested ones underneath,
+// with the average time in each outer scope listed below
+fn flame_bar(ui: &mut Ui) {
+    let frame = match profiler::last_frame() {
+        Some(f) if profiler::enabled() && f.duration > 0 => f,
+        _ => return,
+    };
+    let rect = ui.allocate(FLAME_WIDTH, FLAME_ROW * FLAME_ROWS);
+    ui.fill(rect, Color::RGBA(0, 0, 0, 160));
+    let scale = FLAME_WIDTH as f32 / frame.duration as f32;
+    for span in frame.spans.iter().filter(|s| s.depth < FLAME_ROWS) {
+        let w = (span.duration as f32 * scale) as u32;
+        if w > 0 {
+            let x = rect.x() + (span.start as f32 * scale) as i32;
+            let y = rect.y() + (span.depth * FLAME_ROW) as i32;
+            ui.fill(Rect::new(x, y, w, FLAME_ROW - 1), span_color(span.name));
+        }
+    }
+    for (name, ms) in profiler::averages() {
+        ui.label_colored(&format!("{} {:.2} MS", name.to_uppercase(), ms), span_color(name));
+    }
+}
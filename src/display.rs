@@ -0,0 +1,88 @@
+//display module - window modes, logical resolution and HUD anchoring
+
+use crate::config::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::error::Error;
+use crate::settings::{DisplaySettings, WindowMode};
+
+use sdl2::rect::Rect;
+use sdl2::render::WindowCanvas;
+use sdl2::video::FullscreenType;
+
+// The game is laid out in a fixed SCREEN_WIDTH x SCREEN_HEIGHT logical space.
+// SDL scales that to whatever the window is, letterboxing as needed, and
+// maps mouse coordinates back into it.
+pub fn apply_display(canvas: &mut WindowCanvas, display: &DisplaySettings) -> Result<(), Error> {
+    let fail = |e: String| Error::init("the display mode", e);
+    let fullscreen = match display.mode {
+        WindowMode::Windowed => FullscreenType::Off,
+        WindowMode::Borderless => FullscreenType::Desktop,
+    };
+    canvas.window_mut().set_fullscreen(fullscreen).map_err(fail)?;
+    if display.mode == WindowMode::Windowed {
+        canvas
+            .window_mut()
+            .set_size(display.width, display.height)
+            .map_err(|e| fail(e.to_string()))?;
+    }
+    canvas
+        .set_logical_size(SCREEN_WIDTH, SCREEN_HEIGHT)
+        .map_err(|e| fail(e.to_string()))?;
+    canvas.set_integer_scale(display.integer_scale).map_err(fail)?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+// Places a w x h box relative to an anchor point on the logical screen.
+// Offsets point inwards from edges, so (10, 10) at BottomRight is 10 pixels
+// up and left of the corner.
+pub fn anchored(anchor: Anchor, x: i32, y: i32, w: u32, h: u32) -> Rect {
+    anchored_in(Rect::new(0, 0, SCREEN_WIDTH, SCREEN_HEIGHT), anchor, x, y, w, h)
+}
+
+pub fn anchored_in(area: Rect, anchor: Anchor, x: i32, y: i32, w: u32, h: u32) -> Rect {
+    let (w_i, h_i) = (w as i32, h as i32);
+    let left = area.x() + x;
+    let center_x = area.x() + (area.width() as i32 - w_i) / 2 + x;
+    let right = area.x() + area.width() as i32 - w_i - x;
+    let top = area.y() + y;
+    let center_y = area.y() + (area.height() as i32 - h_i) / 2 + y;
+    let bottom = area.y() + area.height() as i32 - h_i - y;
+    let (px, py) = match anchor {
+        Anchor::TopLeft => (left, top),
+        Anchor::Top => (center_x, top),
+        Anchor::TopRight => (right, top),
+        Anchor::Left => (left, center_y),
+        Anchor::Center => (center_x, center_y),
+        Anchor::Right => (right, center_y),
+        Anchor::BottomLeft => (left, bottom),
+        Anchor::Bottom => (center_x, bottom),
+        Anchor::BottomRight => (right, bottom),
+    };
+    Rect::new(px, py, w, h)
+}
+
+// The part of the window, in real pixels, that the logical screen is
+// scaled into. Everything outside it is letterbox bars.
+pub fn letterbox(canvas: &WindowCanvas) -> Result<Rect, Error> {
+    let (out_w, out_h) = canvas.output_size().map_err(Error::render)?;
+    // minimized, or not shown yet
+    if out_w == 0 || out_h == 0 {
+        return Err(Error::render("window has no area"));
+    }
+    let (scale_x, scale_y) = canvas.scale();
+    let w = ((SCREEN_WIDTH as f32 * scale_x) as u32).clamp(1, out_w);
+    let h = ((SCREEN_HEIGHT as f32 * scale_y) as u32).clamp(1, out_h);
+    Ok(Rect::new(((out_w - w) / 2) as i32, ((out_h - h) / 2) as i32, w, h))
+}
@@ -1,17 +1,27 @@
 //entity module
 
 use crate::config;
+use crate::error::Error;
+use crate::ini::Section;
+use crate::render::{Layer, RenderQueue, Sprite};
+use crate::vfs::Vfs;
 
 use glam::Vec2;
 use sdl2::rect::{Rect, Point};
-use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
 
 use config::*;
-use sdl2::render::WindowCanvas;
-use sdl2::render::Texture;
+use sdl2::render::{BlendMode, Texture};
 
 use rand::Rng;
 
+use std::collections::HashMap;
+
+pub const SPRITES_PATH: &str = "assets/sprites.ini";
+// enemies fly a random ship from a block this many rows and columns across,
+// wrapped to the size of the block SPRITES_PATH gives
+pub const ENEMY_VARIANTS: i32 = 6;
+
 #[derive(Clone)]
 pub struct Transform {
     pub pos: Vec2,
@@ -39,9 +49,17 @@ impl Transform {
 
     pub fn rotate_to_vec2(&mut self, target: Vec2) {
         let d = target - self.pos;
+        self.rotate_to_dir(d);
+    }
+
+    pub fn rotate_to_dir(&mut self, d: Vec2) {
         self.rot = 180.0 -(d.x() as f64).atan2(d.y() as f64).to_degrees();
     }
 
+    pub fn center(&self) -> Vec2 {
+        self.pos + self.scale * 0.5
+    }
+
     pub fn get_rect(&self) -> Rect {
         return Rect::new(self.pos.x() as i32, self.pos.y() as i32, self.scale.x() as u32, self.scale.y() as u32);
     }
@@ -110,15 +128,10 @@ impl Animation {
         }
     }
 
-    pub fn draw(&mut self, canvas: &mut WindowCanvas, texture: &Texture, trans: &Transform) {
-        canvas.copy_ex(&texture, 
-            self.get_frame_rect(), 
-            trans.get_rect(),
-            trans.rot,
-            None,
-            false,
-            false
-        ).unwrap();
+    pub fn sprite<'a>(&self, layer: Layer, texture: &'a Texture<'a>, trans: &Transform) -> Sprite<'a> {
+        let mut sprite = Sprite::new(layer, texture, self.get_frame_rect(), trans.get_rect());
+        sprite.rot = trans.rot;
+        sprite
     }
 
     pub fn get_frame_rect(&self) -> Rect {
@@ -128,8 +141,107 @@ impl Animation {
             self.frame_size.x as u32,
         self.frame_size.y as u32)
     }
+
+    // The same animation starting from another cell of the sheet
+    pub fn moved(&self, row: i32, col: i32) -> Animation {
+        Animation { first_row: row, first_col: col, ..self.clone() }
+    }
+
+    // Every frame's rect, first to last
+    pub fn frame_rects(&self) -> Vec<Rect> {
+        (0..self.total_frames)
+            .map(|frame| Animation { current_frame: frame, ..self.clone() }.get_frame_rect())
+            .collect()
+    }
+}
+
+// The sheet each kind of entity is drawn from
+pub fn sheet(typ: &EntityType) -> &'static str {
+    match typ {
+        EntityType::Player | EntityType::Enemy => SHIP_SHEET,
+        EntityType::Bullet | EntityType::EnemyBullet => PROJECTILE_SHEET,
+        _ => MISC_SHEET,
+    }
 }
 
+// Which of the game's own sprites an entity shows. Where each one is on its
+// sheet is looked up in Sprites when it's drawn.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Look {
+    // the one for its type
+    Stock,
+    Player(usize),
+    // row and column into the enemy block
+    Enemy(i32, i32),
+    // frames set by a script, drawn as given
+    Scripted,
+}
+
+// Where the stock sprites are on their sheets, from SPRITES_PATH so a mod
+// that redraws a sheet can move them. Cells are (column, row).
+pub struct Sprites {
+    players: Vec<(i32, i32)>,
+    enemy_first: (i32, i32),
+    enemy_size: (i32, i32),
+    stock: HashMap<&'static str, (i32, i32)>,
+}
+
+impl Sprites {
+    pub fn load(vfs: &Vfs, path: &str) -> Result<Self, Error> {
+        let ini = vfs.ini(path)?;
+        let bad = |e: String| Error::config(vfs.resolve(path), e);
+        let section = |name: &str| ini.section(name).ok_or_else(|| bad(format!("no [{}] section", name)));
+
+        let players = section("players")?;
+        let players = (1..=MAX_PLAYERS).map(|n| cell(players, &n.to_string())).collect::<Result<_, _>>().map_err(bad)?;
+        let enemies = section("enemies")?;
+        let (first, last) = (cell(enemies, "first").map_err(bad)?, cell(enemies, "last").map_err(bad)?);
+        if last.0 < first.0 || last.1 < first.1 {
+            return Err(bad("[enemies] last: before first".to_string()));
+        }
+        let mut stock = HashMap::new();
+        for typ in [EntityType::Bullet, EntityType::EnemyBullet] {
+            stock.insert(typ.name(), cell(section("projectiles")?, typ.name()).map_err(bad)?);
+        }
+        for typ in POWERUPS {
+            stock.insert(typ.name(), cell(section("powerups")?, typ.name()).map_err(bad)?);
+        }
+        Ok(Sprites {
+            players,
+            enemy_first: first,
+            enemy_size: (last.0 - first.0 + 1, last.1 - first.1 + 1),
+            stock,
+        })
+    }
+
+    // The entity's animation, moved to where its sprite is
+    pub fn place(&self, entity: &Entity) -> Animation {
+        let (col, row) = match entity.look {
+            Look::Stock => match self.stock.get(entity.typ.name()) {
+                Some(cell) => *cell,
+                None => return entity.anim.clone(),
+            },
+            Look::Player(index) => self.players[index % self.players.len()],
+            Look::Enemy(row, col) => (
+                self.enemy_first.0 + col % self.enemy_size.0,
+                self.enemy_first.1 + row % self.enemy_size.1,
+            ),
+            Look::Scripted => return entity.anim.clone(),
+        };
+        entity.anim.moved(row, col)
+    }
+}
+
+// "column, row" of a cell on the sheet
+fn cell(section: &Section, key: &str) -> Result<(i32, i32), String> {
+    let err = || format!("[{}] {}: expected column, row", section.name, key);
+    let value = section.get(key).ok_or_else(err)?;
+    let numbers: Vec<i32> = value.split(',').map(|n| n.trim().parse().ok().filter(|n| *n >= 0).ok_or_else(err)).collect::<Result<_, _>>()?;
+    match numbers[..] {
+        [col, row] => Ok((col, row)),
+        _ => Err(err()),
+    }
+}
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum EntityType {
@@ -137,20 +249,50 @@ pub enum EntityType {
     Enemy,
     Bullet,
     EnemyBullet,
-    Particle,
     PowerupHealth,
     PowerupShield,
     PowerupBulletSpeed,
     PowerupNuke
 }
 
+pub const POWERUPS: [EntityType; 4] = [
+    EntityType::PowerupHealth,
+    EntityType::PowerupShield,
+    EntityType::PowerupBulletSpeed,
+    EntityType::PowerupNuke,
+];
+
+impl EntityType {
+    // What the console, scripts, the stream and telemetry call it
+    pub fn name(&self) -> &'static str {
+        match self {
+            EntityType::Player => "player",
+            EntityType::Enemy => "enemy",
+            EntityType::Bullet => "bullet",
+            EntityType::EnemyBullet => "enemy_bullet",
+            EntityType::PowerupHealth => "health",
+            EntityType::PowerupShield => "shield",
+            EntityType::PowerupBulletSpeed => "bullet_speed",
+            EntityType::PowerupNuke => "nuke",
+        }
+    }
+
+    pub fn powerup(name: &str) -> Option<EntityType> {
+        POWERUPS.iter().find(|typ| typ.name() == name).cloned()
+    }
+}
+
 #[derive(Clone)]
 pub struct Entity {
     pub typ: EntityType,
     pub trans: Transform,
     pub anim: Animation,
+    pub look: Look,
     pub life: i32,
-    pub shield: i32
+    pub shield: i32,
+    pub flash: f32,
+    // index of the player that fired a bullet
+    pub owner: Option<usize>
 }
 
 impl Entity {
@@ -158,8 +300,11 @@ impl Entity {
         Entity {
             trans: Transform::new(),
             anim: Animation::new(),
+            look: Look::Stock,
             life: 5,
             shield: 3,
+            flash: 0.0,
+            owner: None,
             typ: t,
         }
     }
@@ -168,20 +313,11 @@ impl Entity {
         self.trans.get_rect()
     }
 
-    pub fn update_position(&mut self, keys: &[Keycode], dt: f32) {
-        self.trans.vel = Vec2::zero();
-        for key in keys {
-            match key {
-                Keycode::W => self.trans.vel -= Vec2::unit_y(),
-                Keycode::A => self.trans.vel -= Vec2::unit_x(),
-                Keycode::S => self.trans.vel += Vec2::unit_y(),
-                Keycode::D => self.trans.vel += Vec2::unit_x(),
-                _ => {}
-            }
-        }
+    pub fn update_position(&mut self, dir: Vec2, speed: f32, dt: f32) {
+        self.flash = (self.flash - dt).max(0.0);
+        self.trans.vel = dir;
         if self.trans.vel != Vec2::zero() {
-            self.trans.vel = self.trans.vel.normalize();
-            self.trans.vel *= PLAYER_SPEED;
+            self.trans.vel *= speed;
             self.trans.update(dt);
         }
         
@@ -190,6 +326,7 @@ impl Entity {
 
     pub fn update(&mut self, dt: f32) {
         let mut out_of_bounds = false;
+        self.flash = (self.flash - dt).max(0.0);
         self.trans.update(dt);
         self.trans.rotate_to_velocity();
         if self.trans.pos.x() < -(self.trans.scale.x() * 2.0) {
@@ -220,99 +357,84 @@ impl Entity {
             self.life -= 1;
         }
         self.anim.update(dt);
-        if self.anim.finished && self.typ == EntityType::Particle {
-            self.life = 0;
-        }
         
     }
 
-    pub fn draw(&mut self, canvas: &mut WindowCanvas, texture: &Texture) {
-        self.anim.draw(canvas, texture, &self.trans);
+    pub fn sprite<'a>(&self, layer: Layer, texture: &'a Texture<'a>, sprites: &Sprites) -> Sprite<'a> {
+        sprites.place(self).sprite(layer, texture, &self.trans)
+    }
+
+    pub fn draw<'a>(&self, queue: &mut RenderQueue<'a>, layer: Layer, texture: &'a Texture<'a>, sprites: &Sprites) {
+        queue.submit(self.sprite(layer, texture, sprites));
+        if self.flash > 0.0 {
+            // draw the sprite again additively to wash it out towards white
+            let mut flash = self.sprite(layer, texture, sprites);
+            flash.z = 1.0;
+            flash.tint = Color::RGBA(255, 255, 255, (255.0 * (self.flash / FLASH_TIME).min(1.0)) as u8);
+            flash.blend = BlendMode::Add;
+            queue.submit(flash);
+        }
     }
 
 }
 
-pub fn spawn_enemy() -> Entity {
+pub fn spawn_enemy(rng: &mut impl Rng, speed: f32) -> Entity {
     let mut e = Entity::new(EntityType::Enemy);
-    e.trans.pos = Vec2::new(rand::thread_rng().gen_range(100..=SCREEN_WIDTH-108) as f32, rand::thread_rng().gen_range(100..=SCREEN_HEIGHT-108) as f32);
-    while e.trans.vel == Vec2::zero() {
-        e.trans.vel = Vec2::new(rand::thread_rng().gen_range(-ENEMY_SPEED..=ENEMY_SPEED), rand::thread_rng().gen_range(-ENEMY_SPEED..=ENEMY_SPEED));
+    e.trans.pos = Vec2::new(rng.gen_range(100..=SCREEN_WIDTH-108) as f32, rng.gen_range(100..=SCREEN_HEIGHT-108) as f32);
+    while e.trans.vel == Vec2::zero() && speed > 0.0 {
+        e.trans.vel = Vec2::new(rng.gen_range(-speed..=speed), rng.gen_range(-speed..=speed));
     }
-    e.anim = Animation::construct(1, rand::thread_rng().gen_range(0..=5), rand::thread_rng().gen_range(4..=9), Point::new(8,8), 1, true);
+    e.anim = stock_animation();
+    e.look = Look::Enemy(rng.gen_range(0..ENEMY_VARIANTS), rng.gen_range(0..ENEMY_VARIANTS));
     e.trans.scale = Vec2::new(24.0, 24.0);
     e
 }
 
-pub fn spawn_powerup() -> Entity {
-    let ptype = rand::thread_rng().gen_range(0..4);
-    println!("ptype={}", ptype);
-    let mut e = Entity::new(EntityType::PowerupHealth);
-    e.trans.pos = Vec2::new(rand::thread_rng().gen_range(100..=SCREEN_WIDTH-108) as f32, rand::thread_rng().gen_range(100..=SCREEN_HEIGHT-108) as f32);
-    e.trans.scale = Vec2::new(24.0, 24.0);
-    match ptype {
-        0 => {
-            e.typ = EntityType::PowerupHealth;
-            e.anim = Animation::construct(1, 0, 2, Point::new(8,8), 1, true);
-        }
-        1 => {
-            e.typ = EntityType::PowerupShield;
-            e.anim = Animation::construct(1, 0, 3, Point::new(8,8), 1, true);
-        }
-        2 => {
-            e.typ = EntityType::PowerupNuke;
-            e.anim = Animation::construct(1, 6, 11, Point::new(8,8), 1, true);
-        }
-        3 => {
-            e.typ = EntityType::PowerupBulletSpeed;
-            e.anim = Animation::construct(1, 1, 3, Point::new(8,8), 1, true);
-        }
-        _ => {}
-    }
-    e
+// One 8x8 frame; Sprites says which
+pub fn stock_animation() -> Animation {
+    Animation::construct(1, 0, 0, Point::new(8,8), 1, true)
 }
 
-pub fn spawn_particle(o: &mut Entity) -> Entity {
-    let mut e = Entity::new(EntityType::Particle);
-    e.trans.pos = o.trans.pos;
-    let anim = rand::thread_rng().gen_range(1..=3);
-    e.anim = Animation::construct(4, 0, 0, Point::new(8,8), 4, false);
-    match anim {
-        1 => { e.anim.first_col = 8; e.anim.first_row = 6; },
-        2 => { e.anim.first_col = 9; e.anim.first_row = 6; },
-        3 => { e.anim.first_col = 9; e.anim.first_row = 7; },
-        _ => { e.anim.first_col = 8; e.anim.first_row = 6;  }
-    }
-    e.trans.scale = Vec2::new(24.0, 24.0);
-    e
+pub fn spawn_powerup(rng: &mut impl Rng) -> Entity {
+    let ptype = rng.gen_range(0..4);
+    trace!("spawning powerup type {}", ptype);
+    let typ = match ptype {
+        0 => EntityType::PowerupHealth,
+        1 => EntityType::PowerupShield,
+        2 => EntityType::PowerupNuke,
+        _ => EntityType::PowerupBulletSpeed,
+    };
+    let pos = Vec2::new(rng.gen_range(100..=SCREEN_WIDTH-108) as f32, rng.gen_range(100..=SCREEN_HEIGHT-108) as f32);
+    make_powerup(typ, pos)
 }
 
-pub fn spawn_particle_shield(o: &Entity) -> Entity {
-    let mut e = Entity::new(EntityType::Particle);
-    e.trans.pos = Vec2::new(o.trans.pos.x() - 8.0, o.trans.pos.y() - 8.0);
-    e.anim = Animation::construct(4, 2, 2, Point::new(16,16), 16, false);
-    e.trans.scale = Vec2::new(40.0, 40.0);
+pub fn make_powerup(typ: EntityType, pos: Vec2) -> Entity {
+    let mut e = Entity::new(typ);
+    e.trans.pos = pos;
+    e.trans.scale = Vec2::new(24.0, 24.0);
+    e.anim = stock_animation();
     e
 }
 
-pub fn spawn_bullet(p: &Entity, v: &Vec2) -> Entity {
+pub fn spawn_bullet(p: &Entity, v: &Vec2, speed: f32) -> Entity {
     let mut e = Entity::new(EntityType::Bullet);
     e.trans.pos = p.trans.pos;
     e.trans.vel = *v - p.trans.pos;
     e.trans.vel = e.trans.vel.normalize();
-    e.trans.vel *= BULLET_SPEED;
-    e.anim = Animation::construct(1, 1, 1, Point::new(8,8), 1, true);
+    e.trans.vel *= speed;
+    e.anim = stock_animation();
     e.trans.scale = Vec2::new(24.0, 24.0);
     e.trans.rot = p.trans.rot;
     e
 }
 
-pub fn spawn_enemy_bullet(p: &Entity, v: &Vec2) -> Entity {
+pub fn spawn_enemy_bullet(p: &Entity, v: &Vec2, speed: f32) -> Entity {
     let mut e = Entity::new(EntityType::EnemyBullet);
     e.trans.pos = p.trans.pos;
     e.trans.vel = *v - p.trans.pos;
     e.trans.vel = e.trans.vel.normalize();
-    e.trans.vel *= BULLET_SPEED;
-    e.anim = Animation::construct(1, 1, 0, Point::new(8,8), 1, true);
+    e.trans.vel *= speed;
+    e.anim = stock_animation();
     e.trans.scale = Vec2::new(24.0, 24.0);
     e.trans.rot = p.trans.rot;
     e
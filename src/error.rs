@@ -0,0 +1,88 @@
+//error module - what can go wrong loading assets, drawing and starting up,
+//each carrying the file or call it happened in
+
+use std::error;
+use std::fmt;
+use std::io;
+
+pub enum Error {
+    // nothing provides `path`; `searched` is where was looked
+    NotFound { path: String, searched: Vec<String> },
+    Io { path: String, source: io::Error },
+    // the file is there but isn't what it should be
+    Decode { path: String, message: String },
+    // a data or settings file with a bad value in it
+    Config { path: String, message: String },
+    // a subsystem, the window or the renderer wouldn't start
+    Init { what: String, message: String },
+    Render(String),
+    // what was being done when `source` went wrong
+    Context { context: String, source: Box<Error> },
+    // anything that fits none of the above, as text
+    Other(String),
+}
+
+impl Error {
+    pub fn io(path: impl fmt::Display, source: io::Error) -> Error {
+        Error::Io { path: path.to_string(), source }
+    }
+
+    pub fn decode(path: impl fmt::Display, message: impl fmt::Display) -> Error {
+        Error::Decode { path: path.to_string(), message: message.to_string() }
+    }
+
+    pub fn config(path: impl fmt::Display, message: impl fmt::Display) -> Error {
+        Error::Config { path: path.to_string(), message: message.to_string() }
+    }
+
+    pub fn init(what: &str, message: impl fmt::Display) -> Error {
+        Error::Init { what: what.to_string(), message: message.to_string() }
+    }
+
+    pub fn render(message: impl fmt::Display) -> Error {
+        Error::Render(message.to_string())
+    }
+
+    pub fn context(self, context: impl fmt::Display) -> Error {
+        Error::Context { context: context.to_string(), source: Box::new(self) }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NotFound { path, searched } => write!(f, "asset not found: {} (looked in {})", path, searched.join(", ")),
+            Error::Io { path, source } => write!(f, "{}: {}", path, source),
+            Error::Decode { path, message } => write!(f, "{}: {}", path, message),
+            Error::Config { path, message } => write!(f, "{}: {}", path, message),
+            Error::Init { what, message } => write!(f, "couldn't start {}: {}", what, message),
+            Error::Render(message) => write!(f, "drawing failed: {}", message),
+            Error::Context { context, source } => write!(f, "{}: {}", context, source),
+            Error::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+// What main prints on the way out, so the same as Display rather than the
+// variant's insides
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Context { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for String {
+    fn from(e: Error) -> String {
+        e.to_string()
+    }
+}
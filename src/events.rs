@@ -0,0 +1,76 @@
+//events module
+
+use glam::Vec2;
+
+use crate::entity::EntityType;
+use crate::json::{num, string, Json};
+
+// What hurt a player
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cause {
+    Enemy,
+    EnemyBullet,
+    // friendly fire from this player
+    Teammate(usize),
+}
+
+impl Cause {
+    // which teammate is given alongside, where there is one
+    pub fn name(&self) -> &'static str {
+        match self {
+            Cause::Enemy => "enemy",
+            Cause::EnemyBullet => "enemy_bullet",
+            Cause::Teammate(_) => "teammate",
+        }
+    }
+}
+
+// Things that happened during a frame of gameplay. The collision code only
+// records them; sounds, effects and the rest react to them afterwards.
+#[derive(Debug, Clone)]
+pub enum GameEvent {
+    EnemySpawned { pos: Vec2 },
+    // `by` is the player responsible, if any
+    EnemyKilled { pos: Vec2, by: Option<usize> },
+    PlayerHit { pos: Vec2, shielded: bool, player: usize, cause: Cause },
+    PlayerKilled { pos: Vec2, player: usize, cause: Cause },
+    PlayerRevived { pos: Vec2, player: usize },
+    PowerupSpawned { pos: Vec2 },
+    PowerupCollected { pos: Vec2, typ: EntityType },
+    Shot { pos: Vec2 },
+    Restarted,
+}
+
+impl GameEvent {
+    // The event's name, where it happened and whatever else there is to say
+    // about it. The stream, telemetry and scripts all describe events from
+    // this, so they agree on what they're called.
+    pub fn describe(&self) -> (&'static str, Option<Vec2>, Vec<(&'static str, Json)>) {
+        let cause_fields = |cause: &Cause| {
+            let mut fields = vec![("cause", string(cause.name()))];
+            if let Cause::Teammate(player) = cause {
+                fields.push(("teammate", num(player)));
+            }
+            fields
+        };
+        match self {
+            GameEvent::EnemySpawned { pos } => ("enemy_spawned", Some(*pos), Vec::new()),
+            GameEvent::EnemyKilled { pos, by } => ("enemy_killed", Some(*pos), vec![("by", by.map_or(Json::Null, num))]),
+            GameEvent::PlayerHit { pos, shielded, player, cause } => {
+                let mut fields = vec![("player", num(player)), ("shielded", Json::Bool(*shielded))];
+                fields.extend(cause_fields(cause));
+                ("player_hit", Some(*pos), fields)
+            }
+            GameEvent::PlayerKilled { pos, player, cause } => {
+                let mut fields = vec![("player", num(player))];
+                fields.extend(cause_fields(cause));
+                ("player_killed", Some(*pos), fields)
+            }
+            GameEvent::PlayerRevived { pos, player } => ("player_revived", Some(*pos), vec![("player", num(player))]),
+            GameEvent::PowerupSpawned { pos } => ("powerup_spawned", Some(*pos), Vec::new()),
+            GameEvent::PowerupCollected { pos, typ } => ("powerup_collected", Some(*pos), vec![("powerup", string(typ.name()))]),
+            GameEvent::Shot { pos } => ("shot", Some(*pos), Vec::new()),
+            GameEvent::Restarted => ("restarted", None, Vec::new()),
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//gamepad module - opening, hot-plugging and rumbling game controllers
+
+use sdl2::controller::GameController;
+use sdl2::GameControllerSubsystem;
+use sdl2::event::Event;
+
+// Controllers only need to be kept open here; their buttons and sticks
+// arrive as ordinary events and are turned into actions by Input.
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    pads: Vec<GameController>,
+}
+
+impl Gamepads {
+    pub fn new(subsystem: GameControllerSubsystem) -> Self {
+        Gamepads {
+            subsystem,
+            pads: Vec::new(),
+        }
+    }
+
+    // SDL also sends an added event for every controller already plugged
+    // in at startup, so this is the only place pads get opened.
+    pub fn handle_event(&mut self, event: &Event) {
+        match event {
+            Event::ControllerDeviceAdded { which, .. } => {
+                if !self.subsystem.is_game_controller(*which) {
+                    return;
+                }
+                match self.subsystem.open(*which) {
+                    Ok(pad) => {
+                        info!("controller connected: {}", pad.name());
+                        self.pads.push(pad);
+                    }
+                    Err(e) => warn!("could not open controller {}: {}", which, e),
+                }
+            }
+            Event::ControllerDeviceRemoved { which, .. } => {
+                self.pads.retain(|pad| {
+                    let keep = pad.instance_id() != *which;
+                    if !keep {
+                        info!("controller disconnected: {}", pad.name());
+                    }
+                    keep
+                });
+            }
+            _ => {}
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.pads.len()
+    }
+
+    // instance ids in the order the pads were connected
+    pub fn ids(&self) -> Vec<u32> {
+        self.pads.iter().map(|pad| pad.instance_id()).collect()
+    }
+
+    // Strength from 0 to 1, on one pad or all of them; pads without rumble
+    // motors just ignore it
+    pub fn rumble(&mut self, which: Option<u32>, strength: f32, duration_ms: u32) {
+        let level = (strength.clamp(0.0, 1.0) * u16::MAX as f32) as u16;
+        for pad in &mut self.pads {
+            if which.map_or(true, |id| id == pad.instance_id()) && pad.has_rumble() {
+                let _ = pad.set_rumble(level, level / 2, duration_ms);
+            }
+        }
+    }
+}
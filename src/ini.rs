@@ -0,0 +1,131 @@
+//ini module - tiny reader/writer for the [section] key = value data files
+
+use crate::error::Error;
+
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
+pub struct Section {
+    pub name: String,
+    pub entries: Vec<(String, String)>,
+}
+
+impl Section {
+    pub fn new(name: &str) -> Self {
+        Section {
+            name: name.to_string(),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    // Missing keys are fine, keys that are present but don't parse are not
+    pub fn parse<T: FromStr>(&self, key: &str) -> Result<Option<T>, String> {
+        match self.get(key) {
+            Some(v) => v
+                .parse::<T>()
+                .map(Some)
+                .map_err(|_| format!("[{}] {}: invalid value '{}'", self.name, key, v)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn parse_or<T: FromStr>(&self, key: &str, default: T) -> Result<T, String> {
+        Ok(self.parse(key)?.unwrap_or(default))
+    }
+
+    pub fn set(&mut self, key: &str, value: String) {
+        match self.entries.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = value,
+            None => self.entries.push((key.to_string(), value)),
+        }
+    }
+}
+
+// Keys before the first [section] header end up in a section named ""
+pub struct Ini {
+    pub sections: Vec<Section>,
+}
+
+impl Ini {
+    pub fn new() -> Self {
+        Ini { sections: Vec::new() }
+    }
+
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let text = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        Ini::parse(&text, path)
+    }
+
+    // `path` is only for naming the file in errors
+    pub fn parse(text: &str, path: &str) -> Result<Self, Error> {
+        let mut ini = Ini::new();
+        let mut current = Section::new("");
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if line.starts_with('[') {
+                if !line.ends_with(']') {
+                    return Err(Error::config(path, format!("line {}: unterminated section header", number + 1)));
+                }
+                let name = line[1..line.len() - 1].trim();
+                let previous = std::mem::replace(&mut current, Section::new(name));
+                if !previous.name.is_empty() || !previous.entries.is_empty() {
+                    ini.sections.push(previous);
+                }
+                continue;
+            }
+            match line.split_once('=') {
+                Some((key, value)) => current.set(key.trim(), value.trim().to_string()),
+                None => return Err(Error::config(path, format!("line {}: expected 'key = value'", number + 1))),
+            }
+        }
+        if !current.name.is_empty() || !current.entries.is_empty() {
+            ini.sections.push(current);
+        }
+        Ok(ini)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        fs::write(path, self.to_string()).map_err(|e| Error::io(path, e))
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    pub fn section_mut(&mut self, name: &str) -> &mut Section {
+        match self.sections.iter().position(|s| s.name == name) {
+            Some(i) => &mut self.sections[i],
+            None => {
+                self.sections.push(Section::new(name));
+                self.sections.last_mut().unwrap()
+            }
+        }
+    }
+}
+
+impl fmt::Display for Ini {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for section in &self.sections {
+            if !section.name.is_empty() {
+                writeln!(f, "[{}]", section.name)?;
+            }
+            for (key, value) in &section.entries {
+                writeln!(f, "{} = {}", key, value)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
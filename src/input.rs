@@ -0,0 +1,601 @@
+//input module - maps physical inputs to named actions
+
+use crate::config::*;
+use crate::error::Error;
+use crate::ini::Ini;
+
+use std::collections::{HashMap, HashSet};
+
+use glam::Vec2;
+use sdl2::controller::{Axis, Button};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
+
+pub const BINDINGS_PATH: &str = "bindings.ini";
+
+// how far an axis has to be pushed before it counts as a held action
+pub const AXIS_PRESS_THRESHOLD: i16 = i16::MAX / 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Fire,
+    Bomb,
+    Pause,
+    Restart,
+    Screenshot,
+    ToggleFullscreen,
+}
+
+pub const ACTIONS: [Action; 10] = [
+    Action::MoveUp,
+    Action::MoveDown,
+    Action::MoveLeft,
+    Action::MoveRight,
+    Action::Fire,
+    Action::Bomb,
+    Action::Pause,
+    Action::Restart,
+    Action::Screenshot,
+    Action::ToggleFullscreen,
+];
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::MoveUp => "move_up",
+            Action::MoveDown => "move_down",
+            Action::MoveLeft => "move_left",
+            Action::MoveRight => "move_right",
+            Action::Fire => "fire",
+            Action::Bomb => "bomb",
+            Action::Pause => "pause",
+            Action::Restart => "restart",
+            Action::Screenshot => "screenshot",
+            Action::ToggleFullscreen => "toggle_fullscreen",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTIONS.iter().copied().find(|a| a.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Binding {
+    Key(Keycode),
+    Mouse(MouseButton),
+    Button(Button),
+    // true for the positive direction of the axis
+    Axis(Axis, bool),
+}
+
+impl Binding {
+    pub fn parse(text: &str) -> Option<Binding> {
+        let (kind, name) = text.trim().split_once(':')?;
+        match kind {
+            "key" => Keycode::from_name(name).map(Binding::Key),
+            "mouse" => match name {
+                "left" => Some(Binding::Mouse(MouseButton::Left)),
+                "middle" => Some(Binding::Mouse(MouseButton::Middle)),
+                "right" => Some(Binding::Mouse(MouseButton::Right)),
+                "x1" => Some(Binding::Mouse(MouseButton::X1)),
+                "x2" => Some(Binding::Mouse(MouseButton::X2)),
+                _ => None,
+            },
+            "button" => Button::from_string(name).map(Binding::Button),
+            "axis" => {
+                let positive = name.ends_with('+');
+                if !positive && !name.ends_with('-') {
+                    return None;
+                }
+                Axis::from_string(&name[..name.len() - 1]).map(|a| Binding::Axis(a, positive))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn to_config(&self) -> String {
+        match self {
+            Binding::Key(key) => format!("key:{}", key.name()),
+            Binding::Mouse(button) => format!("mouse:{}", match button {
+                MouseButton::Left => "left",
+                MouseButton::Middle => "middle",
+                MouseButton::Right => "right",
+                MouseButton::X1 => "x1",
+                MouseButton::X2 => "x2",
+                MouseButton::Unknown => "unknown",
+            }),
+            Binding::Button(button) => format!("button:{}", button.string()),
+            Binding::Axis(axis, positive) => format!("axis:{}{}", axis.string(), if *positive { '+' } else { '-' }),
+        }
+    }
+
+    // Short label for showing the binding on screen
+    pub fn label(&self) -> String {
+        let config = self.to_config().to_uppercase();
+        let name = config.split_once(':').map_or(config.as_str(), |(_, n)| n);
+        match self {
+            Binding::Key(_) => name.to_string(),
+            Binding::Mouse(_) => format!("MOUSE {}", name),
+            _ => format!("PAD {}", name),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Bindings {
+    pub map: Vec<(Action, Binding)>,
+}
+
+impl Bindings {
+    pub fn defaults() -> Self {
+        use Action::*;
+        let map = vec![
+            (MoveUp, Binding::Key(Keycode::W)),
+            (MoveUp, Binding::Key(Keycode::Up)),
+            (MoveUp, Binding::Button(Button::DPadUp)),
+            (MoveUp, Binding::Axis(Axis::LeftY, false)),
+            (MoveDown, Binding::Key(Keycode::S)),
+            (MoveDown, Binding::Key(Keycode::Down)),
+            (MoveDown, Binding::Button(Button::DPadDown)),
+            (MoveDown, Binding::Axis(Axis::LeftY, true)),
+            (MoveLeft, Binding::Key(Keycode::A)),
+            (MoveLeft, Binding::Key(Keycode::Left)),
+            (MoveLeft, Binding::Button(Button::DPadLeft)),
+            (MoveLeft, Binding::Axis(Axis::LeftX, false)),
+            (MoveRight, Binding::Key(Keycode::D)),
+            (MoveRight, Binding::Key(Keycode::Right)),
+            (MoveRight, Binding::Button(Button::DPadRight)),
+            (MoveRight, Binding::Axis(Axis::LeftX, true)),
+            (Fire, Binding::Mouse(MouseButton::Left)),
+            (Fire, Binding::Axis(Axis::TriggerRight, true)),
+            (Bomb, Binding::Mouse(MouseButton::Right)),
+            (Bomb, Binding::Key(Keycode::Space)),
+            (Bomb, Binding::Axis(Axis::TriggerLeft, true)),
+            (Pause, Binding::Key(Keycode::Escape)),
+            (Pause, Binding::Button(Button::Start)),
+            (Restart, Binding::Key(Keycode::R)),
+            (Restart, Binding::Button(Button::A)),
+            (Screenshot, Binding::Key(Keycode::F12)),
+            (ToggleFullscreen, Binding::Key(Keycode::F11)),
+        ];
+        Bindings { map }
+    }
+
+    // Actions missing from the file keep their default bindings, so adding
+    // a new action doesn't leave it unbound for existing players.
+    pub fn load(path: &str) -> Self {
+        let mut bindings = Bindings::defaults();
+        let ini = match Ini::load(path) {
+            Ok(ini) => ini,
+            Err(e) => {
+                if std::path::Path::new(path).exists() {
+                    warn!("could not read bindings, using defaults: {}", e);
+                }
+                return bindings;
+            }
+        };
+        if let Some(section) = ini.section("bindings") {
+            for (name, value) in &section.entries {
+                let action = match Action::from_name(name) {
+                    Some(a) => a,
+                    None => {
+                        warn!("{}: unknown action '{}'", path, name);
+                        continue;
+                    }
+                };
+                bindings.map.retain(|(a, _)| *a != action);
+                for text in value.split(',').filter(|t| !t.trim().is_empty()) {
+                    match Binding::parse(text) {
+                        Some(b) => bindings.map.push((action, b)),
+                        None => warn!("{}: bad binding '{}' for {}", path, text.trim(), name),
+                    }
+                }
+            }
+        }
+        bindings
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        let mut ini = Ini::new();
+        let section = ini.section_mut("bindings");
+        for action in ACTIONS.iter() {
+            let value: Vec<String> = self.for_action(*action).iter().map(|b| b.to_config()).collect();
+            section.set(action.name(), value.join(", "));
+        }
+        ini.save(path)
+    }
+
+    pub fn for_action(&self, action: Action) -> Vec<Binding> {
+        self.map.iter().filter(|(a, _)| *a == action).map(|(_, b)| *b).collect()
+    }
+
+    // Rebinding replaces the bindings of the same kind (keyboard/mouse or
+    // controller) and keeps the other, so rebinding a key doesn't unbind
+    // the gamepad. The binding is taken off whatever else had it.
+    pub fn rebind(&mut self, action: Action, binding: Binding) {
+        let pad = is_controller(&binding);
+        self.map.retain(|(a, b)| *b != binding && (*a != action || is_controller(b) != pad));
+        self.map.push((action, binding));
+    }
+}
+
+fn is_controller(binding: &Binding) -> bool {
+    matches!(binding, Binding::Button(_) | Binding::Axis(..))
+}
+
+// Turns an input event into the binding it would trigger, which is what a
+// rebinding screen listens for
+pub fn binding_from_event(event: &Event) -> Option<Binding> {
+    match event {
+        Event::KeyDown { keycode: Some(key), repeat: false, .. } => Some(Binding::Key(*key)),
+        Event::MouseButtonDown { mouse_btn, .. } => Some(Binding::Mouse(*mouse_btn)),
+        Event::ControllerButtonDown { button, .. } => Some(Binding::Button(*button)),
+        Event::ControllerAxisMotion { axis, value, .. } if value.unsigned_abs() > AXIS_PRESS_THRESHOLD as u16 => {
+            Some(Binding::Axis(*axis, *value > 0))
+        }
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Device {
+    KeyboardMouse,
+    Gamepad,
+}
+
+// Which events an Input listens to. A single player takes everything; in
+// co-op each player gets their own device.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Any,
+    KeyboardMouse,
+    // a controller's instance id
+    Pad(u32),
+    Nothing,
+}
+
+// Current state of every action. Gameplay only ever asks this about
+// actions, never about keys or buttons.
+pub struct Input {
+    pub bindings: Bindings,
+    source: Source,
+    // indices into bindings.map that are currently held down
+    held: HashSet<usize>,
+    pressed: HashSet<Action>,
+    pointer: Vec2,
+    axes: HashMap<Axis, f32>,
+    // aiming follows whichever of mouse or right stick was used last
+    last_device: Device,
+}
+
+impl Input {
+    pub fn new(bindings: Bindings) -> Self {
+        Input {
+            bindings,
+            source: Source::Any,
+            held: HashSet::new(),
+            pressed: HashSet::new(),
+            pointer: Vec2::new(0.0, 0.0),
+            axes: HashMap::new(),
+            last_device: Device::KeyboardMouse,
+        }
+    }
+
+    // call once per frame before handing it that frame's events
+    pub fn begin_frame(&mut self) {
+        self.pressed.clear();
+    }
+
+    pub fn source(&self) -> Source {
+        self.source
+    }
+
+    pub fn set_source(&mut self, source: Source) {
+        if source == self.source {
+            return;
+        }
+        self.source = source;
+        self.release_all();
+        if let Source::Pad(_) = source {
+            self.last_device = Device::Gamepad;
+        }
+    }
+
+    pub fn accepts(&self, event: &Event) -> bool {
+        let pad = match event {
+            Event::ControllerAxisMotion { which, .. }
+            | Event::ControllerButtonDown { which, .. }
+            | Event::ControllerButtonUp { which, .. } => Some(*which),
+            _ => None,
+        };
+        match self.source {
+            Source::Any => true,
+            Source::KeyboardMouse => pad.is_none(),
+            Source::Pad(id) => pad == Some(id),
+            Source::Nothing => false,
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &Event) {
+        if !self.accepts(event) {
+            return;
+        }
+        match event {
+            Event::KeyDown { .. } | Event::MouseButtonDown { .. } | Event::MouseMotion { .. } => {
+                self.last_device = Device::KeyboardMouse;
+            }
+            Event::ControllerButtonDown { .. } => self.last_device = Device::Gamepad,
+            Event::ControllerAxisMotion { value, .. } if value.unsigned_abs() as f32 > AIM_DEADZONE * i16::MAX as f32 => {
+                self.last_device = Device::Gamepad;
+            }
+            _ => {}
+        }
+        match event {
+            Event::KeyDown { keycode: Some(key), repeat, .. } => {
+                if !*repeat {
+                    self.set(Binding::Key(*key), true);
+                }
+            }
+            Event::KeyUp { keycode: Some(key), .. } => self.set(Binding::Key(*key), false),
+            Event::MouseButtonDown { mouse_btn, x, y, .. } => {
+                self.move_pointer(*x, *y);
+                self.set(Binding::Mouse(*mouse_btn), true);
+            }
+            Event::MouseButtonUp { mouse_btn, .. } => self.set(Binding::Mouse(*mouse_btn), false),
+            Event::MouseMotion { x, y, .. } => self.move_pointer(*x, *y),
+            Event::ControllerButtonDown { button, .. } => self.set(Binding::Button(*button), true),
+            Event::ControllerButtonUp { button, .. } => self.set(Binding::Button(*button), false),
+            Event::ControllerAxisMotion { axis, value, .. } => {
+                self.axes.insert(*axis, (*value as f32 / i16::MAX as f32).clamp(-1.0, 1.0));
+                self.set(Binding::Axis(*axis, true), *value > AXIS_PRESS_THRESHOLD);
+                self.set(Binding::Axis(*axis, false), *value < -AXIS_PRESS_THRESHOLD);
+            }
+            _ => {}
+        }
+    }
+
+    // Forget everything held, e.g. when focus is lost and key-up events
+    // will never arrive, or after the bindings have changed
+    pub fn release_all(&mut self) {
+        self.held.clear();
+        self.axes.clear();
+    }
+
+    pub fn is_down(&self, action: Action) -> bool {
+        self.held
+            .iter()
+            .any(|i| self.bindings.map.get(*i).map_or(false, |(a, _)| *a == action))
+    }
+
+    pub fn was_pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    // Unit-or-shorter direction to move in. The left stick gives analog
+    // speed; otherwise it comes from the (digital) movement actions.
+    pub fn movement(&self) -> Vec2 {
+        let stick = self.stick(Axis::LeftX, Axis::LeftY, STICK_DEADZONE);
+        if stick != Vec2::zero() {
+            return stick;
+        }
+        let mut dir = Vec2::zero();
+        if self.is_down(Action::MoveUp) { dir -= Vec2::unit_y(); }
+        if self.is_down(Action::MoveDown) { dir += Vec2::unit_y(); }
+        if self.is_down(Action::MoveLeft) { dir -= Vec2::unit_x(); }
+        if self.is_down(Action::MoveRight) { dir += Vec2::unit_x(); }
+        if dir != Vec2::zero() { dir.normalize() } else { dir }
+    }
+
+    pub fn pointer(&self) -> Vec2 {
+        self.pointer
+    }
+
+    pub fn device(&self) -> Device {
+        self.last_device
+    }
+
+    // Stick position with a radial deadzone, rescaled so that speed ramps
+    // up smoothly from the edge of the deadzone instead of jumping.
+    pub fn stick(&self, x: Axis, y: Axis, deadzone: f32) -> Vec2 {
+        let v = Vec2::new(
+            *self.axes.get(&x).unwrap_or(&0.0),
+            *self.axes.get(&y).unwrap_or(&0.0),
+        );
+        let len = v.length();
+        if len <= deadzone {
+            return Vec2::zero();
+        }
+        v / len * ((len - deadzone) / (1.0 - deadzone)).min(1.0)
+    }
+
+    pub fn snapshot(&self) -> PlayerInput {
+        let mut input = PlayerInput {
+            movement: self.movement(),
+            aim: None,
+            aim_dir: None,
+            fire: self.is_down(Action::Fire),
+            fire_pressed: self.was_pressed(Action::Fire),
+            restart: self.was_pressed(Action::Restart),
+            bomb: self.was_pressed(Action::Bomb),
+        };
+        match self.last_device {
+            Device::KeyboardMouse => input.aim = Some(self.pointer),
+            Device::Gamepad => {
+                // twin-stick: the right stick aims and, pushed far enough, fires
+                let aim = self.stick(Axis::RightX, Axis::RightY, AIM_DEADZONE);
+                if aim != Vec2::zero() {
+                    input.aim_dir = Some(aim);
+                    input.fire |= aim.length() >= AUTO_FIRE_THRESHOLD;
+                }
+            }
+        }
+        input
+    }
+
+    fn move_pointer(&mut self, x: i32, y: i32) {
+        self.pointer = Vec2::new(x as f32, y as f32);
+    }
+
+    // Held state is tracked per binding so that letting go of one of two
+    // keys bound to the same action doesn't release it.
+    fn set(&mut self, binding: Binding, down: bool) {
+        for (i, (action, b)) in self.bindings.map.iter().enumerate() {
+            if *b != binding {
+                continue;
+            }
+            if down {
+                if self.held.insert(i) {
+                    self.pressed.insert(*action);
+                }
+            } else {
+                self.held.remove(&i);
+            }
+        }
+    }
+}
+
+// The first player keeps the keyboard and mouse and the others get the
+// connected pads in the order they were plugged in, so unplugging a pad
+// leaves its player idle until one is plugged back in
+pub fn assign_pads(inputs: &mut [Input], ids: &[u32]) {
+    for (i, input) in inputs.iter_mut().enumerate().skip(1) {
+        input.set_source(ids.get(i - 1).map_or(Source::Nothing, |id| Source::Pad(*id)));
+    }
+}
+
+// What one player asked for this frame. This is all the simulation sees,
+// so it can just as well come from a replay or a network peer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlayerInput {
+    pub movement: Vec2,
+    // point in the world to face and shoot at
+    pub aim: Option<Vec2>,
+    // or a direction to face and shoot in, from a stick
+    pub aim_dir: Option<Vec2>,
+    pub fire: bool,
+    pub fire_pressed: bool,
+    pub restart: bool,
+    pub bomb: bool,
+}
+
+impl PlayerInput {
+    pub fn new() -> Self {
+        PlayerInput {
+            movement: Vec2::zero(),
+            aim: None,
+            aim_dir: None,
+            fire: false,
+            fire_pressed: false,
+            restart: false,
+            bomb: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn axis(which: u32, axis: Axis, amount: f32) -> Event {
+        Event::ControllerAxisMotion { timestamp: 0, which, axis, value: (amount * i16::MAX as f32) as i16 }
+    }
+
+    fn button(which: u32, button: Button) -> Event {
+        Event::ControllerButtonDown { timestamp: 0, which, button }
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 0.01
+    }
+
+    #[test]
+    fn stick_deadzone_ramps_up_from_its_edge() {
+        let mut input = Input::new(Bindings::defaults());
+        input.handle_event(&axis(0, Axis::LeftX, STICK_DEADZONE * 0.9));
+        assert_eq!(input.movement(), Vec2::zero());
+        input.handle_event(&axis(0, Axis::LeftX, 0.6));
+        assert!(close(input.movement().x(), (0.6 - STICK_DEADZONE) / (1.0 - STICK_DEADZONE)));
+        input.handle_event(&axis(0, Axis::LeftX, 1.0));
+        input.handle_event(&axis(0, Axis::LeftY, 1.0));
+        // pushed into the corner it still tops out at full speed
+        assert!(close(input.movement().length(), 1.0));
+    }
+
+    #[test]
+    fn axes_past_the_threshold_hold_actions() {
+        let mut input = Input::new(Bindings::defaults());
+        input.begin_frame();
+        input.handle_event(&axis(0, Axis::LeftY, -0.25));
+        assert!(!input.is_down(Action::MoveUp));
+        input.handle_event(&axis(0, Axis::LeftY, -1.0));
+        assert!(input.is_down(Action::MoveUp) && input.was_pressed(Action::MoveUp));
+        assert!(!input.is_down(Action::MoveDown));
+        input.handle_event(&axis(0, Axis::LeftY, 0.0));
+        assert!(!input.is_down(Action::MoveUp));
+
+        input.handle_event(&axis(0, Axis::TriggerRight, 1.0));
+        assert!(input.snapshot().fire && input.snapshot().fire_pressed);
+        input.begin_frame();
+        // still held, but only pressed on the frame it went down
+        assert!(input.snapshot().fire && !input.snapshot().fire_pressed);
+    }
+
+    #[test]
+    fn right_stick_aims_and_fires_when_pushed_far() {
+        let mut input = Input::new(Bindings::defaults());
+        input.handle_event(&axis(0, Axis::RightX, AIM_DEADZONE * 0.9));
+        // too small to take aiming away from the mouse
+        assert_eq!(input.device(), Device::KeyboardMouse);
+        input.handle_event(&axis(0, Axis::RightX, 0.5));
+        assert_eq!(input.device(), Device::Gamepad);
+        let aimed = input.snapshot();
+        assert!(aimed.aim.is_none() && aimed.aim_dir.is_some_and(|d| d.x() > 0.0));
+        assert!(!aimed.fire);
+        input.handle_event(&axis(0, Axis::RightX, 1.0));
+        assert!(input.snapshot().fire);
+    }
+
+    #[test]
+    fn unplugged_pads_release_their_player() {
+        let mut inputs = vec![Input::new(Bindings::defaults()), Input::new(Bindings::defaults())];
+        inputs[0].set_source(Source::KeyboardMouse);
+        assign_pads(&mut inputs, &[7]);
+        let fire = axis(7, Axis::TriggerRight, 1.0);
+        for input in &mut inputs {
+            input.handle_event(&fire);
+        }
+        assert!(!inputs[0].is_down(Action::Fire));
+        assert!(inputs[1].is_down(Action::Fire));
+
+        // the pad goes away mid-press and its trigger-up never arrives
+        assign_pads(&mut inputs, &[]);
+        assert_eq!(inputs[1].source(), Source::Nothing);
+        assert!(!inputs[1].is_down(Action::Fire));
+        inputs[1].handle_event(&fire);
+        assert!(!inputs[1].is_down(Action::Fire));
+
+        // plugged back in it comes back with a new instance id
+        assign_pads(&mut inputs, &[9]);
+        inputs[1].begin_frame();
+        inputs[1].handle_event(&button(7, Button::A));
+        assert!(!inputs[1].was_pressed(Action::Restart));
+        inputs[1].handle_event(&button(9, Button::A));
+        assert!(inputs[1].was_pressed(Action::Restart));
+    }
+
+    #[test]
+    fn rebinding_takes_the_binding_off_other_actions() {
+        let mut bindings = Bindings::defaults();
+        bindings.rebind(Action::Pause, Binding::Key(Keycode::W));
+        assert_eq!(bindings.for_action(Action::Pause), vec![Binding::Button(Button::Start), Binding::Key(Keycode::W)]);
+        assert!(!bindings.for_action(Action::MoveUp).contains(&Binding::Key(Keycode::W)));
+        // the other keys for moving up are untouched
+        assert!(!bindings.for_action(Action::MoveUp).is_empty());
+    }
+}
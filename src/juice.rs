@@ -0,0 +1,138 @@
+//juice module - screen shake, hit flash, hit-stop and damage vignette
+
+use crate::config::*;
+use crate::error::Error;
+use crate::events::GameEvent;
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, WindowCanvas};
+
+use rand::Rng;
+
+// Every effect can be switched off on its own, mostly for players who
+// are sensitive to flashing or camera motion.
+#[derive(Clone)]
+pub struct JuiceSettings {
+    pub shake: bool,
+    pub shake_intensity: f32,
+    pub flash: bool,
+    pub hit_stop: bool,
+    pub vignette: bool,
+    pub rumble: bool,
+}
+
+impl JuiceSettings {
+    pub fn new() -> Self {
+        JuiceSettings {
+            shake: true,
+            shake_intensity: 1.0,
+            flash: true,
+            hit_stop: true,
+            vignette: true,
+            rumble: true,
+        }
+    }
+}
+
+pub struct Juice {
+    pub settings: JuiceSettings,
+    trauma: f32,
+    hit_stop: f32,
+    vignette: f32,
+}
+
+impl Juice {
+    pub fn new(settings: JuiceSettings) -> Self {
+        Juice {
+            settings,
+            trauma: 0.0,
+            hit_stop: 0.0,
+            vignette: 0.0,
+        }
+    }
+
+    pub fn handle(&mut self, event: &GameEvent) {
+        match event {
+            GameEvent::EnemyKilled { .. } => {
+                self.add_trauma(0.3);
+                self.freeze(HIT_STOP_TIME);
+            }
+            GameEvent::PlayerHit { shielded, .. } => {
+                self.add_trauma(if *shielded { 0.4 } else { 0.6 });
+                if !*shielded {
+                    self.vignette = VIGNETTE_TIME;
+                }
+            }
+            GameEvent::PlayerKilled { .. } => {
+                self.add_trauma(1.0);
+                self.freeze(HIT_STOP_TIME * 4.0);
+                self.vignette = VIGNETTE_TIME * 2.0;
+            }
+            _ => {}
+        }
+    }
+
+    // Called with the real frame time, so effects still wind down while
+    // the simulation is frozen by a hit-stop.
+    pub fn update(&mut self, dt: f32) {
+        self.trauma = (self.trauma - TRAUMA_DECAY * dt).max(0.0);
+        self.hit_stop = (self.hit_stop - dt).max(0.0);
+        self.vignette = (self.vignette - dt).max(0.0);
+    }
+
+    pub fn time_scale(&self) -> f32 {
+        if self.hit_stop > 0.0 { 0.0 } else { 1.0 }
+    }
+
+    pub fn flash_time(&self) -> f32 {
+        if self.settings.flash { FLASH_TIME } else { 0.0 }
+    }
+
+    pub fn shake_offset(&self) -> (i32, i32) {
+        if !self.settings.shake || self.trauma <= 0.0 {
+            return (0, 0);
+        }
+        let amount = self.trauma * self.trauma * SHAKE_MAX_OFFSET * self.settings.shake_intensity;
+        let mut rng = rand::thread_rng();
+        (
+            (rng.gen_range(-1.0..=1.0) * amount) as i32,
+            (rng.gen_range(-1.0..=1.0) * amount) as i32,
+        )
+    }
+
+    pub fn draw_vignette(&self, canvas: &mut WindowCanvas) -> Result<(), Error> {
+        if !self.settings.vignette || self.vignette <= 0.0 {
+            return Ok(());
+        }
+        let strength = (self.vignette / VIGNETTE_TIME).min(1.0);
+        let (w, h) = (SCREEN_WIDTH, SCREEN_HEIGHT);
+        canvas.set_blend_mode(BlendMode::Blend);
+        // a few nested borders, fading out towards the middle of the screen
+        for i in 0..4u32 {
+            let alpha = (strength * 120.0 / (i + 1) as f32) as u8;
+            let edge = 12 * i;
+            canvas.set_draw_color(Color::RGBA(200, 0, 0, alpha));
+            canvas.fill_rects(&[
+                Rect::new(0, edge as i32, w, 12),
+                Rect::new(0, (h - edge - 12) as i32, w, 12),
+                Rect::new(edge as i32, 0, 12, h),
+                Rect::new((w - edge - 12) as i32, 0, 12, h),
+            ])
+            .map_err(Error::render)?;
+        }
+        canvas.set_blend_mode(BlendMode::None);
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        Ok(())
+    }
+
+    fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).min(1.0);
+    }
+
+    fn freeze(&mut self, time: f32) {
+        if self.settings.hit_stop {
+            self.hit_stop = self.hit_stop.max(time);
+        }
+    }
+}
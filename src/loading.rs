@@ -0,0 +1,154 @@
+//loading module - reading assets on a background thread, the per-scene
+//manifests saying what to read, and the screen shown while it happens
+//
+//A manifest is assets/scenes/<scene>.ini listing what the scene draws with:
+//
+//  [textures]
+//  ships = assets/SpaceShooterAssetPack_Ships.png
+//
+//  [fonts]
+//  title = assets/fonts/Pono_188.ttf, 48
+//
+//The names on the left are only for whoever reads the file.
+
+use crate::asset_manager::{FontDetails, FontManager, LoadState, TextureManager};
+use crate::display::Anchor;
+use crate::error::Error;
+use crate::ui::{Align, Ui};
+use crate::vfs::Vfs;
+
+use sdl2::rect::Rect;
+
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+pub const SCENES_DIR: &str = "assets/scenes";
+// milliseconds a frame spends turning what's been read into textures
+pub const UPLOAD_BUDGET_MS: u64 = 4;
+
+// A thread that reads whatever keys it's sent and sends back the results,
+// in the order they were asked for
+pub struct Background<K, D> {
+    jobs: Sender<K>,
+    done: Receiver<(K, Result<D, Error>)>,
+}
+
+impl<K, D> Background<K, D> {
+    pub fn start(vfs: Vfs, read: fn(&Vfs, &K) -> Result<D, Error>) -> Self
+    where
+        K: Send + 'static,
+        D: Send + 'static,
+    {
+        let (jobs, queue) = mpsc::channel::<K>();
+        let (finished, done) = mpsc::channel();
+        // stops when the manager, and with it `jobs`, goes away
+        thread::spawn(move || {
+            for key in queue {
+                let result = read(&vfs, &key);
+                if finished.send((key, result)).is_err() {
+                    break;
+                }
+            }
+        });
+        Background { jobs, done }
+    }
+
+    pub fn send(&self, key: K) {
+        // only fails once the thread is gone, which nothing else stops
+        let _ = self.jobs.send(key);
+    }
+
+    pub fn try_recv(&self) -> Option<(K, Result<D, Error>)> {
+        self.done.try_recv().ok()
+    }
+
+    pub fn recv(&self) -> Option<(K, Result<D, Error>)> {
+        self.done.recv().ok()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    pub scene: String,
+    pub textures: Vec<String>,
+    pub fonts: Vec<FontDetails>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    pub loaded: usize,
+    pub total: usize,
+    // what couldn't be loaded and why
+    pub failed: Vec<String>,
+}
+
+impl Progress {
+    pub fn finished(&self) -> bool {
+        self.loaded + self.failed.len() >= self.total
+    }
+
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            return 1.0;
+        }
+        (self.loaded + self.failed.len()) as f32 / self.total as f32
+    }
+}
+
+impl Manifest {
+    pub fn load(vfs: &Vfs, scene: &str) -> Result<Self, Error> {
+        let path = format!("{}/{}.ini", SCENES_DIR, scene);
+        let ini = vfs.ini(&path)?;
+        let textures = ini.section("textures").map_or(Vec::new(), |s| s.entries.iter().map(|(_, v)| v.clone()).collect());
+        let mut fonts = Vec::new();
+        for (name, value) in ini.section("fonts").map_or(&[][..], |s| &s.entries[..]) {
+            let font = match value.rsplit_once(',') {
+                Some((file, size)) => size.trim().parse().ok().map(|size| FontDetails { path: file.trim().to_string(), size }),
+                None => None,
+            };
+            fonts.push(font.ok_or_else(|| Error::config(vfs.resolve(&path), format!("[fonts] {}: expected path, size", name)))?);
+        }
+        Ok(Manifest { scene: scene.to_string(), textures, fonts })
+    }
+
+    // Starts everything reading in the background; nothing waits for it
+    pub fn request<T>(&self, textures: &mut TextureManager<T>, fonts: &mut FontManager) {
+        info!("loading scene {}: {} textures, {} fonts", self.scene, self.textures.len(), self.fonts.len());
+        for path in &self.textures {
+            textures.request(path.as_str());
+        }
+        for font in &self.fonts {
+            fonts.request(font);
+        }
+    }
+
+    pub fn progress<T>(&self, textures: &TextureManager<T>, fonts: &FontManager) -> Progress {
+        let states = self
+            .textures
+            .iter()
+            .map(|path| (path.clone(), textures.state(path.as_str())))
+            .chain(self.fonts.iter().map(|font| (format!("{} {}pt", font.path, font.size), fonts.state(font))));
+        let mut progress = Progress { total: self.textures.len() + self.fonts.len(), ..Progress::default() };
+        for (name, state) in states {
+            match state {
+                Some(LoadState::Loaded) => progress.loaded += 1,
+                Some(LoadState::Failed(e)) => progress.failed.push(if e.contains(&name) { e } else { format!("{}: {}", name, e) }),
+                Some(LoadState::Pending) | None => {}
+            }
+        }
+        progress
+    }
+}
+
+// The loading screen: the scene's name and a bar
+pub fn build(ui: &mut Ui, scene: &str, progress: &Progress) {
+    let (w, h) = (400, 24);
+    let dim = ui.theme.text_dim;
+    ui.begin_area(Anchor::Center, 0, 0, w + 40, 110, true);
+    ui.align(Align::Center);
+    ui.label(&format!("LOADING {}", scene.to_uppercase()));
+    let rect = ui.allocate(w, h);
+    ui.bar(Rect::new(rect.x(), rect.y(), w, h), progress.fraction());
+    ui.label_colored(&format!("{} / {}", progress.loaded + progress.failed.len(), progress.total), dim);
+    ui.end();
+}
@@ -0,0 +1,70 @@
+//lobby module - direct-IP host/join screen for online co-op
+
+use crate::display::Anchor;
+use crate::net::{NetRole, NET_PORT};
+use crate::ui::{Align, Ui};
+
+const MAX_ADDRESS: usize = 64;
+
+pub struct LobbyMenu {
+    pub open: bool,
+    // typed into while the join line has focus
+    pub address: String,
+    pub port: u16,
+}
+
+impl LobbyMenu {
+    pub fn new() -> Self {
+        LobbyMenu {
+            open: false,
+            address: format!("127.0.0.1:{}", NET_PORT),
+            port: NET_PORT,
+        }
+    }
+
+    // Returns what to start once the player picks host or join
+    pub fn run(&mut self, ui: &mut Ui, status: &str) -> Option<NetRole> {
+        ui.begin_menu("lobby");
+        ui.begin_area(Anchor::Top, 0, 100, 600, 200, true);
+        ui.align(Align::Center);
+        ui.label("ONLINE CO-OP");
+        ui.space(8);
+        let host = ui.item("HOST ON PORT", &format!("< {} >", self.port));
+        let join = ui.text_input("JOIN", &mut self.address, MAX_ADDRESS);
+        let back = ui.button("BACK");
+        ui.end();
+
+        if !status.is_empty() {
+            ui.begin_area(Anchor::Top, 0, 320, 800, 60, false);
+            ui.align(Align::Center);
+            ui.label(status);
+            ui.end();
+        }
+        ui.begin_area(Anchor::Bottom, 0, 0, 600, 50, false);
+        ui.align(Align::Center);
+        let hint = ui.theme.text_dim;
+        ui.label_colored("ENTER SELECT  ESC BACK", hint);
+        ui.end();
+        ui.end_menu();
+
+        if host.step < 0 {
+            self.port = self.port.saturating_sub(1).max(1024);
+        }
+        else if host.step > 0 {
+            self.port = self.port.saturating_add(1);
+        }
+        if back || ui.back() {
+            self.open = false;
+            None
+        }
+        else if host.activated {
+            Some(NetRole::Host(self.port))
+        }
+        else if join && !self.address.is_empty() {
+            Some(NetRole::Join(self.address.clone()))
+        }
+        else {
+            None
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//log module - levelled log messages with per-module filtering, to stderr
+//and optionally a JSON Lines file
+
+use crate::error::Error;
+use crate::json::{num, object, string};
+
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Instant;
+
+// Read when no --log is given, e.g. GAME_LOG=info,net=debug,asset_manager=warn
+pub const LOG_ENV: &str = "GAME_LOG";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+}
+
+// "level,module=level,..." where a bare level sets the default. A module
+// setting also covers its submodules.
+struct Filter {
+    default: Level,
+    modules: Vec<(String, Level)>,
+}
+
+impl Filter {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let mut filter = Filter { default: Level::Info, modules: Vec::new() };
+        for part in spec.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            let bad = || format!("bad log filter '{}'", part);
+            match part.split_once('=') {
+                Some((module, level)) => {
+                    let level = Level::from_name(level.trim()).ok_or_else(bad)?;
+                    filter.modules.push((module.trim().to_string(), level));
+                }
+                None => filter.default = Level::from_name(part).ok_or_else(bad)?,
+            }
+        }
+        // most specific first
+        filter.modules.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        Ok(filter)
+    }
+
+    fn level_for(&self, module: &str) -> Level {
+        self.modules
+            .iter()
+            .find(|(m, _)| module == m || module.starts_with(&format!("{}::", m)))
+            .map_or(self.default, |(_, level)| *level)
+    }
+}
+
+struct Logger {
+    filter: Filter,
+    file: Option<File>,
+    start: Instant,
+}
+
+static LOGGER: Mutex<Option<Logger>> = Mutex::new(None);
+
+pub fn init(spec: Option<&str>, path: Option<&str>) -> Result<(), Error> {
+    let from_env = std::env::var(LOG_ENV).ok();
+    let filter = Filter::parse(spec.or(from_env.as_deref()).unwrap_or("info")).map_err(|e| Error::init("logging", e))?;
+    let file = match path {
+        Some(p) => Some(File::create(p).map_err(|e| Error::io(p, e))?),
+        None => None,
+    };
+    let mut logger = LOGGER.lock().map_err(|e| Error::init("logging", e))?;
+    *logger = Some(Logger { filter, file, start: Instant::now() });
+    Ok(())
+}
+
+// module_path!() with the crate name taken off
+fn short_module(module: &str) -> &str {
+    module.split_once("::").map_or(module, |(_, rest)| rest)
+}
+
+pub fn enabled(level: Level, module: &str) -> bool {
+    match LOGGER.lock() {
+        Ok(logger) => match &*logger {
+            Some(l) => level <= l.filter.level_for(short_module(module)),
+            None => level <= Level::Info,
+        },
+        Err(_) => false,
+    }
+}
+
+// Called through the macros below rather than directly
+pub fn write(level: Level, module: &str, message: fmt::Arguments) {
+    if !enabled(level, module) {
+        return;
+    }
+    let module = short_module(module);
+    let message = message.to_string();
+    let mut guard = match LOGGER.lock() {
+        Ok(g) => g,
+        Err(_) => return,
+    };
+    let seconds = guard.as_ref().map_or(0.0, |l| l.start.elapsed().as_secs_f32());
+    eprintln!("[{:9.3} {:5} {}] {}", seconds, level.name().to_uppercase(), module, message);
+    if let Some(file) = guard.as_mut().and_then(|l| l.file.as_mut()) {
+        let line = object(vec![
+            ("t", num(format!("{:.3}", seconds))),
+            ("level", string(level.name())),
+            ("module", string(module)),
+            ("msg", string(&message)),
+        ]);
+        // nowhere left to report a failure to write the log
+        let _ = writeln!(file, "{}", line);
+    }
+}
+
+#[macro_export]
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)+) => {
+        $crate::log::write($level, module_path!(), format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::log_at!($crate::log::Level::Error, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => { $crate::log_at!($crate::log::Level::Warn, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::log_at!($crate::log::Level::Info, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::log_at!($crate::log::Level::Debug, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => { $crate::log_at!($crate::log::Level::Trace, $($arg)+) };
+}
@@ -1,367 +1,904 @@
 extern crate sdl2;
 
+#[macro_use]
+mod log;
+#[macro_use]
+mod profiler;
 mod entity;
+mod bench;
+mod cli;
 mod config;
+mod error;
+mod console;
 mod asset_manager;
-
-use asset_manager::{FontManager, TextureManager};
-
-use rusty_audio::Audio;
+mod capture;
+mod controls;
+mod debug;
+mod display;
+mod events;
+mod gamepad;
+mod ini;
+mod input;
+mod json;
+mod juice;
+mod loading;
+mod lobby;
+mod mods;
+mod net;
+mod pack;
+mod particles;
+mod pause;
+mod render;
+mod rollback;
+mod script;
+mod settings;
+mod sound;
+mod stream;
+mod telemetry;
+mod title;
+mod ui;
+mod validate;
+mod vfs;
+mod world;
+
+use asset_manager::{Fallback, FontDetails, FontManager, TextureManager};
+use capture::Capture;
+use cli::Args;
+use console::Console;
+use controls::ControlsMenu;
+use debug::DebugOverlay;
+use display::{apply_display, Anchor};
+use entity::{Sprites, SPRITES_PATH};
+use error::Error;
+use events::GameEvent;
+use gamepad::Gamepads;
+use input::{assign_pads, Action, Bindings, Input, Source, BINDINGS_PATH};
+use juice::Juice;
+use loading::Manifest;
+use lobby::LobbyMenu;
+use mods::ModsMenu;
+use net::{NetOptions, NetState, Netplay};
+use particles::{EmitterId, ParticleSystem, PARTICLES_PATH};
+use pause::{PauseAction, PauseMenu};
+use profiler::Scope;
+use rollback::TICK_MS;
+use render::{Layer, RenderQueue, Sprite};
+use script::Scripts;
+use settings::{Settings, WindowMode};
+use sound::Sound;
+use stream::{Recorder, Spectator, StreamServer};
+use telemetry::Telemetry;
+use title::{TitleAction, TitleMenu};
+use ui::{Align, Sheet, Theme, Ui, PADDING, SPACING, UI_THEME_PATH};
+use vfs::Vfs;
+use world::{Player, World};
 
 use sdl2::image::InitFlag;
-use sdl2::mouse::MouseButton;
-use sdl2::render::{Texture, TextureCreator, WindowCanvas};
+use sdl2::render::{BlendMode, Texture};
+use sdl2::video::WindowContext;
 use sdl2::pixels::Color;
-use sdl2::event::Event;
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
-use sdl2::rect::{Point, Rect};
+use sdl2::rect::Rect;
 
-use glam::Vec2;
+use std::rc::Rc;
+use std::time::Duration;
 
 use config::*;
-use entity::*;
-use entity::EntityType;
-use sdl2::ttf::Font;
-use sdl2::video::WindowContext;
 
-fn draw_background(canvas: &mut WindowCanvas, texture: &Texture) -> Result <(), String> {
+fn draw_background<'a>(queue: &mut RenderQueue<'a>, texture: &'a Texture<'a>) {
     for x in (0..=SCREEN_WIDTH).step_by(127) {
         for y in (0..=SCREEN_HEIGHT).step_by(255) {
-            canvas.copy_ex(&texture, 
-                Rect::new(0,0,127,255), 
-                Rect::new(x as i32,y as i32,127,255),
-                0.0,
-                None,
-                false,
-                false
-            )?; 
+            queue.submit(Sprite::new(Layer::Background,
+                texture,
+                Rect::new(0,0,127,255),
+                Rect::new(x as i32,y as i32,127,255)
+            ));
         }
     }
-    Ok(())
 }
 
-fn draw_string(str: String, x: i32, y: i32, canvas: &mut WindowCanvas, font: &Font, texture_creator: &TextureCreator<WindowContext>) -> Result <(), String> {
-    let surface = font.render(str.as_str()).solid(Color::RGBA(255, 255, 255, 255)).map_err(|e| e.to_string()).map_err(|e| e.to_string())?;
-    let font_texture = texture_creator.create_texture_from_surface(&surface).map_err(|e| e.to_string()).map_err(|e| e.to_string())?;
-    let size = font.size_of(&str.as_str()).unwrap();
-    let target = Rect::new(x,y, size.0, size.1);
-    canvas.copy(&font_texture, None, Some(target))?;
-    Ok(())
+// The sprite sheets the world is drawn from, and where things are on them
+struct WorldSheets<'a> {
+    background: Rc<Texture<'a>>,
+    ships: Rc<Texture<'a>>,
+    projectiles: Rc<Texture<'a>>,
+    // particles, powerups and UI icons
+    misc: Rc<Texture<'a>>,
+    sprites: Sprites,
 }
 
-pub fn main() -> Result<(), String> {
+impl<'a> WorldSheets<'a> {
+    fn load(textures: &mut TextureManager<'a, WindowContext>, vfs: &Vfs) -> Result<Self, Error> {
+        Ok(WorldSheets {
+            background: textures.load(BACKGROUND_SHEET)?,
+            ships: textures.load(SHIP_SHEET)?,
+            projectiles: textures.load(PROJECTILE_SHEET)?,
+            misc: textures.load(MISC_SHEET)?,
+            sprites: Sprites::load(vfs, SPRITES_PATH)?,
+        })
+    }
+}
+
+// Everything in the game world, without the HUD; also drawn by --bench
+fn draw_world<'a>(queue: &mut RenderQueue<'a>, sheets: &'a WorldSheets<'a>, world: &World, particles: &ParticleSystem) {
+    draw_background(queue, &sheets.background);
+
+    for e in &world.enemies {
+        e.draw(queue, Layer::Enemies, &sheets.ships, &sheets.sprites);
+    }
+
+    for e in &world.bullets {
+        e.draw(queue, Layer::Bullets, &sheets.projectiles, &sheets.sprites);
+    }
+
+    for player in &world.players {
+        if player.alive() {
+            player.ship.draw(queue, Layer::Player, &sheets.ships, &sheets.sprites);
+        }
+        else if !world.game_over() {
+            // downed but can still be revived: a faded ship marks the spot
+            let mut ghost = player.ship.sprite(Layer::Player, &sheets.ships, &sheets.sprites);
+            ghost.tint = Color::RGBA(255, 255, 255, 90);
+            ghost.blend = BlendMode::Blend;
+            queue.submit(ghost);
+        }
+    }
+
+    particles.draw(queue, &sheets.misc);
+
+    for e in &world.powerups {
+        e.draw(queue, Layer::Powerups, &sheets.misc, &sheets.sprites);
+    }
+}
+
+// Score, lives and shields for one player, each in their own corner
+fn player_hud(ui: &mut Ui, player: &Player, index: usize, count: usize) {
+    let anchor = [Anchor::TopLeft, Anchor::TopRight, Anchor::BottomLeft, Anchor::BottomRight][index % 4];
+    let label = if count == 1 { format!("SCORE: {}", player.score) } else { format!("P{}: {}", index + 1, player.score) };
+    let (life, shield) = (player.ship.life.max(0) as u32, player.ship.shield.max(0) as u32);
+
+    // sized to fit so the bottom corners sit flush with the screen edge
+    let block = |n: u32| if n == 0 { 0 } else { SPACING as u32 + (n + 7) / 8 * 20 };
+    let height = ui.text_size(&label).1 + block(life) + block(shield) + PADDING as u32 * 2;
+    ui.begin_area(anchor, 0, 0, 200, height, false);
+    if index % 2 == 1 {
+        ui.align(Align::End);
+    }
+    ui.label(&label);
+    ui.icons(Sheet::Icons, Rect::new(0, 32, 8, 8), life, 20, 8);
+    ui.icons(Sheet::Icons, Rect::new(0, 24, 8, 8), shield, 20, 8);
+    ui.end();
+
+    if player.revive > 0 {
+        // revive progress bar over the downed ship
+        let rect = player.ship.get_rect();
+        ui.bar(Rect::new(rect.x(), rect.y() - 12, rect.width(), 8), player.revive as f32 / REVIVE_TIME as f32);
+    }
+}
+
+// one engine trail per ship, replacing any left over from an earlier world
+fn add_engine_trails(particles: &mut ParticleSystem, world: &World, old: Vec<Option<EmitterId>>) -> Vec<Option<EmitterId>> {
+    for trail in old.into_iter().flatten() {
+        particles.remove_emitter(trail);
+    }
+    world.players
+        .iter()
+        .map(|p| particles.add_emitter("engine_trail", p.ship.trans.center()))
+        .collect()
+}
+
+// A frame that fails to draw is lost rather than the game; each new kind
+// of failure is logged once instead of every frame
+fn report(result: Result<(), Error>, last: &mut String) {
+    if let Err(e) = result {
+        let message = e.to_string();
+        if *last != message {
+            error!("{}", message);
+            *last = message;
+        }
+    }
+}
+
+// a fresh local game, announced to anyone watching the stream and written
+// to the replay; from `seed` if one was given
+fn new_world(settings: &Settings, scripts: &Rc<Scripts>, tuning: Tuning, seed: Option<u64>, stream: &mut Option<StreamServer>, recorder: &mut Option<Recorder>) -> World {
+    let seed = seed.unwrap_or_else(rand::random);
+    let mut world = World::new(settings.coop.players, seed);
+    world.friendly_fire = settings.coop.friendly_fire;
+    world.scripts = Some(scripts.clone());
+    world.tuning = tuning;
+    if let Some(s) = stream {
+        s.new_world(seed, &world);
+    }
+    if let Some(r) = recorder {
+        r.new_world(seed, &world);
+    }
+    world
+}
+
+pub fn main() -> Result<(), Error> {
+    let args = Args::parse(std::env::args().skip(1)).map_err(|e| Error::config("command line", e))?;
+    if args.help {
+        print!("{}", cli::USAGE);
+        return Ok(());
+    }
+    log::init(args.log.as_deref(), args.log_file.as_deref())?;
+    // the last few seconds are saved as a trace on the way out
+    profiler::set_enabled(args.profile.is_some());
+    let headless = args.headless;
+    if headless {
+        // no display or audio device on a build server; render in software
+        sdl2::hint::set("SDL_VIDEODRIVER", "dummy");
+    }
+
+    let mut settings = Settings::load(&args.config);
+    let root = args.assets.clone().unwrap_or_else(vfs::find_root);
+    let vfs = Vfs::with_mods(&root, |id| settings.mod_enabled(id));
+    if args.validate_assets {
+        return validate::run(&vfs);
+    }
+    let mut tuning = vfs.tuning();
+    args.difficulty.apply(&mut tuning);
+    if let Some(options) = &args.bench {
+        let seed = args.seed.unwrap_or(bench::SEED);
+        let scripts = Rc::new(Scripts::new(vfs.clone()));
+        let world = new_world(&settings, &scripts, tuning, Some(seed), &mut None, &mut None);
+        return bench::run(world, seed, options, &vfs);
+    }
+
     //SDL Init stuff
-    let sdl_context = sdl2::init()?;
-    let video_subsystem = sdl_context.video()?;
-    let _image_context = sdl2::image::init(InitFlag::PNG | InitFlag::JPG)?;
-    let window = video_subsystem.window("rust-sdl2 demo", SCREEN_WIDTH, SCREEN_HEIGHT)
-        .position_centered()
+    let sdl_context = sdl2::init().map_err(|e| Error::init("SDL", e))?;
+    let video_subsystem = sdl_context.video().map_err(|e| Error::init("video", e))?;
+    let _image_context = sdl2::image::init(InitFlag::PNG | InitFlag::JPG).map_err(|e| Error::init("SDL_image", e))?;
+    let mut display = settings.display.clone();
+    // for this run only; what's saved is what the settings menu changes
+    if let Some(mode) = args.window_mode {
+        display.mode = mode;
+    }
+    if let Some((width, height)) = args.resolution {
+        display.width = width;
+        display.height = height;
+    }
+    if headless {
+        display.mode = WindowMode::Windowed;
+        display.width = SCREEN_WIDTH;
+        display.height = SCREEN_HEIGHT;
+    }
+    let mut window_builder = video_subsystem.window("rust-sdl2 demo", display.width, display.height);
+    window_builder.position_centered().resizable().allow_highdpi();
+    if headless {
+        window_builder.hidden();
+    }
+    let window = window_builder
         .build()
-        .expect("failed to build window");
-    let mut canvas = window.into_canvas()
+        .map_err(|e| Error::init("the window", e))?;
+    let mut canvas_builder = window.into_canvas();
+    if headless {
+        canvas_builder = canvas_builder.software();
+    }
+    let mut canvas = canvas_builder
         .build()
-        .expect("failed to build window's canvas");
-    let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string())?;
+        .map_err(|e| Error::init("the renderer", e))?;
+    apply_display(&mut canvas, &display)?;
+    let ttf_context = sdl2::ttf::init().map_err(|e| Error::init("SDL_ttf", e))?;
 
     let texture_creator = canvas.texture_creator();
 
+    // missing assets get stand-ins while developing, but a release build,
+    // or anything checking one, should hear about them
+    let fallback = if args.strict_assets || !cfg!(debug_assertions) { Fallback::Strict } else { Fallback::Placeholder };
+    let mut texture_manager = TextureManager::new(&texture_creator, &vfs);
+    texture_manager.budget = Some(TEXTURE_CACHE_BYTES);
+    texture_manager.fallback = fallback;
+    let mut font_manager = FontManager::new(&ttf_context, &vfs);
+    font_manager.fallback = fallback;
+
     // Load a font
-    let font: sdl2::ttf::Font<'_, '_> = ttf_context.load_font("assets/fonts/Pono_188.ttf", 24)?;
+    let font = font_manager.load(&FontDetails { path: FONT_PATH.to_string(), size: FONT_SIZE })?;
     //font.set_style(sdl2::ttf::FontStyle::BOLD);
 
-    let mut score = 0;
-
-    let mut event_pump = sdl_context.event_pump()?;
-
-    let mut texture_manager = TextureManager::new(&texture_creator);
-    let mut _font_manager = FontManager::new(&ttf_context);
-    
-
-    let bg_texture = texture_manager.load("assets/SpaceShooterAssetPack_BackGrounds.png")?;
-    let texture = texture_manager.load("assets/SpaceShooterAssetPack_Ships.png")?;
-    let bullets_texture = texture_manager.load("assets/SpaceShooterAssetPack_Projectiles.png")?;
-    let particle_texture = texture_manager.load("assets/SpaceShooterAssetPack_Miscellaneous.png")?;
+    let mut event_pump = sdl_context.event_pump().map_err(|e| Error::init("events", e))?;
+    // no controller support is not a reason to refuse to start
+    let mut gamepads = match sdl_context.game_controller() {
+        Ok(subsystem) => Some(Gamepads::new(subsystem)),
+        Err(e) => {
+            warn!("controllers unavailable: {}", e);
+            None
+        }
+    };
 
-    let mut audio = Audio::new();
-    audio.add("shoot", "assets/sfx/LASERSHOOT.wav"); // Load the sound, give it a name
-    audio.add("explode", "assets/sfx/EXPLOSION.wav"); // Load the sound, give it a name
-    audio.add("powerup_spawn", "assets/sfx/POWERUP.wav"); // Load the sound, give it a name
-    audio.add("powerup_collect", "assets/sfx/POWER_UP3.wav"); // Load the sound, give it a name
+    // just enough to draw the loading screen with
+    let theme = Theme::load(&vfs, UI_THEME_PATH).map_err(|e| e.context("loading the UI theme"))?;
+    let ui_texture = texture_manager.load(theme.sheet.as_str())?;
+    let mut ui = Ui::new(&font, theme);
 
-    let mut player = Entity::new(EntityType::Player);
-    player.trans.scale = Vec2::new(24.0,24.0); //size = 24;
-    player.trans.pos = Vec2::new(400.0,300.0);
-    //player.tex_coords = Rect::new(8,0, 8,8);
-    player.anim = Animation::construct(1, 0, 1, Point::new(8,8), 1, true);
+    let scene = args.scene.as_deref().unwrap_or("game");
+    let manifest = Manifest::load(&vfs, scene).map_err(|e| e.context(format!("loading scene {}", scene)))?;
+    manifest.request(&mut texture_manager, &mut font_manager);
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    // the last drawing failure logged
+    let mut render_error = String::new();
+    loop {
+        let progress = manifest.progress(&texture_manager, &font_manager);
+        // the rest were swapped for placeholders
+        if !progress.failed.is_empty() && fallback == Fallback::Strict {
+            return Err(Error::Other(progress.failed.join("\n")));
+        }
+        if progress.finished() {
+            break;
+        }
+        for event in event_pump.poll_iter() {
+            if let Event::Quit { .. } = event {
+                return Ok(());
+            }
+        }
+        texture_manager.poll(Duration::from_millis(loading::UPLOAD_BUDGET_MS));
+        font_manager.poll(Duration::from_millis(loading::UPLOAD_BUDGET_MS));
+        ui.begin_frame();
+        loading::build(&mut ui, &manifest.scene, &progress);
+        canvas.clear();
+        report(ui.render(&mut canvas, &texture_creator, &ui_texture, &ui_texture), &mut render_error);
+        canvas.present();
+    }
 
-    let mut enemies: Vec<Entity> = Vec::new();
-    let mut bullets: Vec<Entity> = Vec::new();
-    let mut particles: Vec<Entity> = Vec::new();
-    let mut powerups: Vec<Entity> = Vec::new();
+    let sheets = WorldSheets::load(&mut texture_manager, &vfs)?;
 
-    enemies.push(spawn_enemy());
-    enemies.push(spawn_enemy());
-    enemies.push(spawn_enemy());
+    let audio_subsystem = if headless { None } else { sdl_context.audio().map_err(|e| warn!("no sound: {}", e)).ok() };
+    let mut audio = Sound::new(audio_subsystem);
+    audio.muted = args.mute || !settings.audio.sound_effects;
+    audio.set_volume(settings.audio.volume);
+    for (name, path) in sound::CLIPS {
+        audio.load(&vfs, name, path, fallback)?; // Load the sound, give it a name
+    }
 
-    let timer = sdl_context.timer()?;
-    let mut ticks = timer.ticks();
-    let mut time_elapsed: u32 = 0;
-    let mut time_powerup: u32 = 0;
-    let mut delta_time;
+    let scripts = Rc::new(Scripts::new(vfs.clone()));
+    let mut script_timer = 0;
+    let mut recorder = match &args.record {
+        Some(path) => Some(Recorder::create(path)?),
+        None => None,
+    };
+    // the stream isn't up yet and is told about this world once it is
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let mut world = new_world(&settings, &scripts, tuning, Some(seed), &mut None, &mut recorder);
+    let player_count = world.players.len();
+    let mut inputs: Vec<Input> = Vec::new();
+    for i in 0..player_count {
+        let mut input = Input::new(Bindings::load(BINDINGS_PATH));
+        // controllers are handed out below as they connect
+        input.set_source(match (i, player_count) {
+            (0, 1) => Source::Any,
+            (0, _) => Source::KeyboardMouse,
+            _ => Source::Nothing,
+        });
+        inputs.push(input);
+    }
+    let mut controls_menu = ControlsMenu::new();
+    let mut lobby = LobbyMenu::new();
+    let mut mods_menu = ModsMenu::new();
+    let mut pause_menu = PauseMenu::new();
+    let mut title = TitleMenu::new();
+    let mut console = Console::new();
+    let mut debug = DebugOverlay::new();
+    // set from the console to run the local game slower or faster
+    let mut time_scale = 1.0f32;
+    // the part of a millisecond a scaled step left over, for the next frame
+    let mut step_remainder = 0.0f32;
+    // straight into the game when started for a capture, a network session,
+    // a replay or a particular level
+    title.open = args.capture.is_none() && args.net_role.is_none() && args.spectate.is_none() && args.replay.is_none() && args.scene.is_none();
+    let (net_delay, link, friendly_fire) = (args.net_delay, args.link, settings.coop.friendly_fire);
+    let net_options = move |role| NetOptions {
+        role,
+        delay: net_delay,
+        friendly_fire,
+        link,
+    };
+    let mut netplay = match args.net_role.clone() {
+        Some(role) => Some(Netplay::start(net_options(role))?),
+        None => None,
+    };
+    let mut net_error = String::new();
+    let mut stream = match args.stream_port {
+        Some(port) => Some(StreamServer::start(port)?),
+        None => None,
+    };
+    if let Some(s) = &mut stream {
+        s.new_world(seed, &world);
+    }
+    let mut telemetry = match args.telemetry {
+        Some(format) => Some(Telemetry::create(format)?),
+        None => None,
+    };
+    if let Some(t) = &telemetry {
+        info!("recording telemetry to {}", t.path);
+    }
+    let mut spectator = match (&args.spectate, &args.replay) {
+        (Some(address), _) => Some(Spectator::connect(address)?),
+        (None, Some(path)) => Some(Spectator::replay(path)?),
+        (None, None) => None,
+    };
+
+    let mut particles = ParticleSystem::load(&vfs, PARTICLES_PATH).map_err(|e| e.context("loading particle presets"))?;
+    let mut engine_trails = add_engine_trails(&mut particles, &world, Vec::new());
+    let mut game_events: Vec<GameEvent> = Vec::new();
+    let mut juice = Juice::new(settings.juice.clone());
+    let mut render_queue = RenderQueue::new();
+
+    let timer = sdl_context.timer().map_err(|e| Error::init("timers", e))?;
+    let mut last_ticks = timer.ticks();
+    let mut take_screenshot = false;
+    let mut capture = match args.capture {
+        Some(options) => Some(Capture::start(options)?),
+        None => None,
+    };
 
 
     canvas.set_draw_color(Color::RGB(0, 0, 0));
     'running: loop {
 
-        delta_time = (timer.ticks() as f32 - ticks as f32) / 1000.0;
+        // a capture steps the game, and any replay it plays, at its own rate
+        let frame_ms = match &capture {
+            Some(c) => c.frame_ms(),
+            None => timer.ticks() - last_ticks,
+        };
+        last_ticks = timer.ticks();
+        profiler::begin_frame();
+        debug.record_frame(frame_ms);
+        script_timer += frame_ms;
+        if script_timer >= script::RELOAD_MS {
+            script_timer = 0;
+            scripts.reload();
+        }
+        juice.update(frame_ms as f32 / 1000.0);
+        // anything asked for since loading, e.g. the next scene, streams in
+        // a little each frame
+        texture_manager.poll(Duration::from_millis(loading::UPLOAD_BUDGET_MS));
+        font_manager.poll(Duration::from_millis(loading::UPLOAD_BUDGET_MS));
         // The rest of the game loop goes here...
-        time_elapsed += timer.ticks() - ticks;
-        time_powerup += timer.ticks() - ticks;
-        ticks = timer.ticks();
         
         //println!("dt={}", delta_time);
 
+        for input in &mut inputs {
+            input.begin_frame();
+        }
+        ui.begin_frame();
+        let lobby_was_open = lobby.open;
+        let phase = Scope::new("events");
         for event in event_pump.poll_iter() {
+            if let Some(pads) = &mut gamepads {
+                pads.handle_event(&event);
+            }
             match event {
                 Event::Quit { .. } => break 'running,
 
-                Event::KeyDown { keycode: Some(keycode), .. } => {
-                    if keycode == Keycode::Escape {
-                        break 'running;
+                Event::KeyDown { keycode: Some(Keycode::F3), repeat: false, .. } => {
+                    debug.open = !debug.open;
+                    profiler::set_enabled(debug.open || args.profile.is_some());
+                }
+
+                Event::KeyDown { keycode: Some(Keycode::Backquote), repeat: false, .. } if !controls_menu.waiting => {
+                    console.open = !console.open;
+                    for input in &mut inputs {
+                        input.release_all();
                     }
-                    if keycode == Keycode::R {
-                        if player.life == 0
-                        {
-                            player.life = 5;
-                            player.shield = 3;
-                            player.trans.pos = Vec2::new(400.0, 300.0);
-                            enemies.clear();
-                            powerups.clear();
-                            enemies.push(spawn_enemy());
-                            enemies.push(spawn_enemy());
-                            enemies.push(spawn_enemy());
-                            score = 0;
-                        }
+                }
+
+                Event::KeyDown { keycode: Some(Keycode::F1), repeat: false, .. } if !controls_menu.waiting && !lobby.open => {
+                    controls_menu.open = !controls_menu.open;
+                    for input in &mut inputs {
+                        input.release_all();
+                    }
+                }
+
+                Event::KeyDown { keycode: Some(Keycode::F2), repeat: false, .. } if !controls_menu.open => {
+                    lobby.open = !lobby.open;
+                    for input in &mut inputs {
+                        input.release_all();
                     }
                 }
 
-                Event::MouseButtonDown { x, y, .. } => {               
-                    if player.life > 0 {
-                        let m_pos = Vec2::new(x as f32,y as f32);
-                        bullets.push(spawn_bullet(&player, &m_pos));
-                        audio.play("shoot");
+                Event::Window { win_event: WindowEvent::FocusLost, .. } if capture.is_none() => {
+                    if !title.open && !pause_menu.open {
+                        pause_menu.open();
+                        for input in &mut inputs {
+                            input.release_all();
+                        }
                     }
                 }
 
-                Event::MouseMotion { x, y, .. } => {
-                    player.trans.rotate_to_vec2(Vec2::new(x as f32,y as f32));
+                Event::Window { win_event: WindowEvent::Resized(w, h), .. } => {
+                    if settings.display.mode == WindowMode::Windowed && !headless {
+                        settings.display.width = w as u32;
+                        settings.display.height = h as u32;
+                    }
                 }
 
-                _ => {}
+                _ => {
+                    if console.open {
+                        console.handle_event(&event);
+                    }
+                    else if controls_menu.waiting {
+                        // everyone shares player 1's bindings
+                        controls_menu.capture(&event, &mut inputs[0]);
+                        let bindings = inputs[0].bindings.clone();
+                        for input in &mut inputs[1..] {
+                            input.bindings = bindings.clone();
+                        }
+                    }
+                    else if controls_menu.open || lobby.open || pause_menu.open || title.open {
+                        ui.handle_event(&event);
+                    }
+                    else {
+                        for input in &mut inputs {
+                            input.handle_event(&event);
+                        }
+                    }
+                }
             }
         }
+        drop(phase);
 
-        if event_pump.mouse_state().is_mouse_button_pressed(MouseButton::Left) {
-            let m_pos = Vec2::new(event_pump.mouse_state().x() as f32, event_pump.mouse_state().y() as f32);
-            if ticks % 450 < 4 {
-                bullets.push(spawn_bullet(&player, &m_pos));
-                audio.play("shoot");
-            }
+        // nothing outside the inputs may change a game others are following
+        let shared = netplay.is_some() || spectator.is_some() || stream.is_some() || recorder.is_some();
+        for line in console.take_entered() {
+            console.execute(&line, &mut world, &mut time_scale, shared);
         }
 
-        let keys: Vec<Keycode> = event_pump
-            .keyboard_state()
-            .pressed_scancodes()
-            .filter_map(Keycode::from_scancode)
-            .collect();
-
-        player.update_position(&keys, delta_time);
-
-        if time_elapsed > SPAWN_RATE {
-            enemies.push(spawn_enemy());
-            time_elapsed = 0;
-        }    
-
-        if time_powerup > POWERUP_RATE {
-            powerups.push(spawn_powerup());
-            audio.play("powerup_spawn");
-            time_powerup = 0;
-        }     
-
-        for e in &mut enemies {
-            e.update(delta_time);
-            if player.life > 0 {
-                let dist = e.trans.pos - player.trans.pos;
-                if dist.length_squared() < 200.0 * 200.0 {
-                    e.trans.rotate_to_vec2(player.trans.pos);
-                    if ticks % 800 < 2 {
-                        bullets.push(spawn_enemy_bullet(&e, &player.trans.pos));
-                        audio.play("shoot");
-                    }
-                }
+        if player_count > 1 {
+            if let Some(pads) = &gamepads {
+                assign_pads(&mut inputs, &pads.ids());
             }
         }
-        
-        for e in &mut bullets {
-            e.update(delta_time);
+
+        let mut net_status = netplay.as_ref().map_or(net_error.clone(), |n| n.status());
+        if let Some(watch) = &spectator {
+            let what = if watch.replay { "REPLAY" } else { "STREAM" };
+            net_status = if watch.closed {
+                format!("{} ENDED", what)
+            } else if let Some(tick) = watch.desync {
+                format!("OUT OF STEP WITH THE {} AT {}", what, tick)
+            } else if watch.replay {
+                "REPLAY".to_string()
+            } else {
+                "SPECTATING".to_string()
+            };
         }
 
-        for e in &mut particles {
-            e.update(delta_time);
+        // only the topmost open menu gets built, and with it the UI's input
+        if controls_menu.open {
+            controls_menu.run(&mut ui, &mut inputs[0]);
+            let bindings = inputs[0].bindings.clone();
+            for input in &mut inputs[1..] {
+                input.bindings = bindings.clone();
+            }
         }
-        
-        let p_rect = player.get_rect();
-        for p in &mut powerups {
-            let e_rect = p.get_rect();
-            if p_rect.has_intersection(e_rect) && player.life > 0 {
-                p.life = 0;
-                match p.typ {
-                    EntityType::PowerupHealth => {
-                        player.life += 1;
-                    }
-                    EntityType::PowerupShield => {
-                        player.shield += 1;
+        else if lobby.open {
+            if let Some(role) = lobby.run(&mut ui, &net_status) {
+                if let Some(mut old) = netplay.take() {
+                    old.disconnect();
+                }
+                match Netplay::start(net_options(role)) {
+                    Ok(net) => {
+                        netplay = Some(net);
+                        net_error.clear();
                     }
-                    EntityType::PowerupNuke => {
-                        for e in &mut enemies {
-                            e.life = 0;
-                            particles.push(spawn_particle(e));
-                        }
+                    Err(e) => net_error = e.to_string().to_uppercase(),
+                }
+            }
+        }
+        else if pause_menu.open {
+            match pause_menu.run(&mut ui, &mut settings) {
+                Some(PauseAction::Restart) => {
+                    // nobody gets to restart a shared online game alone. A
+                    // restart isn't an input, so anyone watching or a replay
+                    // is told about it as a new game.
+                    if netplay.is_none() && spectator.is_none() {
+                        world = new_world(&settings, &scripts, tuning, args.seed, &mut stream, &mut recorder);
+                        particles.clear();
                     }
-                    EntityType::PowerupBulletSpeed => { 
-                        for r in (0..=360).step_by(20) {
-                            let mut v = Vec2::new(
-                                p.trans.pos.x() * f32::cos(f32::to_radians(r as f32)),
-                                p.trans.pos.y() * f32::sin(f32::to_radians(r as f32))
-                            );
-                            v *= BULLET_SPEED;
-                            bullets.push(spawn_bullet(p, &v));
-                            audio.play("shoot");
-                        }
+                }
+                Some(PauseAction::Controls) => controls_menu.open = true,
+                Some(PauseAction::QuitToTitle) => {
+                    if let Some(mut net) = netplay.take() {
+                        net.disconnect();
                     }
-                    _ => {}
+                    world = new_world(&settings, &scripts, tuning, args.seed, &mut stream, &mut recorder);
+                    particles.clear();
+                    // whatever only the game needed can go
+                    let evicted = texture_manager.evict_unused() + font_manager.evict_unused();
+                    debug!("evicted {} unused assets", evicted);
+                    title.open = true;
+                }
+                Some(PauseAction::SettingsChanged) => {
+                    juice.settings = settings.juice.clone();
+                    audio.muted = args.mute || !settings.audio.sound_effects;
+                    audio.set_volume(settings.audio.volume);
+                    apply_display(&mut canvas, &settings.display)?;
                 }
-                //player.life += 1;
-                audio.play("powerup_collect");
+                Some(PauseAction::Resume) | None => {}
+            }
+        }
+        else if mods_menu.open {
+            mods_menu.run(&mut ui, &vfs.mods, &mut settings);
+        }
+        else if title.open {
+            match title.run(&mut ui) {
+                Some(TitleAction::Play) => {
+                    world = new_world(&settings, &scripts, tuning, args.seed, &mut stream, &mut recorder);
+                    particles.clear();
+                }
+                Some(TitleAction::Online) => lobby.open = true,
+                Some(TitleAction::Mods) => mods_menu.open = true,
+                Some(TitleAction::Settings) => pause_menu.open_settings(),
+                Some(TitleAction::Quit) => break 'running,
+                None => {}
+            }
+        }
+        if lobby_was_open && !lobby.open {
+            // backing out of the lobby also cancels a pending connection
+            if let Some(net) = &mut netplay {
+                if net.state != NetState::Connected {
+                    net.disconnect();
+                    netplay = None;
+                }
+            }
+        }
+
+        let pressed = |action| inputs.iter().any(|i| i.was_pressed(action));
+        let pause = pressed(Action::Pause) && !title.open;
+        if pressed(Action::Screenshot) {
+            take_screenshot = true;
+        }
+        if pressed(Action::ToggleFullscreen) {
+            settings.display.mode = match settings.display.mode {
+                WindowMode::Windowed => WindowMode::Borderless,
+                WindowMode::Borderless => WindowMode::Windowed,
+            };
+            apply_display(&mut canvas, &settings.display)?;
+        }
+        if pause {
+            pause_menu.open();
+            // the menu gets the key releases, so nothing held stays held
+            for input in &mut inputs {
+                input.release_all();
             }
         }
 
-        for e in &mut enemies {
-            let e_rect = e.get_rect();
-            if p_rect.has_intersection(e_rect) && player.life > 0 {
-                e.life = 0;
-                if player.shield > 0 {
-                    player.shield -= 1;
-                    particles.push(spawn_particle_shield(&player));
+        let phase = Scope::new("simulate");
+        let step_ms = if let Some(watch) = &mut spectator {
+            match watch.update(&mut world, frame_ms, &mut game_events) {
+                // effects keep time with the recording, not the screen
+                Ok(played) if watch.replay => played,
+                Ok(_) => frame_ms,
+                Err(e) => {
+                    error!("{}", e);
+                    break 'running;
                 }
-                else {
-                    player.life -= 1;
+            }
+        }
+        else if let Some(net) = &mut netplay {
+            // online the simulation runs on fixed ticks and can't be
+            // paused or frozen by hit-stop on just one side
+            if net.update(&mut world, &inputs[0].snapshot(), frame_ms, &mut game_events) {
+                lobby.open = false;
+                title.open = false;
+                pause_menu.open = false;
+                if let Some(s) = &mut stream {
+                    s.new_world(net.seed(), &world);
                 }
-                particles.push(spawn_particle(e));
-                if player.life == 0 {
-                    particles.push(spawn_particle(&mut player));
+                if let Some(r) = &mut recorder {
+                    r.new_world(net.seed(), &world);
                 }
-                audio.play("explode");
             }
-            for b in &mut bullets {
-                let b_rect = b.trans.get_rect();
-                // Check if the rectangles collide
-                if b.typ == EntityType::Bullet && b_rect.has_intersection(e_rect)  {
-                    e.life = 0;
-                    b.life = 0;
-                    particles.push(spawn_particle(e));
-                    audio.play("explode");
-                    score += 500;
+            for settled in net.take_settled() {
+                if let Some(s) = &mut stream {
+                    s.step(TICK_MS, &settled);
                 }
+                if let Some(r) = &mut recorder {
+                    r.step(TICK_MS, &settled);
+                }
+            }
+            if net.state == NetState::Connected { frame_ms } else { 0 }
+        }
+        else {
+            let paused = controls_menu.open || lobby.open || pause_menu.open || title.open;
+            let step_ms = if paused {
+                0
+            } else {
+                let scaled = frame_ms as f32 * juice.time_scale() * time_scale + step_remainder;
+                step_remainder = scaled.fract();
+                scaled as u32
+            };
+            let snapshots: Vec<_> = inputs.iter().map(|i| i.snapshot()).collect();
+            world.update(&snapshots, step_ms, &mut game_events);
+            if let Some(s) = &mut stream {
+                s.step(step_ms, &snapshots);
             }
+            if let Some(r) = &mut recorder {
+                r.step(step_ms, &snapshots);
+            }
+            step_ms
+        };
+        drop(phase);
+        let disconnected = matches!(netplay.as_ref().map(|n| &n.state), Some(NetState::Disconnected(_)));
+        if disconnected {
+            // back to a local game
+            net_error = netplay.take().map_or(String::new(), |n| n.status());
+            info!("{}", net_error.to_lowercase());
+            world = new_world(&settings, &scripts, tuning, args.seed, &mut stream, &mut recorder);
+            particles.clear();
+        }
+        if engine_trails.len() != world.players.len() {
+            engine_trails = add_engine_trails(&mut particles, &world, engine_trails);
+        }
+        if let Some(t) = &mut telemetry {
+            t.record(&world, &game_events, frame_ms);
+        }
+        if let Some(s) = &mut stream {
+            s.events(&world, &game_events);
+            s.state(&world);
+            s.flush();
         }
 
-        for b in &mut bullets {
-            let b_rect = b.trans.get_rect(); //Rect::new(b.pos.x() as i32, b.pos.y() as i32, b.size, b.size);
+        for (player, trail) in world.players.iter().zip(&engine_trails) {
+            if let Some(trail) = *trail {
+                // trail out of the back of the ship, opposite to where it faces
+                let rot = player.ship.trans.rot.to_radians() as f32;
+                let back = f32::atan2(rot.cos(), -rot.sin()).to_degrees();
+                particles.move_emitter(trail, player.ship.trans.center(), Some(back));
+                particles.set_emitter_active(trail, player.alive());
+            }
+        }
+        {
+            profile!("particles");
+            particles.update(step_ms as f32 / 1000.0);
+        }
 
-            if b.typ == EntityType::EnemyBullet && b_rect.has_intersection(p_rect)  {
-                if player.life > 0 {
-                    if player.shield > 0 {
-                        player.shield -= 1;
-                        particles.push(spawn_particle_shield(&player));
-                    }
-                    else {
-                        player.life -= 1;
-                    }
-                    b.life = 0;
-                    if player.life == 0 {
-                        particles.push(spawn_particle(&mut player));
-                        audio.play("explode");
+        // only the pad of the player who got hit shakes; online that is
+        // only ever the local player
+        let rumble_enabled = juice.settings.rumble;
+        let local_player = netplay.as_ref().and_then(|n| n.local_player());
+        let mut rumble = |player: usize, strength: f32, duration_ms: u32| {
+            let index = match local_player {
+                Some(local) if local != player => return,
+                Some(_) => 0,
+                None => player,
+            };
+            let which = match inputs.get(index).map(|i| i.source()) {
+                Some(Source::Any) => None,
+                Some(Source::Pad(id)) => Some(id),
+                _ => return,
+            };
+            if let (true, Some(pads)) = (rumble_enabled, &mut gamepads) {
+                pads.rumble(which, strength, duration_ms);
+            }
+        };
+        for event in game_events.drain(..) {
+            juice.handle(&event);
+            match event {
+                GameEvent::EnemyKilled { pos, .. } => {
+                    particles.burst("explosion", pos);
+                    audio.play("explode");
+                }
+                GameEvent::PlayerKilled { pos, player, .. } => {
+                    particles.burst("explosion", pos);
+                    audio.play("explode");
+                    rumble(player, 1.0, 400);
+                }
+                GameEvent::PlayerHit { pos, shielded, player, .. } => {
+                    if shielded {
+                        particles.burst("shield_hit", pos);
                     }
+                    world.players[player].ship.flash = juice.flash_time();
+                    rumble(player, if shielded { 0.3 } else { 0.7 }, 150);
+                }
+                GameEvent::PlayerRevived { pos, player } => {
+                    particles.burst("pickup_sparkle", pos);
+                    audio.play("powerup_collect");
+                    world.players[player].ship.flash = juice.flash_time();
+                }
+                GameEvent::EnemySpawned { .. } => {}
+                GameEvent::PowerupSpawned { .. } => {
+                    audio.play("powerup_spawn");
+                }
+                GameEvent::PowerupCollected { pos, .. } => {
+                    particles.burst("pickup_sparkle", pos);
+                    audio.play("powerup_collect");
+                }
+                GameEvent::Shot { .. } => {
+                    audio.play("shoot");
+                }
+                GameEvent::Restarted => {
+                    particles.clear();
                 }
             }
         }
-
-        enemies.retain(|e| e.life > 0);
-        bullets.retain(|e| e.life > 0);
-        particles.retain(|e| e.life > 0);
-        powerups.retain(|e| e.life > 0);
        
         // Render
 
+        let phase = Scope::new("render");
+        canvas.set_viewport(None);
         canvas.clear();
-        draw_background(&mut canvas, &bg_texture)?;
-        
-
-        for e in &mut enemies {
-            e.draw(&mut canvas, &texture);
+        let (shake_x, shake_y) = juice.shake_offset();
+        canvas.set_viewport(Rect::new(shake_x, shake_y, SCREEN_WIDTH, SCREEN_HEIGHT));
+        draw_world(&mut render_queue, &sheets, &world, &particles);
+        report(render_queue.flush(&mut canvas), &mut render_error);
+        report(debug.draw_world(&mut canvas, &world), &mut render_error);
+        drop(phase);
+
+        canvas.set_viewport(None);
+        report(juice.draw_vignette(&mut canvas), &mut render_error);
+
+        for (i, player) in world.players.iter().enumerate() {
+            player_hud(&mut ui, player, i, world.players.len());
         }
 
-        for e in &mut bullets {
-            e.draw(&mut canvas, &bullets_texture);
+        if world.game_over() {
+            let restart = inputs[0].bindings.for_action(Action::Restart).first().map_or("-".to_string(), |b| b.label());
+            ui.begin_area(Anchor::Center, 0, 0, 460, 130, true);
+            ui.align(Align::Center);
+            ui.label("GAME OVER");
+            ui.space(20);
+            ui.label(&format!("PRESS '{}' TO RESTART", restart));
+            ui.end();
         }
 
-        if player.life > 0 {
-            player.draw(&mut canvas, &texture);
+        if !lobby.open && !net_status.is_empty() && (netplay.is_some() || spectator.is_some()) {
+            ui.begin_area(Anchor::Top, 0, 0, SCREEN_WIDTH, 60, false);
+            ui.align(Align::Center);
+            ui.label(&net_status);
+            ui.end();
         }
 
-        for e in &mut particles {
-            e.draw(&mut canvas, &particle_texture);
-        }
-
-        for e in &mut powerups {
-            e.draw(&mut canvas, &particle_texture);
-        }
+        let mut textures = texture_manager.cached();
+        textures.sort();
+        debug.build(&mut ui, &world, &textures, &texture_manager.stats(), time_scale);
+        console.build(&mut ui);
 
-        
-        
-        draw_string(format!("SCORE: {}", score), 10, 10, &mut canvas, &font, &texture_creator)?;
+        report(ui.render(&mut canvas, &texture_creator, &ui_texture, &sheets.misc), &mut render_error);
 
-        for i in 0..player.life {
-            canvas.copy_ex(&particle_texture, 
-                Rect::new(0,32,8,8), 
-                Rect::new((((i % 8 )*20) + 10) as i32 ,40 + ((i / 8) * 20),20,20),
-                0.0, 
-                None,
-                false,
-                false
-            )?;
+        // the back buffer is only valid until present()
+        if take_screenshot {
+            match capture::screenshot(&canvas) {
+                Ok(path) => info!("saved screenshot {}", path),
+                Err(e) => warn!("screenshot failed: {}", e),
+            }
+            take_screenshot = false;
         }
-
-        for i in 0..player.shield {
-            canvas.copy_ex(&particle_texture, 
-                Rect::new(0,24,8,8), 
-                Rect::new((((i % 8 )*20) + 10) as i32 ,80 + ((i / 8) * 20),20,20),
-                0.0, 
-                None,
-                false,
-                false
-            )?;
+        if let Some(c) = &mut capture {
+            c.record(&canvas)?;
+            // replay steps were played against c.frame_ms(), so the capture
+            // runs at the recording's speed and stops where it does
+            let replayed = spectator.as_ref().is_some_and(|watch| watch.replay && watch.closed);
+            if c.is_done() || replayed {
+                break 'running;
+            }
         }
 
-        if player.life == 0 {
-            draw_string(format!("GAME OVER"), 300, 250, &mut canvas, &font, &texture_creator)?;
-            draw_string(format!("PRESS 'R' TO RESTART"), 180, 350, &mut canvas, &font, &texture_creator)?;
+        {
+            // waits for vsync, so this is mostly idle time
+            profile!("present");
+            canvas.present();
         }
 
-        canvas.present();
-
         //::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
     }
+
+    if let Some(net) = &mut netplay {
+        net.disconnect();
+    }
+
+    if let Some(c) = capture {
+        info!("captured {} frames", c.finish()?);
+    }
+
+    if let Some(path) = &args.profile {
+        let frames = profiler::save_trace(path)?;
+        info!("saved {} frames of profile to {}", frames, path);
+    }
+
+    settings.juice = juice.settings.clone();
+    settings.save(&args.config)?;
     Ok(())
-}
\ No newline at end of file
+}
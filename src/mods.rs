@@ -0,0 +1,65 @@
+//mods module - the list of installed mods, to switch them on and off
+
+use crate::display::Anchor;
+use crate::settings::Settings;
+use crate::ui::{Align, Ui};
+use crate::vfs::{ModInfo, MODS_DIR};
+
+use sdl2::pixels::Color;
+
+pub struct ModsMenu {
+    pub open: bool,
+    // mods are layered once at startup, so changes wait for a restart
+    changed: bool,
+}
+
+impl ModsMenu {
+    pub fn new() -> Self {
+        ModsMenu { open: false, changed: false }
+    }
+
+    pub fn run(&mut self, ui: &mut Ui, mods: &[ModInfo], settings: &mut Settings) {
+        ui.begin_menu("mods");
+        ui.begin_area(Anchor::Top, 0, 10, 700, 530, true);
+        ui.align(Align::Center);
+        ui.label("MODS");
+        ui.align(Align::Start);
+        let dim = ui.theme.text_dim;
+        if mods.is_empty() {
+            ui.label_colored(&format!("NONE INSTALLED IN {}/", MODS_DIR.to_uppercase()), dim);
+        }
+        for info in mods {
+            let mut on = settings.mod_enabled(&info.id);
+            let title = format!("{} {}", info.name, info.version).trim().to_uppercase();
+            if ui.toggle(&title, &mut on) {
+                settings.set_mod_enabled(&info.id, on);
+                self.changed = true;
+            }
+            let mut about = info.description.to_uppercase();
+            if !info.author.is_empty() {
+                about = format!("{}  BY {}", about, info.author.to_uppercase());
+            }
+            ui.label_colored(&about, dim);
+            if !info.conflicts.is_empty() {
+                let warning = format!("{} FILES CLASH WITH OTHER MODS", info.conflicts.len());
+                ui.label_colored(&warning, Color::RGB(255, 160, 40));
+            }
+        }
+        ui.align(Align::Center);
+        if self.changed {
+            ui.label("RESTART TO APPLY CHANGES");
+        }
+        let back = ui.button("BACK");
+        ui.end();
+
+        ui.begin_area(Anchor::Bottom, 0, 0, 600, 50, false);
+        ui.align(Align::Center);
+        ui.label_colored("ENTER TOGGLE  ESC BACK", dim);
+        ui.end();
+        ui.end_menu();
+
+        if back || ui.back() {
+            self.open = false;
+        }
+    }
+}
@@ -0,0 +1,423 @@
+//net module - UDP transport for online co-op: handshake, input exchange,
+//checksums and an optional latency/packet-loss shim for testing
+
+use crate::error::Error;
+use crate::events::GameEvent;
+use crate::input::PlayerInput;
+use crate::rollback::{Rollback, MAX_DELAY};
+use crate::world::World;
+
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use glam::Vec2;
+use rand::Rng;
+
+pub const NET_PORT: u16 = 7777;
+const PROTOCOL_VERSION: u32 = 1;
+const HELLO_INTERVAL: Duration = Duration::from_millis(250);
+const TIMEOUT: Duration = Duration::from_secs(5);
+// at most this many inputs go into one packet
+const MAX_INPUTS_PER_PACKET: usize = 32;
+const INPUT_SIZE: usize = 9;
+
+pub enum Packet {
+    Hello { version: u32 },
+    Welcome { seed: u64, delay: u32, friendly_fire: bool },
+    // the sender's inputs from first_tick on, plus how many of ours it has
+    Inputs { ack: u32, first_tick: u32, inputs: Vec<PlayerInput> },
+    Checksum { tick: u32, sum: u64 },
+    Bye,
+}
+
+impl Packet {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        match self {
+            Packet::Hello { version } => {
+                out.push(1);
+                out.extend_from_slice(&version.to_le_bytes());
+            }
+            Packet::Welcome { seed, delay, friendly_fire } => {
+                out.push(2);
+                out.extend_from_slice(&seed.to_le_bytes());
+                out.extend_from_slice(&delay.to_le_bytes());
+                out.push(*friendly_fire as u8);
+            }
+            Packet::Inputs { ack, first_tick, inputs } => {
+                out.push(3);
+                out.extend_from_slice(&ack.to_le_bytes());
+                out.extend_from_slice(&first_tick.to_le_bytes());
+                out.push(inputs.len() as u8);
+                for input in inputs {
+                    out.extend_from_slice(&encode_input(input));
+                }
+            }
+            Packet::Checksum { tick, sum } => {
+                out.push(4);
+                out.extend_from_slice(&tick.to_le_bytes());
+                out.extend_from_slice(&sum.to_le_bytes());
+            }
+            Packet::Bye => out.push(5),
+        }
+        out
+    }
+
+    // None for anything truncated or unknown; stray datagrams are ignored
+    pub fn decode(data: &[u8]) -> Option<Packet> {
+        let (kind, body) = data.split_first()?;
+        let u32_at = |at: usize| Some(u32::from_le_bytes(body.get(at..at + 4)?.try_into().ok()?));
+        let u64_at = |at: usize| Some(u64::from_le_bytes(body.get(at..at + 8)?.try_into().ok()?));
+        match kind {
+            1 => Some(Packet::Hello { version: u32_at(0)? }),
+            2 => Some(Packet::Welcome { seed: u64_at(0)?, delay: u32_at(8)?, friendly_fire: *body.get(12)? != 0 }),
+            3 => {
+                let count = *body.get(8)? as usize;
+                let mut inputs = Vec::with_capacity(count);
+                for i in 0..count {
+                    let at = 9 + i * INPUT_SIZE;
+                    inputs.push(decode_input(body.get(at..at + INPUT_SIZE)?));
+                }
+                Some(Packet::Inputs { ack: u32_at(0)?, first_tick: u32_at(4)?, inputs })
+            }
+            4 => Some(Packet::Checksum { tick: u32_at(0)?, sum: u64_at(4)? }),
+            5 => Some(Packet::Bye),
+            _ => None,
+        }
+    }
+}
+
+fn unit_to_i8(v: f32) -> u8 {
+    (v.clamp(-1.0, 1.0) * 127.0).round() as i8 as u8
+}
+
+fn i8_to_unit(b: u8) -> f32 {
+    b as i8 as f32 / 127.0
+}
+
+pub fn encode_input(input: &PlayerInput) -> [u8; INPUT_SIZE] {
+    let mut out = [0u8; INPUT_SIZE];
+    out[0] = input.fire as u8
+        | (input.fire_pressed as u8) << 1
+        | (input.restart as u8) << 2
+        | (input.aim.is_some() as u8) << 3
+        | (input.aim_dir.is_some() as u8) << 4
+        | (input.bomb as u8) << 5;
+    out[1] = unit_to_i8(input.movement.x());
+    out[2] = unit_to_i8(input.movement.y());
+    let aim = input.aim.unwrap_or(Vec2::zero());
+    out[3..5].copy_from_slice(&(aim.x().round() as i16).to_le_bytes());
+    out[5..7].copy_from_slice(&(aim.y().round() as i16).to_le_bytes());
+    let dir = input.aim_dir.unwrap_or(Vec2::zero());
+    out[7] = unit_to_i8(dir.x());
+    out[8] = unit_to_i8(dir.y());
+    out
+}
+
+pub fn decode_input(data: &[u8]) -> PlayerInput {
+    let flags = data[0];
+    let mut input = PlayerInput::new();
+    input.fire = flags & 1 != 0;
+    input.fire_pressed = flags & 2 != 0;
+    input.restart = flags & 4 != 0;
+    input.bomb = flags & 32 != 0;
+    input.movement = Vec2::new(i8_to_unit(data[1]), i8_to_unit(data[2]));
+    if flags & 8 != 0 {
+        let x = i16::from_le_bytes([data[3], data[4]]);
+        let y = i16::from_le_bytes([data[5], data[6]]);
+        input.aim = Some(Vec2::new(x as f32, y as f32));
+    }
+    if flags & 16 != 0 {
+        input.aim_dir = Some(Vec2::new(i8_to_unit(data[7]), i8_to_unit(data[8])));
+    }
+    input
+}
+
+// Local input has to go through the same rounding as what the peer
+// receives, or the two simulations would see different values.
+pub fn quantize(input: &PlayerInput) -> PlayerInput {
+    decode_input(&encode_input(input))
+}
+
+// Fake network conditions on outgoing packets, for testing two instances
+// on one machine.
+#[derive(Debug, Clone, Copy)]
+pub struct LinkConditions {
+    pub latency_ms: u32,
+    pub jitter_ms: u32,
+    // chance of dropping each packet, 0 to 1
+    pub loss: f32,
+}
+
+impl LinkConditions {
+    pub fn new() -> Self {
+        LinkConditions {
+            latency_ms: 0,
+            jitter_ms: 0,
+            loss: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum NetRole {
+    Host(u16),
+    Join(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct NetOptions {
+    pub role: NetRole,
+    // ticks between reading local input and applying it
+    pub delay: u32,
+    pub friendly_fire: bool,
+    pub link: LinkConditions,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetState {
+    // host waiting for someone to join
+    Waiting,
+    // joiner waiting for the host to answer
+    Connecting,
+    Connected,
+    Disconnected(String),
+}
+
+pub struct Netplay {
+    socket: UdpSocket,
+    peer: Option<SocketAddr>,
+    pub state: NetState,
+    options: NetOptions,
+    link: LinkConditions,
+    // outgoing packets held back by the shim, with when to send them
+    delayed: Vec<(Instant, Vec<u8>)>,
+    last_hello: Instant,
+    last_heard: Instant,
+    seed: u64,
+    pub rollback: Option<Rollback>,
+}
+
+impl Netplay {
+    pub fn start(options: NetOptions) -> Result<Self, Error> {
+        let fail = |message: String| Error::init("netplay", message);
+        let (bind, peer) = match &options.role {
+            NetRole::Host(port) => (format!("0.0.0.0:{}", port), None),
+            NetRole::Join(address) => {
+                let with_port = if address.contains(':') { address.clone() } else { format!("{}:{}", address, NET_PORT) };
+                let peer = with_port
+                    .to_socket_addrs()
+                    .map_err(|e| fail(format!("bad address '{}': {}", address, e)))?
+                    .next()
+                    .ok_or_else(|| fail(format!("could not resolve '{}'", address)))?;
+                ("0.0.0.0:0".to_string(), Some(peer))
+            }
+        };
+        let socket = UdpSocket::bind(&bind).map_err(|e| fail(format!("could not open {}: {}", bind, e)))?;
+        socket.set_nonblocking(true).map_err(|e| fail(e.to_string()))?;
+        let now = Instant::now();
+        Ok(Netplay {
+            socket,
+            peer,
+            state: if peer.is_some() { NetState::Connecting } else { NetState::Waiting },
+            link: options.link,
+            options,
+            delayed: Vec::new(),
+            last_hello: now - HELLO_INTERVAL,
+            last_heard: now,
+            seed: 0,
+            rollback: None,
+        })
+    }
+
+    // The seed both sides build their world from, once connected
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn friendly_fire(&self) -> bool {
+        self.options.friendly_fire
+    }
+
+    // inputs for ticks both sides have agreed on, oldest first
+    pub fn take_settled(&mut self) -> Vec<[PlayerInput; 2]> {
+        self.rollback.as_mut().map_or(Vec::new(), |r| r.take_settled())
+    }
+
+    pub fn local_player(&self) -> Option<usize> {
+        self.rollback.as_ref().map(|r| r.local)
+    }
+
+    pub fn status(&self) -> String {
+        match &self.state {
+            NetState::Waiting => match &self.options.role {
+                NetRole::Host(port) => format!("WAITING FOR A PLAYER ON PORT {}", port),
+                NetRole::Join(_) => "WAITING".to_string(),
+            },
+            NetState::Connecting => format!("CONNECTING TO {}", self.peer.map_or("?".to_string(), |p| p.to_string())),
+            NetState::Connected => match &self.rollback {
+                Some(r) if r.desync.is_some() => format!("DESYNC AT TICK {}", r.desync.unwrap_or(0)),
+                _ => String::new(),
+            },
+            NetState::Disconnected(reason) => format!("DISCONNECTED: {}", reason.to_uppercase()),
+        }
+    }
+
+    // Handles the connection and runs as many simulation ticks as real time
+    // allows. Returns true on the frame the game starts, when the caller
+    // should build a fresh world from seed().
+    pub fn update(&mut self, world: &mut World, local: &PlayerInput, frame_ms: u32, events: &mut Vec<GameEvent>) -> bool {
+        let started = self.receive();
+        if self.state == NetState::Connecting && self.last_hello.elapsed() >= HELLO_INTERVAL {
+            self.send(&Packet::Hello { version: PROTOCOL_VERSION });
+            self.last_hello = Instant::now();
+        }
+        if self.state != NetState::Connected {
+            self.pump();
+            return started;
+        }
+        if started {
+            *world = World::new(2, self.seed);
+            world.friendly_fire = self.options.friendly_fire;
+            events.push(GameEvent::Restarted);
+        }
+        if self.last_heard.elapsed() > TIMEOUT {
+            self.state = NetState::Disconnected("timed out".to_string());
+            return started;
+        }
+
+        let rollback = self.rollback.as_mut().unwrap();
+        rollback.update(world, &quantize(local), frame_ms, events);
+
+        let ack = rollback.confirmed();
+        let first_tick = rollback.acked();
+        let inputs: Vec<PlayerInput> = rollback.local_inputs(first_tick).take(MAX_INPUTS_PER_PACKET).map(|(_, i)| *i).collect();
+        let checksums = rollback.take_checksums();
+        // everything not yet acknowledged is resent every frame, so a lost
+        // packet costs nothing but a few milliseconds
+        self.send(&Packet::Inputs { ack, first_tick, inputs });
+        for (tick, sum) in checksums {
+            self.send(&Packet::Checksum { tick, sum });
+        }
+        self.pump();
+        started
+    }
+
+    pub fn disconnect(&mut self) {
+        if self.peer.is_some() {
+            self.send(&Packet::Bye);
+            self.link = LinkConditions::new();
+            self.pump();
+        }
+        self.state = NetState::Disconnected("left the game".to_string());
+    }
+
+    fn receive(&mut self) -> bool {
+        let mut started = false;
+        let mut buf = [0u8; 1500];
+        loop {
+            let (len, from) = match self.socket.recv_from(&mut buf) {
+                Ok(r) => r,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                // e.g. ICMP port unreachable while the host isn't up yet
+                Err(_) => continue,
+            };
+            let packet = match Packet::decode(&buf[..len]) {
+                Some(p) => p,
+                None => continue,
+            };
+            if self.peer.map_or(false, |p| p != from) {
+                continue;
+            }
+            self.last_heard = Instant::now();
+            match packet {
+                Packet::Hello { version } => {
+                    if version != PROTOCOL_VERSION {
+                        warn!("{} tried to join with protocol {}, we have {}", from, version, PROTOCOL_VERSION);
+                        continue;
+                    }
+                    if self.state == NetState::Waiting {
+                        self.peer = Some(from);
+                        self.seed = rand::thread_rng().gen();
+                        self.rollback = Some(Rollback::new(0, self.options.delay));
+                        self.state = NetState::Connected;
+                        started = true;
+                        info!("{} joined", from);
+                    }
+                    // answered every time in case the welcome was lost
+                    self.send(&Packet::Welcome { seed: self.seed, delay: self.options.delay, friendly_fire: self.options.friendly_fire });
+                }
+                Packet::Welcome { seed, delay, friendly_fire } => {
+                    if delay > MAX_DELAY {
+                        warn!("{} asked for an input delay of {} ticks, more than {}", from, delay, MAX_DELAY);
+                        continue;
+                    }
+                    if self.state == NetState::Connecting {
+                        self.seed = seed;
+                        // the host's settings win so both simulations match
+                        self.options.delay = delay;
+                        self.options.friendly_fire = friendly_fire;
+                        self.rollback = Some(Rollback::new(1, delay));
+                        self.state = NetState::Connected;
+                        started = true;
+                        info!("connected to {}", from);
+                    }
+                }
+                Packet::Inputs { ack, first_tick, inputs } => {
+                    if let Some(rollback) = &mut self.rollback {
+                        rollback.ack(ack);
+                        for (i, input) in inputs.into_iter().enumerate() {
+                            match first_tick.checked_add(i as u32) {
+                                Some(tick) => rollback.add_remote(tick, input),
+                                None => break,
+                            }
+                        }
+                    }
+                }
+                Packet::Checksum { tick, sum } => {
+                    if let Some(rollback) = &mut self.rollback {
+                        rollback.add_remote_checksum(tick, sum);
+                    }
+                }
+                Packet::Bye => {
+                    self.state = NetState::Disconnected("the other player left".to_string());
+                }
+            }
+        }
+        started
+    }
+
+    fn send(&mut self, packet: &Packet) {
+        let peer = match self.peer {
+            Some(p) => p,
+            None => return,
+        };
+        let mut rng = rand::thread_rng();
+        if self.link.loss > 0.0 && rng.gen::<f32>() < self.link.loss {
+            return;
+        }
+        let delay = self.link.latency_ms + if self.link.jitter_ms > 0 { rng.gen_range(0..=self.link.jitter_ms) } else { 0 };
+        if delay == 0 {
+            let _ = self.socket.send_to(&packet.encode(), peer);
+        } else {
+            self.delayed.push((Instant::now() + Duration::from_millis(delay as u64), packet.encode()));
+        }
+    }
+
+    // sends whatever the shim has held back long enough
+    fn pump(&mut self) {
+        let peer = match self.peer {
+            Some(p) => p,
+            None => return,
+        };
+        let now = Instant::now();
+        let socket = &self.socket;
+        self.delayed.retain(|(due, data)| {
+            if *due > now {
+                return true;
+            }
+            let _ = socket.send_to(data, peer);
+            false
+        });
+    }
+}
@@ -0,0 +1,304 @@
+//pack module - the asset archive: every file under assets/ in one file with
+//an index up front, each entry optionally deflated and checksummed
+//
+//Layout, all numbers little endian:
+//
+//  "GPAK" u32 version u32 entry count u32 index length
+//  per entry: u16 name length, name, u64 offset, u64 stored size,
+//             u64 size, u8 flags, u64 checksum
+//  then the data, one entry after another
+//
+//Names are paths as the game asks for them, e.g. assets/ui.ini, and the
+//checksum is FNV-1a over the unpacked bytes.
+
+use crate::error::Error;
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+
+pub const PACK_PATH: &str = "assets.pak";
+const MAGIC: &[u8; 4] = b"GPAK";
+const VERSION: u32 = 1;
+const DEFLATED: u8 = 1;
+// not worth inflating on load for less than this saved
+const MIN_SAVING: f32 = 0.1;
+
+#[derive(Debug, Clone)]
+struct Entry {
+    offset: u64,
+    stored: u64,
+    size: u64,
+    deflated: bool,
+    checksum: u64,
+}
+
+pub fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+// Entry names end up as paths under the temp dir when extracted, so each
+// one must stay relative and never step up out of it
+fn safe_name(name: &str) -> bool {
+    !name.is_empty() && Path::new(name).components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+#[derive(Debug, Clone)]
+pub struct Archive {
+    pub path: PathBuf,
+    entries: HashMap<String, Entry>,
+}
+
+// Reads little endian numbers off the front of the index
+struct Cursor<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < n {
+            return Err("index is cut short".to_string());
+        }
+        let (front, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(front)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+impl Archive {
+    // Reads just the index; entries are read when asked for
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let bad = |message: &str| Error::decode(path.display(), message);
+        let mut file = File::open(path).map_err(|e| Error::io(path.display(), e))?;
+        let mut header = [0u8; 16];
+        file.read_exact(&mut header).map_err(|_| bad("not an asset archive"))?;
+        let mut cursor = Cursor { data: &header };
+        if cursor.take(4).map_err(|e| bad(&e))? != MAGIC {
+            return Err(bad("not an asset archive"));
+        }
+        let version = cursor.u32().map_err(|e| bad(&e))?;
+        if version != VERSION {
+            return Err(bad(&format!("archive version {}, this build reads {}", version, VERSION)));
+        }
+        let count = cursor.u32().map_err(|e| bad(&e))?;
+        let index_length = cursor.u32().map_err(|e| bad(&e))? as u64;
+        let length = file.metadata().map_err(|e| Error::io(path.display(), e))?.len();
+        // the index can't be bigger than the file it's in
+        if index_length > length - header.len() as u64 {
+            return Err(bad("index is cut short"));
+        }
+        let mut index = vec![0u8; index_length as usize];
+        file.read_exact(&mut index).map_err(|_| bad("index is cut short"))?;
+        let mut cursor = Cursor { data: &index };
+        let mut entries = HashMap::new();
+        for _ in 0..count {
+            let mut entry = || -> Result<(String, Entry), String> {
+                let name_length = cursor.u16()? as usize;
+                let name = String::from_utf8(cursor.take(name_length)?.to_vec()).map_err(|_| "bad entry name".to_string())?;
+                let entry = Entry {
+                    offset: cursor.u64()?,
+                    stored: cursor.u64()?,
+                    size: cursor.u64()?,
+                    deflated: cursor.take(1)?[0] & DEFLATED != 0,
+                    checksum: cursor.u64()?,
+                };
+                Ok((name, entry))
+            };
+            let (name, entry) = entry().map_err(|e| bad(&e))?;
+            if !safe_name(&name) {
+                return Err(bad(&format!("bad entry name {:?}", name)));
+            }
+            if entry.offset.checked_add(entry.stored).is_none_or(|end| end > length) {
+                return Err(bad(&format!("{} runs past the end of the archive", name)));
+            }
+            entries.insert(name, entry);
+        }
+        Ok(Archive { path: path.to_path_buf(), entries })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.entries.keys()
+    }
+
+    // The unpacked bytes, checked against the checksum they were packed with
+    pub fn read(&self, name: &str) -> Result<Vec<u8>, Error> {
+        let at = format!("{}:{}", self.path.display(), name);
+        let entry = self.entries.get(name).ok_or_else(|| Error::decode(&at, "not in archive"))?;
+        let mut file = File::open(&self.path).map_err(|e| Error::io(self.path.display(), e))?;
+        let mut stored = vec![0u8; entry.stored as usize];
+        file.seek(SeekFrom::Start(entry.offset))
+            .and_then(|_| file.read_exact(&mut stored))
+            .map_err(|e| Error::io(&at, e))?;
+        let data = if entry.deflated {
+            miniz_oxide::inflate::decompress_to_vec(&stored).map_err(|e| Error::decode(&at, format!("can't inflate: {:?}", e.status)))?
+        }
+        else {
+            stored
+        };
+        if data.len() as u64 != entry.size || checksum(&data) != entry.checksum {
+            return Err(Error::decode(&at, "corrupt, checksum doesn't match"));
+        }
+        Ok(data)
+    }
+}
+
+// Every file under dir, relative to root and with forward slashes
+pub fn walk(root: &Path, dir: &Path, out: &mut Vec<String>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path.is_dir() {
+            walk(root, &path, out);
+        }
+        else if let Ok(relative) = path.strip_prefix(root) {
+            out.push(relative.to_string_lossy().replace('\\', "/"));
+        }
+    }
+}
+
+// Packs every file under root/dir into out, naming entries relative to
+// root. Files are deflated when `compress` is set and it's worth it. Returns
+// how many files went in. Only the packer binary calls it.
+#[allow(dead_code)]
+pub fn write(root: &Path, dir: &str, out: &Path, compress: bool) -> Result<usize, Error> {
+    let mut names = Vec::new();
+    walk(root, &root.join(dir), &mut names);
+    if names.is_empty() {
+        return Err(Error::Other(format!("{}: no files to pack", root.join(dir).display())));
+    }
+    // same bytes every time for the same files
+    names.sort();
+
+    let mut blobs = Vec::new();
+    for name in &names {
+        let data = fs::read(root.join(name)).map_err(|e| Error::io(name, e))?;
+        let packed = if compress { miniz_oxide::deflate::compress_to_vec(&data, 9) } else { Vec::new() };
+        let deflated = compress && (packed.len() as f32) < data.len() as f32 * (1.0 - MIN_SAVING);
+        let entry = Entry { offset: 0, stored: 0, size: data.len() as u64, deflated, checksum: checksum(&data) };
+        blobs.push((entry, if deflated { packed } else { data }));
+    }
+
+    let index_length: usize = names.iter().map(|n| 2 + n.len() + 8 * 4 + 1).sum();
+    let mut offset = (MAGIC.len() + 12 + index_length) as u64;
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(names.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&(index_length as u32).to_le_bytes());
+    for (name, (entry, blob)) in names.iter().zip(blobs.iter_mut()) {
+        if name.len() > u16::MAX as usize {
+            return Err(Error::Other(format!("{}: name too long", name)));
+        }
+        entry.offset = offset;
+        entry.stored = blob.len() as u64;
+        offset += entry.stored;
+        bytes.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.extend_from_slice(&entry.offset.to_le_bytes());
+        bytes.extend_from_slice(&entry.stored.to_le_bytes());
+        bytes.extend_from_slice(&entry.size.to_le_bytes());
+        bytes.push(if entry.deflated { DEFLATED } else { 0 });
+        bytes.extend_from_slice(&entry.checksum.to_le_bytes());
+    }
+    for (_, blob) in &blobs {
+        bytes.extend_from_slice(blob);
+    }
+    fs::write(out, bytes).map_err(|e| Error::io(out.display(), e))?;
+    Ok(names.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fresh directory holding assets/ with one file that deflates well
+    // and one that doesn't
+    fn packed(test: &str) -> (PathBuf, PathBuf) {
+        let root = std::env::temp_dir().join(format!("pack-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("assets/sub")).unwrap();
+        fs::write(root.join("assets/text.ini"), "[same]\nline = again\n".repeat(200)).unwrap();
+        let mut seed = 7u32;
+        let noise: Vec<u8> = (0..4096)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (seed >> 24) as u8
+            })
+            .collect();
+        fs::write(root.join("assets/sub/noise.bin"), noise).unwrap();
+        let out = root.join(PACK_PATH);
+        assert_eq!(write(&root, "assets", &out, true).unwrap(), 2);
+        (root, out)
+    }
+
+    fn decode_error(result: Result<impl std::fmt::Debug, Error>) -> String {
+        match result {
+            Err(Error::Decode { message, .. }) => message,
+            other => panic!("expected a decode error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn round_trips_deflated_and_stored_entries() {
+        let (root, out) = packed("round-trip");
+        let archive = Archive::open(&out).unwrap();
+        assert!(archive.entries["assets/text.ini"].deflated);
+        assert!(!archive.entries["assets/sub/noise.bin"].deflated);
+        for name in ["assets/text.ini", "assets/sub/noise.bin"] {
+            assert_eq!(archive.read(name).unwrap(), fs::read(root.join(name)).unwrap());
+        }
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn rejects_a_corrupted_byte() {
+        let (root, out) = packed("corrupt");
+        let archive = Archive::open(&out).unwrap();
+        let entry = &archive.entries["assets/sub/noise.bin"];
+        let mut bytes = fs::read(&out).unwrap();
+        bytes[entry.offset as usize + 100] ^= 0xff;
+        fs::write(&out, bytes).unwrap();
+        assert!(decode_error(archive.read("assets/sub/noise.bin")).contains("checksum"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn rejects_a_name_outside_the_archive() {
+        let (root, out) = packed("escape");
+        let mut bytes = fs::read(&out).unwrap();
+        let at = bytes.windows(15).position(|w| w == b"assets/text.ini").unwrap();
+        bytes[at..at + 15].copy_from_slice(b"../../text.ini_");
+        fs::write(&out, bytes).unwrap();
+        assert!(decode_error(Archive::open(&out)).contains("bad entry name"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn rejects_a_truncated_index() {
+        let (root, out) = packed("truncated");
+        let bytes = fs::read(&out).unwrap();
+        fs::write(&out, &bytes[..MAGIC.len() + 12 + 20]).unwrap();
+        assert!(decode_error(Archive::open(&out)).contains("cut short"));
+        fs::remove_dir_all(root).unwrap();
+    }
+}
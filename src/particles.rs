@@ -0,0 +1,326 @@
+//particles module - pooled particles spawned by data-defined emitters
+
+use crate::error::Error;
+use crate::ini::Section;
+use crate::render::{Layer, RenderQueue, Sprite};
+use crate::vfs::Vfs;
+
+use glam::Vec2;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Texture};
+
+use rand::Rng;
+
+pub const MAX_PARTICLES: usize = 8192;
+pub const PARTICLES_PATH: &str = "assets/particles.ini";
+
+pub struct ParticlePreset {
+    pub name: String,
+    pub frame: (i32, i32),
+    pub frame_size: i32,
+    pub frames: i32,
+    pub count: u32,
+    pub rate: f32,
+    pub speed: (f32, f32),
+    pub direction: f32,
+    pub spread: f32,
+    pub lifetime: (f32, f32),
+    pub gravity: Vec2,
+    pub drag: f32,
+    pub spin: (f32, f32),
+    pub scale_start: f32,
+    pub scale_end: f32,
+    pub color_start: [f32; 4],
+    pub color_end: [f32; 4],
+    pub blend: BlendMode,
+    pub layer: Layer,
+}
+
+impl ParticlePreset {
+    // `path` is the file the section came from, for errors
+    pub fn from_section(section: &Section, path: &str) -> Result<Self, Error> {
+        ParticlePreset::parse(section).map_err(|e| Error::config(path, e))
+    }
+
+    fn parse(section: &Section) -> Result<Self, String> {
+        let err = |key: &str| format!("[{}] {}: invalid value", section.name, key);
+        let floats = |key: &str, default: &[f32]| -> Result<Vec<f32>, String> {
+            match section.get(key) {
+                Some(v) => v
+                    .split(',')
+                    .map(|n| n.trim().parse::<f32>().ok().filter(|n| n.is_finite()).ok_or_else(|| err(key)))
+                    .collect(),
+                None => Ok(default.to_vec()),
+            }
+        };
+        let range = |key: &str, default: f32| -> Result<(f32, f32), String> {
+            match floats(key, &[default])?[..] {
+                [v] => Ok((v, v)),
+                [min, max] => Ok((min, max)),
+                _ => Err(err(key)),
+            }
+        };
+        let color = |key: &str| -> Result<[f32; 4], String> {
+            match floats(key, &[255.0, 255.0, 255.0, 255.0])?[..] {
+                [r, g, b, a] => Ok([r, g, b, a]),
+                _ => Err(err(key)),
+            }
+        };
+        let frame = range("frame", 0.0)?;
+        let gravity = range("gravity", 0.0)?;
+        let blend = match section.get("blend").unwrap_or("blend") {
+            "blend" => BlendMode::Blend,
+            "add" => BlendMode::Add,
+            _ => return Err(err("blend")),
+        };
+        let layer = Layer::from_name(section.get("layer").unwrap_or("effects")).ok_or_else(|| err("layer"))?;
+
+        let preset = ParticlePreset {
+            name: section.name.clone(),
+            frame: (frame.0 as i32, frame.1 as i32),
+            frame_size: section.parse_or("frame_size", 8)?,
+            frames: section.parse_or("frames", 1)?,
+            count: section.parse_or("count", 0)?,
+            rate: section.parse_or("rate", 0.0)?,
+            speed: range("speed", 0.0)?,
+            direction: section.parse_or("direction", 0.0)?,
+            spread: section.parse_or("spread", 180.0)?,
+            lifetime: range("lifetime", 1.0)?,
+            gravity: Vec2::new(gravity.0, gravity.1),
+            drag: section.parse_or("drag", 0.0)?,
+            spin: range("spin", 0.0)?,
+            scale_start: section.parse_or("scale_start", 8.0)?,
+            scale_end: section.parse_or("scale_end", 8.0)?,
+            color_start: color("color_start")?,
+            color_end: color("color_end")?,
+            blend,
+            layer,
+        };
+        // emit() draws from these ranges and draw() divides by frames
+        let bad = |key: &str, why: &str| Err(format!("[{}] {}: {}", section.name, key, why));
+        for (key, (min, max)) in [("speed", preset.speed), ("spin", preset.spin), ("lifetime", preset.lifetime)] {
+            if min > max {
+                return bad(key, "min is more than max");
+            }
+        }
+        for (key, value) in [("rate", preset.rate), ("direction", preset.direction), ("spread", preset.spread), ("drag", preset.drag)] {
+            if !value.is_finite() {
+                return bad(key, "must be a number");
+            }
+        }
+        for (key, value) in [("spread", preset.spread), ("rate", preset.rate), ("drag", preset.drag)] {
+            if value < 0.0 {
+                return bad(key, "can't be negative");
+            }
+        }
+        if preset.frames < 1 {
+            return bad("frames", "must be at least 1");
+        }
+        if preset.frame_size < 1 {
+            return bad("frame_size", "must be at least 1");
+        }
+        Ok(preset)
+    }
+
+    // Where `frame` of the sequence is on the sheet
+    pub fn frame_rect(&self, frame: i32) -> Rect {
+        let fs = self.frame_size;
+        Rect::new((self.frame.0 + frame) * fs, self.frame.1 * fs, fs as u32, fs as u32)
+    }
+}
+
+struct Particle {
+    pos: Vec2,
+    vel: Vec2,
+    rot: f32,
+    spin: f32,
+    age: f32,
+    lifetime: f32,
+    preset: usize,
+}
+
+struct Emitter {
+    preset: usize,
+    pos: Vec2,
+    direction: Option<f32>,
+    accumulator: f32,
+    active: bool,
+}
+
+pub type EmitterId = usize;
+
+pub struct ParticleSystem {
+    presets: Vec<ParticlePreset>,
+    particles: Vec<Particle>,
+    emitters: Vec<Option<Emitter>>,
+}
+
+impl ParticleSystem {
+    pub fn new(presets: Vec<ParticlePreset>) -> Self {
+        ParticleSystem {
+            presets,
+            particles: Vec::with_capacity(MAX_PARTICLES),
+            emitters: Vec::new(),
+        }
+    }
+
+    pub fn load(vfs: &Vfs, path: &str) -> Result<Self, Error> {
+        let ini = vfs.ini(path)?;
+        let resolved = vfs.resolve(path);
+        let presets = ini
+            .sections
+            .iter()
+            .map(|section| ParticlePreset::from_section(section, &resolved))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ParticleSystem::new(presets))
+    }
+
+    pub fn len(&self) -> usize {
+        self.particles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.particles.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.clear();
+    }
+
+    pub fn burst(&mut self, name: &str, pos: Vec2) {
+        if let Some(preset) = self.find_preset(name) {
+            for _ in 0..self.presets[preset].count {
+                self.emit(preset, pos, None);
+            }
+        }
+    }
+
+    pub fn add_emitter(&mut self, name: &str, pos: Vec2) -> Option<EmitterId> {
+        let preset = self.find_preset(name)?;
+        let emitter = Emitter {
+            preset,
+            pos,
+            direction: None,
+            accumulator: 0.0,
+            active: true,
+        };
+        match self.emitters.iter().position(|e| e.is_none()) {
+            Some(id) => {
+                self.emitters[id] = Some(emitter);
+                Some(id)
+            }
+            None => {
+                self.emitters.push(Some(emitter));
+                Some(self.emitters.len() - 1)
+            }
+        }
+    }
+
+    // direction overrides the preset's direction, e.g. to trail behind a ship
+    pub fn move_emitter(&mut self, id: EmitterId, pos: Vec2, direction: Option<f32>) {
+        if let Some(Some(e)) = self.emitters.get_mut(id) {
+            e.pos = pos;
+            e.direction = direction;
+        }
+    }
+
+    pub fn set_emitter_active(&mut self, id: EmitterId, active: bool) {
+        if let Some(Some(e)) = self.emitters.get_mut(id) {
+            e.active = active;
+        }
+    }
+
+    pub fn remove_emitter(&mut self, id: EmitterId) {
+        if let Some(e) = self.emitters.get_mut(id) {
+            *e = None;
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        for i in 0..self.emitters.len() {
+            let (preset, pos, direction, count) = match &mut self.emitters[i] {
+                Some(e) if e.active => {
+                    e.accumulator += self.presets[e.preset].rate * dt;
+                    let count = e.accumulator as u32;
+                    e.accumulator -= count as f32;
+                    (e.preset, e.pos, e.direction, count)
+                }
+                _ => continue,
+            };
+            for _ in 0..count {
+                self.emit(preset, pos, direction);
+            }
+        }
+
+        let mut i = 0;
+        while i < self.particles.len() {
+            let p = &mut self.particles[i];
+            p.age += dt;
+            if p.age >= p.lifetime {
+                self.particles.swap_remove(i);
+                continue;
+            }
+            let preset = &self.presets[p.preset];
+            p.vel += preset.gravity * dt;
+            p.vel *= (1.0 - preset.drag * dt).max(0.0);
+            p.pos += p.vel * dt;
+            p.rot += p.spin * dt;
+            i += 1;
+        }
+    }
+
+    pub fn draw<'a>(&self, queue: &mut RenderQueue<'a>, texture: &'a Texture<'a>) {
+        for p in &self.particles {
+            let preset = &self.presets[p.preset];
+            let t = p.age / p.lifetime;
+            let frame = ((t * preset.frames as f32) as i32).min(preset.frames - 1);
+            let src = preset.frame_rect(frame);
+            let size = lerp(preset.scale_start, preset.scale_end, t);
+            let dst = Rect::new(
+                (p.pos.x() - size / 2.0) as i32,
+                (p.pos.y() - size / 2.0) as i32,
+                size.max(1.0) as u32,
+                size.max(1.0) as u32,
+            );
+            let c = |n: usize| lerp(preset.color_start[n], preset.color_end[n], t).clamp(0.0, 255.0) as u8;
+            let mut sprite = Sprite::new(preset.layer, texture, src, dst);
+            sprite.rot = p.rot as f64;
+            sprite.tint = Color::RGBA(c(0), c(1), c(2), c(3));
+            sprite.blend = preset.blend;
+            queue.submit(sprite);
+        }
+    }
+
+    fn find_preset(&self, name: &str) -> Option<usize> {
+        let found = self.presets.iter().position(|p| p.name == name);
+        if found.is_none() {
+            warn!("unknown particle preset '{}'", name);
+        }
+        found
+    }
+
+    fn emit(&mut self, preset: usize, pos: Vec2, direction: Option<f32>) {
+        if self.particles.len() >= MAX_PARTICLES {
+            return;
+        }
+        let p = &self.presets[preset];
+        let mut rng = rand::thread_rng();
+        let angle = direction.unwrap_or(p.direction) + rng.gen_range(-p.spread..=p.spread);
+        let speed = rng.gen_range(p.speed.0..=p.speed.1);
+        let angle = angle.to_radians();
+        self.particles.push(Particle {
+            pos,
+            vel: Vec2::new(angle.cos(), angle.sin()) * speed,
+            rot: rng.gen_range(0.0..360.0),
+            spin: rng.gen_range(p.spin.0..=p.spin.1),
+            age: 0.0,
+            lifetime: rng.gen_range(p.lifetime.0..=p.lifetime.1).max(0.01),
+            preset,
+        });
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
@@ -0,0 +1,147 @@
+//pause module - pause overlay and the settings screen reached from it
+
+use crate::display::Anchor;
+use crate::settings::{Settings, WindowMode};
+use crate::ui::{Align, Nav, Ui};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PauseAction {
+    Resume,
+    Restart,
+    Controls,
+    QuitToTitle,
+    // something on the settings page changed and needs applying
+    SettingsChanged,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Page {
+    Main,
+    Settings,
+}
+
+pub struct PauseMenu {
+    pub open: bool,
+    page: Page,
+    // opened straight into settings from the title screen
+    from_title: bool,
+}
+
+impl PauseMenu {
+    pub fn new() -> Self {
+        PauseMenu {
+            open: false,
+            page: Page::Main,
+            from_title: false,
+        }
+    }
+
+    pub fn open(&mut self) {
+        self.open = true;
+        self.page = Page::Main;
+        self.from_title = false;
+    }
+
+    // for the title screen, which has its own main page
+    pub fn open_settings(&mut self) {
+        self.open = true;
+        self.page = Page::Settings;
+        self.from_title = true;
+    }
+
+    pub fn run(&mut self, ui: &mut Ui, settings: &mut Settings) -> Option<PauseAction> {
+        let action = match self.page {
+            Page::Main => self.main_page(ui),
+            Page::Settings => self.settings_page(ui, settings),
+        };
+        ui.begin_area(Anchor::Bottom, 0, 0, 600, 50, false);
+        ui.align(Align::Center);
+        let hint = ui.theme.text_dim;
+        ui.label_colored("ENTER SELECT  ESC BACK", hint);
+        ui.end();
+        ui.end_menu();
+        action
+    }
+
+    fn main_page(&mut self, ui: &mut Ui) -> Option<PauseAction> {
+        ui.begin_menu("pause");
+        ui.begin_area(Anchor::Center, 0, 0, 320, 230, true);
+        ui.align(Align::Center);
+        ui.label("PAUSED");
+        ui.space(8);
+        let resume = ui.button("RESUME");
+        let restart = ui.button("RESTART");
+        let settings = ui.button("SETTINGS");
+        let quit = ui.button("QUIT TO TITLE");
+        ui.end();
+
+        if resume || ui.back() || ui.pressed(Nav::Start) {
+            return self.back();
+        }
+        if restart {
+            self.open = false;
+            return Some(PauseAction::Restart);
+        }
+        if settings {
+            self.page = Page::Settings;
+        }
+        if quit {
+            self.open = false;
+            return Some(PauseAction::QuitToTitle);
+        }
+        None
+    }
+
+    fn settings_page(&mut self, ui: &mut Ui, settings: &mut Settings) -> Option<PauseAction> {
+        ui.begin_menu("settings");
+        ui.begin_area(Anchor::Top, 0, 10, 560, 530, true);
+        ui.align(Align::Center);
+        ui.label("SETTINGS");
+        let mut changed = ui.toggle("SOUND EFFECTS", &mut settings.audio.sound_effects);
+        let volume = format!("VOLUME {}%", (settings.audio.volume * 100.0).round());
+        changed |= ui.slider(&volume, &mut settings.audio.volume, 0.0, 1.0, 0.1);
+        let mode = match settings.display.mode {
+            WindowMode::Windowed => "WINDOWED",
+            WindowMode::Borderless => "FULLSCREEN",
+        };
+        let window = ui.item("WINDOW", mode);
+        if window.activated || window.step != 0 {
+            settings.display.mode = match settings.display.mode {
+                WindowMode::Windowed => WindowMode::Borderless,
+                WindowMode::Borderless => WindowMode::Windowed,
+            };
+            changed = true;
+        }
+        changed |= ui.toggle("PIXEL PERFECT SCALING", &mut settings.display.integer_scale);
+        let j = &mut settings.juice;
+        changed |= ui.toggle("SCREEN SHAKE", &mut j.shake);
+        let intensity = format!("SHAKE INTENSITY {}%", (j.shake_intensity * 100.0).round());
+        changed |= ui.slider(&intensity, &mut j.shake_intensity, 0.0, 2.0, 0.25);
+        changed |= ui.toggle("HIT FLASH", &mut j.flash);
+        changed |= ui.toggle("HIT STOP", &mut j.hit_stop);
+        changed |= ui.toggle("DAMAGE VIGNETTE", &mut j.vignette);
+        changed |= ui.toggle("CONTROLLER RUMBLE", &mut j.rumble);
+        let controls = ui.button("CONTROLS");
+        let back = ui.button("BACK");
+        ui.end();
+
+        if back || ui.back() || ui.pressed(Nav::Start) {
+            return self.back();
+        }
+        if controls {
+            return Some(PauseAction::Controls);
+        }
+        if changed { Some(PauseAction::SettingsChanged) } else { None }
+    }
+
+    // Escape backs out one page at a time
+    fn back(&mut self) -> Option<PauseAction> {
+        if self.page == Page::Settings && !self.from_title {
+            self.page = Page::Main;
+            None
+        } else {
+            self.open = false;
+            Some(PauseAction::Resume)
+        }
+    }
+}
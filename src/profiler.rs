@@ -0,0 +1,193 @@
+//profiler module - scoped timers around the phases of a frame, kept for the
+//last few seconds to show as a flame bar or save as a Chrome trace
+
+use crate::error::Error;
+use crate::json::{num, object, string, Json};
+
+use std::collections::VecDeque;
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+// open in chrome://tracing or ui.perfetto.dev
+pub const TRACE_PATH: &str = "trace.json";
+// frames kept, a few seconds' worth
+const HISTORY: usize = 300;
+
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub name: &'static str,
+    // 0 for the outermost scopes
+    pub depth: u32,
+    // microseconds from the start of the frame
+    pub start: u32,
+    pub duration: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    // microseconds from when profiling first started
+    pub start: u64,
+    pub duration: u32,
+    // in the order they finished, so inner scopes come before outer ones
+    pub spans: Vec<Span>,
+}
+
+struct State {
+    epoch: Option<Instant>,
+    frame_start: Option<Instant>,
+    depth: u32,
+    spans: Vec<Span>,
+    frames: VecDeque<Frame>,
+}
+
+// checked on its own so a disabled profiler never takes the lock
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static STATE: Mutex<State> = Mutex::new(State {
+    epoch: None,
+    frame_start: None,
+    depth: 0,
+    spans: Vec::new(),
+    frames: VecDeque::new(),
+});
+
+fn micros(from: Instant, to: Instant) -> u64 {
+    to.saturating_duration_since(from).as_micros() as u64
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(on: bool) {
+    ENABLED.store(on, Ordering::Relaxed);
+}
+
+// Ends the frame being recorded and starts the next one
+pub fn begin_frame() {
+    let now = Instant::now();
+    let mut state = match STATE.lock() {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    let epoch = *state.epoch.get_or_insert(now);
+    if let Some(start) = state.frame_start.take() {
+        let frame = Frame {
+            start: micros(epoch, start),
+            duration: micros(start, now) as u32,
+            spans: std::mem::take(&mut state.spans),
+        };
+        if state.frames.len() == HISTORY {
+            state.frames.pop_front();
+        }
+        state.frames.push_back(frame);
+    }
+    state.spans.clear();
+    state.depth = 0;
+    if enabled() {
+        state.frame_start = Some(now);
+    }
+}
+
+// Times from when it's made until it goes out of scope; see profile!
+pub struct Scope {
+    name: &'static str,
+    start: Option<Instant>,
+}
+
+impl Scope {
+    pub fn new(name: &'static str) -> Self {
+        if !enabled() {
+            return Scope { name, start: None };
+        }
+        if let Ok(mut state) = STATE.lock() {
+            state.depth += 1;
+        }
+        Scope { name, start: Some(Instant::now()) }
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let start = match self.start {
+            Some(s) => s,
+            None => return,
+        };
+        let now = Instant::now();
+        if let Ok(mut state) = STATE.lock() {
+            state.depth = state.depth.saturating_sub(1);
+            // scopes still open when profiling was switched on don't count
+            if let Some(frame_start) = state.frame_start {
+                let span = Span {
+                    name: self.name,
+                    depth: state.depth,
+                    start: micros(frame_start, start) as u32,
+                    duration: micros(start, now) as u32,
+                };
+                state.spans.push(span);
+            }
+        }
+    }
+}
+
+pub fn last_frame() -> Option<Frame> {
+    STATE.lock().ok().and_then(|s| s.frames.back().cloned())
+}
+
+// Average milliseconds a frame spends in each outermost scope, in the order
+// they run
+pub fn averages() -> Vec<(&'static str, f32)> {
+    let state = match STATE.lock() {
+        Ok(s) => s,
+        Err(_) => return Vec::new(),
+    };
+    let mut totals: Vec<(&'static str, u64)> = Vec::new();
+    for frame in &state.frames {
+        let mut top: Vec<&Span> = frame.spans.iter().filter(|s| s.depth == 0).collect();
+        top.sort_by_key(|s| s.start);
+        for span in top {
+            match totals.iter_mut().find(|(name, _)| *name == span.name) {
+                Some(t) => t.1 += span.duration as u64,
+                None => totals.push((span.name, span.duration as u64)),
+            }
+        }
+    }
+    let frames = state.frames.len().max(1) as f32;
+    totals.iter().map(|(name, total)| (*name, *total as f32 / 1000.0 / frames)).collect()
+}
+
+// Everything still in the history as Chrome trace events. Returns how many
+// frames went in.
+pub fn save_trace(path: &str) -> Result<usize, Error> {
+    let frames: Vec<Frame> = STATE.lock().map_err(|e| Error::Other(e.to_string()))?.frames.iter().cloned().collect();
+    let event = |name: &str, start: u64, duration: u32| object(vec![
+        ("name", string(name)),
+        ("ph", string("X")),
+        ("ts", num(start)),
+        ("dur", num(duration)),
+        ("pid", num(1)),
+        ("tid", num(1)),
+    ]);
+    let mut events = Vec::new();
+    for frame in &frames {
+        events.push(event("frame", frame.start, frame.duration));
+        for span in &frame.spans {
+            events.push(event(span.name, frame.start + span.start as u64, span.duration));
+        }
+    }
+    let trace = object(vec![
+        ("traceEvents", Json::Array(events)),
+        ("displayTimeUnit", string("ms")),
+    ]);
+    fs::write(path, trace.to_string()).map_err(|e| Error::io(path, e))?;
+    Ok(frames.len())
+}
+
+// Times the rest of the enclosing block under the given name
+#[macro_export]
+macro_rules! profile {
+    ($name:expr) => {
+        let _profile_scope = $crate::profiler::Scope::new($name);
+    };
+}
@@ -0,0 +1,160 @@
+//render module
+
+use crate::error::Error;
+
+use std::cmp::Ordering;
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Texture, WindowCanvas};
+use sdl2::sys;
+
+// Colour, alpha and blend mods are plain SDL state on the texture, so they
+// can be changed through the shared Rc<Texture> handed out by the
+// ResourceManager without needing a mutable borrow.
+pub fn set_texture_mods(texture: &Texture, color: Color, blend: BlendMode) {
+    let blend = match blend {
+        BlendMode::None => sys::SDL_BlendMode::SDL_BLENDMODE_NONE,
+        BlendMode::Add => sys::SDL_BlendMode::SDL_BLENDMODE_ADD,
+        BlendMode::Mod => sys::SDL_BlendMode::SDL_BLENDMODE_MOD,
+        _ => sys::SDL_BlendMode::SDL_BLENDMODE_BLEND,
+    };
+    // The safe setters on Texture take &mut self, which a texture behind an
+    // Rc can't give.
+    // SAFETY: `texture.raw()` is a live SDL_Texture for as long as `texture`
+    // is borrowed. These calls only store three values on it that SDL reads
+    // back when the texture is copied; no Rust reference points into that
+    // state, and all drawing happens on the one thread that owns the canvas.
+    unsafe {
+        sys::SDL_SetTextureColorMod(texture.raw(), color.r, color.g, color.b);
+        sys::SDL_SetTextureAlphaMod(texture.raw(), color.a);
+        sys::SDL_SetTextureBlendMode(texture.raw(), blend);
+    }
+}
+
+pub fn reset_texture_mods(texture: &Texture) {
+    set_texture_mods(texture, Color::RGBA(255, 255, 255, 255), BlendMode::Blend);
+}
+
+// Draw order, back to front. Everything in a layer is drawn before
+// anything in the next one, whatever order it was submitted in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Layer {
+    Background,
+    Powerups,
+    Trails,
+    Enemies,
+    Bullets,
+    Player,
+    Effects,
+    Overlay,
+}
+
+impl Layer {
+    pub fn from_name(name: &str) -> Option<Layer> {
+        match name {
+            "background" => Some(Layer::Background),
+            "powerups" => Some(Layer::Powerups),
+            "trails" => Some(Layer::Trails),
+            "enemies" => Some(Layer::Enemies),
+            "bullets" => Some(Layer::Bullets),
+            "player" => Some(Layer::Player),
+            "effects" => Some(Layer::Effects),
+            "overlay" => Some(Layer::Overlay),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Sprite<'a> {
+    pub layer: Layer,
+    pub z: f32,
+    pub texture: &'a Texture<'a>,
+    pub src: Rect,
+    pub dst: Rect,
+    pub rot: f64,
+    pub tint: Color,
+    pub blend: BlendMode,
+}
+
+impl<'a> Sprite<'a> {
+    pub fn new(layer: Layer, texture: &'a Texture<'a>, src: Rect, dst: Rect) -> Self {
+        Sprite {
+            layer,
+            z: 0.0,
+            texture,
+            src,
+            dst,
+            rot: 0.0,
+            tint: Color::RGBA(255, 255, 255, 255),
+            blend: BlendMode::Blend,
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct RenderStats {
+    pub sprites: usize,
+    pub state_changes: usize,
+}
+
+// Collects a frame's sprites so they can be drawn sorted by layer and z.
+// Within equal z, sprites sharing a texture are drawn back to back so the
+// texture mods only have to be set when they actually change.
+pub struct RenderQueue<'a> {
+    sprites: Vec<Sprite<'a>>,
+    pub stats: RenderStats,
+}
+
+impl<'a> RenderQueue<'a> {
+    pub fn new() -> Self {
+        RenderQueue {
+            sprites: Vec::new(),
+            stats: RenderStats::default(),
+        }
+    }
+
+    pub fn submit(&mut self, sprite: Sprite<'a>) {
+        self.sprites.push(sprite);
+    }
+
+    pub fn flush(&mut self, canvas: &mut WindowCanvas) -> Result<(), Error> {
+        profile!("draw");
+        self.sprites.sort_by(|a, b| {
+            a.layer
+                .cmp(&b.layer)
+                .then(a.z.partial_cmp(&b.z).unwrap_or(Ordering::Equal))
+                .then((a.texture as *const Texture as usize).cmp(&(b.texture as *const Texture as usize)))
+        });
+
+        let mut stats = RenderStats { sprites: self.sprites.len(), state_changes: 0 };
+        let mut current: Option<(*const Texture, Color, BlendMode)> = None;
+        let mut touched: Vec<&Texture> = Vec::new();
+        let mut result = Ok(());
+        for s in &self.sprites {
+            let state = (s.texture as *const Texture, s.tint, s.blend);
+            if current != Some(state) {
+                set_texture_mods(s.texture, s.tint, s.blend);
+                if !touched.iter().any(|t| std::ptr::eq(*t, s.texture)) {
+                    touched.push(s.texture);
+                }
+                current = Some(state);
+                stats.state_changes += 1;
+            }
+            if let Err(e) = canvas.copy_ex(s.texture, s.src, s.dst, s.rot, None, false, false) {
+                result = Err(Error::render(e));
+                break;
+            }
+        }
+        // leave textures as we found them for anything drawn outside the queue
+        for texture in touched {
+            reset_texture_mods(texture);
+        }
+
+        // a failed frame is dropped, not drawn again under the next one
+        self.sprites.clear();
+        self.stats = stats;
+        result
+    }
+}
@@ -1,5 +1,7 @@
 extern crate sdl2;
 
+use crate::error::Error;
+
 use std::borrow::Borrow;
 
 use sdl2::image::{InitFlag, Sdl2ImageContext};
@@ -20,25 +22,25 @@ pub struct SdlRenderer {
 }
 
 impl SdlRenderer {
-    pub fn init(window_width: u32, window_height: u32) -> Result<Self, String> {
+    pub fn init(window_width: u32, window_height: u32) -> Result<Self, Error> {
         // open SDL context
-        let sdl_ctx = sdl2::init().map_err(|e| e.to_string())?;
+        let sdl_ctx = sdl2::init().map_err(|e| Error::init("SDL", e))?;
         // open video subsystem
-        let video_subsys = sdl_ctx.video().map_err(|e| e.to_string())?;
+        let video_subsys = sdl_ctx.video().map_err(|e| Error::init("video", e))?;
         // open image context
-        let image_ctx = sdl2::image::init(InitFlag::PNG | InitFlag::JPG).unwrap();
+        let image_ctx = sdl2::image::init(InitFlag::PNG | InitFlag::JPG).map_err(|e| Error::init("SDL_image", e))?;
         //open font context
-        let ttf_ctx = sdl2::ttf::init().map_err(|e| e.to_string())?;
+        let ttf_ctx = sdl2::ttf::init().map_err(|e| Error::init("SDL_ttf", e))?;
         // create window
         let w = video_subsys.window("rust-sdl2 demo", window_width, window_height)
             .position_centered()
             .build()
-            .map_err(|e| e.to_string())?;
+            .map_err(|e| Error::init("the window", e))?;
 
         // create canvas
         let canv = w.into_canvas()
             .build()
-            .expect("failed to build window's canvas");
+            .map_err(|e| Error::init("the renderer", e))?;
         
         //create texture create
         let tex_creator = canv.texture_creator();
@@ -69,19 +71,18 @@ impl SdlRenderer {
         self.canvas.present().borrow();
     }
 
-    pub fn draw_text(&mut self, font: &sdl2::ttf::Font<'_, '_>, text: String, x: i32, y:i32) -> Result<(), String> {
+    pub fn draw_text(&mut self, font: &sdl2::ttf::Font<'_, '_>, text: String, x: i32, y:i32) -> Result<(), Error> {
         let surface = font
             .render(text.as_str())
             .solid(Color::RGBA(255, 0, 0, 255))
-            .map_err(|e| e.to_string()).map_err(|e| e.to_string())?;
+            .map_err(Error::render)?;
         let font_texture = self.texture_creator
             .create_texture_from_surface(&surface)
-            .map_err(|e| e.to_string()).map_err(|e| e.to_string())?;
+            .map_err(Error::render)?;
         
-        let size = font.size_of(&text.as_str()).map_err(|e| e.to_string())?;
+        let size = font.size_of(&text.as_str()).map_err(Error::render)?;
         let target = Rect::new(x,y, size.0, size.1);
     
-        self.canvas.copy(&font_texture, None, Some(target)).map_err(|e| e.to_string());
-        Ok(())
+        self.canvas.copy(&font_texture, None, Some(target)).map_err(Error::render)
     }
 }
\ No newline at end of file
@@ -0,0 +1,384 @@
+//rollback module - keeps a World in step with a remote peer by predicting
+//their input and rewinding when the prediction turns out wrong
+
+use crate::events::GameEvent;
+use crate::input::PlayerInput;
+use crate::world::World;
+
+use std::collections::{BTreeMap, VecDeque};
+
+// simulation step used online; both peers must agree on it
+pub const TICK_MS: u32 = 16;
+// how far ahead of the last confirmed remote input we may run before waiting
+pub const MAX_ROLLBACK: u32 = 8;
+// the most input delay, in ticks, a game can be started with
+pub const MAX_DELAY: u32 = 8;
+// a working peer never sends inputs or checksums further past the last
+// confirmed tick than this
+const MAX_AHEAD: u32 = 4 * (MAX_DELAY + MAX_ROLLBACK);
+// only every Nth confirmed tick is checksummed and compared
+pub const CHECKSUM_INTERVAL: u32 = 30;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RollbackStats {
+    pub rollbacks: u32,
+    pub resimulated: u32,
+    pub stalls: u32,
+}
+
+pub struct Rollback {
+    pub local: usize,
+    delay: u32,
+    // the next tick to be simulated
+    tick: u32,
+    // inputs by tick for each player; the remote ones only once received
+    inputs: Vec<BTreeMap<u32, PlayerInput>>,
+    // what was assumed for remote ticks that hadn't arrived yet
+    predicted: BTreeMap<u32, PlayerInput>,
+    // every remote tick before this one is known
+    confirmed: u32,
+    // and the peer has every local tick before this one
+    acked: u32,
+    rollback_to: Option<u32>,
+    // world state at the start of each tick that may still be rewound to
+    snapshots: VecDeque<(u32, World)>,
+    checked: u32,
+    local_checksums: BTreeMap<u32, u64>,
+    remote_checksums: BTreeMap<u32, u64>,
+    outgoing_checksums: Vec<(u32, u64)>,
+    // inputs of ticks that can no longer change, for streaming
+    settled: Vec<[PlayerInput; 2]>,
+    pub desync: Option<u32>,
+    pub stats: RollbackStats,
+    // real time not yet turned into ticks
+    pending_ms: u32,
+    // one-off presses no tick has taken yet
+    fire_pressed: bool,
+    restart: bool,
+    bomb: bool,
+}
+
+impl Rollback {
+    // Two players; local is 0 on the host and 1 on the joining side.
+    pub fn new(local: usize, delay: u32) -> Self {
+        let mut inputs = vec![BTreeMap::new(), BTreeMap::new()];
+        // nobody can have pressed anything during the first `delay` ticks
+        for tick in 0..delay {
+            for player in inputs.iter_mut() {
+                player.insert(tick, PlayerInput::new());
+            }
+        }
+        Rollback {
+            local,
+            delay,
+            tick: 0,
+            inputs,
+            predicted: BTreeMap::new(),
+            confirmed: delay,
+            acked: delay,
+            rollback_to: None,
+            snapshots: VecDeque::new(),
+            checked: 0,
+            local_checksums: BTreeMap::new(),
+            remote_checksums: BTreeMap::new(),
+            outgoing_checksums: Vec::new(),
+            settled: Vec::new(),
+            desync: None,
+            stats: RollbackStats::default(),
+            pending_ms: 0,
+            fire_pressed: false,
+            restart: false,
+            bomb: false,
+        }
+    }
+
+    pub fn tick(&self) -> u32 {
+        self.tick
+    }
+
+    fn remote(&self) -> usize {
+        1 - self.local
+    }
+
+    // Runs as many ticks as real time allows with `local` as this side's
+    // input. Held input counts for every tick run, but a press only for the
+    // first, and one made on a frame too short for a tick waits for the next.
+    pub fn update(&mut self, world: &mut World, local: &PlayerInput, frame_ms: u32, events: &mut Vec<GameEvent>) {
+        self.fire_pressed |= local.fire_pressed;
+        self.restart |= local.restart;
+        self.bomb |= local.bomb;
+        self.pending_ms = (self.pending_ms + frame_ms).min(TICK_MS * 8);
+        while self.pending_ms >= TICK_MS {
+            let mut input = *local;
+            input.fire_pressed = self.fire_pressed;
+            input.restart = self.restart;
+            input.bomb = self.bomb;
+            if self.add_local(input) {
+                self.fire_pressed = false;
+                self.restart = false;
+                self.bomb = false;
+            }
+            if !self.advance(world, events) {
+                // the peer is behind; wait for them rather than run away
+                break;
+            }
+            self.pending_ms -= TICK_MS;
+        }
+    }
+
+    // Local input for the current tick, applied `delay` ticks later so the
+    // peer usually has it before it is needed. False if that tick already
+    // has one, which may have been sent and so can't change.
+    fn add_local(&mut self, input: PlayerInput) -> bool {
+        let tick = self.tick + self.delay;
+        if self.inputs[self.local].contains_key(&tick) {
+            return false;
+        }
+        self.inputs[self.local].insert(tick, input);
+        true
+    }
+
+    pub fn local_inputs(&self, from: u32) -> impl Iterator<Item = (&u32, &PlayerInput)> {
+        self.inputs[self.local].range(from..)
+    }
+
+    // every remote tick before this has been received
+    pub fn confirmed(&self) -> u32 {
+        self.confirmed
+    }
+
+    // the peer has every local input before this tick
+    pub fn acked(&self) -> u32 {
+        self.acked
+    }
+
+    pub fn ack(&mut self, tick: u32) {
+        self.acked = self.acked.max(tick);
+    }
+
+    pub fn add_remote(&mut self, tick: u32, input: PlayerInput) {
+        let remote = self.remote();
+        let expected = self.confirmed..=self.confirmed.saturating_add(MAX_AHEAD);
+        if !expected.contains(&tick) || self.inputs[remote].contains_key(&tick) {
+            return;
+        }
+        self.inputs[remote].insert(tick, input);
+        if let Some(guess) = self.predicted.remove(&tick) {
+            if guess != input {
+                self.rollback_to = Some(self.rollback_to.map_or(tick, |t| t.min(tick)));
+            }
+        }
+        while self.inputs[remote].contains_key(&self.confirmed) {
+            self.confirmed += 1;
+        }
+    }
+
+    // Rewinds if needed, then simulates the next tick. Returns false when it
+    // had to wait because the peer has fallen too far behind.
+    pub fn advance(&mut self, world: &mut World, events: &mut Vec<GameEvent>) -> bool {
+        if let Some(from) = self.rollback_to.take() {
+            self.resimulate(world, from);
+        }
+        if self.tick >= self.confirmed + MAX_ROLLBACK || !self.inputs[self.local].contains_key(&self.tick) {
+            self.stats.stalls += 1;
+            return false;
+        }
+        self.step(world, events);
+        self.record_checksums(world);
+        true
+    }
+
+    fn resimulate(&mut self, world: &mut World, from: u32) {
+        let index = match self.snapshots.iter().position(|(t, _)| *t == from) {
+            Some(i) => i,
+            None => return,
+        };
+        let target = self.tick;
+        *world = self.snapshots[index].1.clone();
+        self.snapshots.truncate(index);
+        self.tick = from;
+        self.stats.rollbacks += 1;
+        // effects for these ticks were already shown the first time round
+        let mut discarded = Vec::new();
+        while self.tick < target {
+            self.step(world, &mut discarded);
+            self.stats.resimulated += 1;
+        }
+    }
+
+    fn step(&mut self, world: &mut World, events: &mut Vec<GameEvent>) {
+        let tick = self.tick;
+        let remote = self.remote();
+        let mut inputs = [PlayerInput::new(); 2];
+        inputs[self.local] = self.inputs[self.local][&tick];
+        inputs[remote] = match self.inputs[remote].get(&tick) {
+            Some(input) => *input,
+            None => {
+                // assume they are still doing what they did last, minus
+                // one-off presses
+                let mut guess = self.inputs[remote].range(..tick).next_back().map_or(PlayerInput::new(), |(_, i)| *i);
+                guess.fire_pressed = false;
+                guess.restart = false;
+                guess.bomb = false;
+                self.predicted.insert(tick, guess);
+                guess
+            }
+        };
+        self.snapshots.push_back((tick, world.clone()));
+        world.update(&inputs, TICK_MS, events);
+        self.tick += 1;
+    }
+
+    // Ticks whose inputs are all confirmed can no longer change, so their
+    // end state can be checksummed. Older snapshots and inputs are dropped.
+    fn record_checksums(&mut self, world: &World) {
+        let settled = self.confirmed.min(self.tick);
+        while self.checked < settled {
+            let tick = self.checked;
+            self.settled.push([self.inputs[0][&tick], self.inputs[1][&tick]]);
+            if tick % CHECKSUM_INTERVAL == 0 {
+                let after = if tick + 1 == self.tick {
+                    Some(world.checksum())
+                } else {
+                    self.snapshots.iter().find(|(t, _)| *t == tick + 1).map(|(_, w)| w.checksum())
+                };
+                if let Some(sum) = after {
+                    self.local_checksums.insert(tick, sum);
+                    self.outgoing_checksums.push((tick, sum));
+                    self.compare(tick);
+                }
+            }
+            self.checked += 1;
+        }
+        while self.snapshots.front().map_or(false, |(t, _)| *t < settled) {
+            self.snapshots.pop_front();
+        }
+        // the last remote input is kept around to predict from, and local
+        // ones until the peer has them
+        let keep = settled.saturating_sub(1);
+        let remote = self.remote();
+        self.inputs[remote] = self.inputs[remote].split_off(&keep);
+        self.inputs[self.local] = self.inputs[self.local].split_off(&keep.min(self.acked));
+        self.predicted = self.predicted.split_off(&settled);
+        self.local_checksums = self.local_checksums.split_off(&settled.saturating_sub(CHECKSUM_INTERVAL * 8));
+        self.remote_checksums = self.remote_checksums.split_off(&settled.saturating_sub(CHECKSUM_INTERVAL * 8));
+    }
+
+    pub fn take_settled(&mut self) -> Vec<[PlayerInput; 2]> {
+        std::mem::take(&mut self.settled)
+    }
+
+    pub fn take_checksums(&mut self) -> Vec<(u32, u64)> {
+        std::mem::take(&mut self.outgoing_checksums)
+    }
+
+    pub fn add_remote_checksum(&mut self, tick: u32, sum: u64) {
+        if tick > self.confirmed.saturating_add(MAX_AHEAD) {
+            return;
+        }
+        self.remote_checksums.insert(tick, sum);
+        self.compare(tick);
+    }
+
+    fn compare(&mut self, tick: u32) {
+        if let (Some(local), Some(remote)) = (self.local_checksums.get(&tick), self.remote_checksums.get(&tick)) {
+            if local != remote && self.desync.is_none() {
+                warn!("desync at tick {}: {:016x} here, {:016x} on the peer", tick, local, remote);
+                self.desync = Some(tick);
+            }
+            self.remote_checksums.remove(&tick);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec2;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    struct Peer {
+        rollback: Rollback,
+        world: World,
+        // every checksum this side worked out
+        sums: BTreeMap<u32, u64>,
+    }
+
+    impl Peer {
+        fn new(local: usize) -> Self {
+            let mut world = World::new(2, 7);
+            // nothing but the players' own bullets, slow enough to stay on screen
+            world.enemies.clear();
+            world.tuning.spawn_rate = u32::MAX;
+            world.tuning.powerup_rate = u32::MAX;
+            world.tuning.bullet_speed = 1.0;
+            Peer { rollback: Rollback::new(local, 2), world, sums: BTreeMap::new() }
+        }
+
+        fn bullets(&self, owner: usize) -> usize {
+            self.world.bullets.iter().filter(|b| b.owner == Some(owner)).count()
+        }
+    }
+
+    // What Netplay sends each frame, with a third of the packets lost
+    fn send(from: &mut Peer, to: &mut Peer, rng: &mut StdRng) {
+        let mut lost = || rng.gen::<f32>() < 0.3;
+        if !lost() {
+            let (ack, first_tick) = (from.rollback.confirmed(), from.rollback.acked());
+            let inputs: Vec<PlayerInput> = from.rollback.local_inputs(first_tick).map(|(_, i)| *i).collect();
+            to.rollback.ack(ack);
+            for (i, input) in inputs.into_iter().enumerate() {
+                to.rollback.add_remote(first_tick + i as u32, input);
+            }
+        }
+        for (tick, sum) in from.rollback.take_checksums() {
+            from.sums.insert(tick, sum);
+            if !lost() {
+                to.rollback.add_remote_checksum(tick, sum);
+            }
+        }
+    }
+
+    #[test]
+    fn peers_agree_and_clicks_fire_once_under_loss() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut peers = [Peer::new(0), Peer::new(1)];
+        let mut events = Vec::new();
+        for frame in 0..600u32 {
+            // three ticks, then a frame too short for any, then one each
+            let frame_ms = match frame % 5 {
+                0 => TICK_MS * 3,
+                1 | 2 => TICK_MS / 2,
+                _ => TICK_MS,
+            };
+            for (player, peer) in peers.iter_mut().enumerate() {
+                let mut input = PlayerInput::new();
+                if frame < 400 {
+                    // changes of direction the other side has to guess at
+                    let sign = if (frame / (7 + player as u32 * 4)) % 2 == 0 { 1.0 } else { -1.0 };
+                    input.movement = if player == 0 { Vec2::new(sign, 0.0) } else { Vec2::new(0.0, sign) };
+                }
+                // a click on a frame that runs three ticks and on one that runs none
+                input.fire_pressed = matches!((player, frame), (0, 50) | (0, 61) | (1, 71));
+                peer.rollback.update(&mut peer.world, &input, frame_ms, &mut events);
+            }
+            let [a, b] = &mut peers;
+            send(a, b, &mut rng);
+            send(b, a, &mut rng);
+        }
+
+        for peer in &peers {
+            assert_eq!(peer.rollback.desync, None);
+            assert_eq!(peer.bullets(0), 2);
+            assert_eq!(peer.bullets(1), 1);
+        }
+        let [a, b] = &peers;
+        let shared: Vec<u32> = a.sums.keys().filter(|t| b.sums.contains_key(t)).copied().collect();
+        assert!(shared.len() > 10);
+        for tick in shared {
+            assert_eq!(a.sums[&tick], b.sums[&tick], "checksums differ at tick {}", tick);
+        }
+        assert!(a.rollback.stats.rollbacks + b.rollback.stats.rollbacks > 0);
+    }
+}
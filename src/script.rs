@@ -0,0 +1,370 @@
+//script module - Rhai scripts for enemy behaviour, powerup effects and
+//level events, reloaded whenever a file under SCRIPTS_DIR changes. Mods can
+//add scripts or replace the game's by using the same name.
+//
+//A script defines whichever of these it needs:
+//  fn enemy_update(target)   `this` is the enemy, target a player or ()
+//  fn pickup(kind, player)   return true to replace the built-in effect
+//  fn tick(world)            every simulation step
+//  fn on_event(event)        for everything that happened during the step
+//and changes the world through the functions registered in Scripts::unloaded,
+//spawning at most MAX_SPAWNS things a step and giving at most MAX_AMOUNT.
+
+use crate::entity::{Animation, Entity, EntityType, Look};
+use crate::error::Error;
+use crate::events::GameEvent;
+use crate::json::Json;
+use crate::vfs::Vfs;
+use crate::world::World;
+
+use glam::Vec2;
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+use sdl2::rect::Point;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::SystemTime;
+
+pub const SCRIPTS_DIR: &str = "assets/scripts";
+// budget for a single hook call, so a stuck loop can't hang the game
+const MAX_OPERATIONS: u64 = 50_000;
+// how often to look for edited scripts
+pub const RELOAD_MS: u32 = 500;
+// the most enemies, powerups and shots scripts may add in one step; the
+// rest are dropped
+pub const MAX_SPAWNS: usize = 64;
+// the most one heal, shield or add_score call may give or take
+const MAX_AMOUNT: i64 = 1_000_000;
+
+// What scripts ask for, applied by the world once the step is done
+#[derive(Debug, Clone)]
+pub enum Command {
+    SpawnEnemy { pos: Vec2, vel: Vec2 },
+    SpawnPowerup { typ: EntityType, pos: Vec2 },
+    Fire { from: Vec2, to: Vec2 },
+    Heal { player: usize, amount: i32 },
+    Shield { player: usize, amount: i32 },
+    Score { player: usize, amount: i32 },
+    KillEnemies { by: Option<usize> },
+}
+
+// Scripts are free to write 3 where they mean 3.0. NaN and infinities,
+// e.g. from dividing by a zero distance, count as no number at all so they
+// never reach the world.
+fn number(value: &Dynamic) -> Option<f32> {
+    let n = value.as_float().map(|f| f as f32).ok().or_else(|| value.as_int().ok().map(|i| i as f32));
+    n.filter(|n| n.is_finite())
+}
+
+// An argument to a command, which has to be a usable number
+fn argument(value: &Dynamic, name: &str) -> Result<f32, Box<EvalAltResult>> {
+    number(value).ok_or_else(|| format!("{} must be a finite number, not {}", name, value).into())
+}
+
+fn field(map: &Map, key: &str) -> Option<f32> {
+    map.get(key).and_then(number)
+}
+
+fn vec2_map(v: Vec2) -> Dynamic {
+    let mut map = Map::new();
+    map.insert("x".into(), Dynamic::from_float(v.x() as f64));
+    map.insert("y".into(), Dynamic::from_float(v.y() as f64));
+    map.into()
+}
+
+pub fn entity_map(e: &Entity) -> Dynamic {
+    let mut map = Map::new();
+    let c = e.trans.center();
+    map.insert("x".into(), Dynamic::from_float(e.trans.pos.x() as f64));
+    map.insert("y".into(), Dynamic::from_float(e.trans.pos.y() as f64));
+    map.insert("cx".into(), Dynamic::from_float(c.x() as f64));
+    map.insert("cy".into(), Dynamic::from_float(c.y() as f64));
+    map.insert("vx".into(), Dynamic::from_float(e.trans.vel.x() as f64));
+    map.insert("vy".into(), Dynamic::from_float(e.trans.vel.y() as f64));
+    map.insert("rot".into(), Dynamic::from_float(e.trans.rot));
+    map.insert("life".into(), Dynamic::from_int(e.life as i64));
+    map.insert("shield".into(), Dynamic::from_int(e.shield as i64));
+    map.into()
+}
+
+// Copies back what a script changed; anything that isn't a finite number is
+// left as it was. `anim` isn't in the map it was given; setting it to
+// [row, col, frames, fps] starts a new animation.
+fn update_entity(e: &mut Entity, value: &Dynamic) {
+    let map = match value.read_lock::<Map>() {
+        Some(m) => m,
+        None => return,
+    };
+    let pos = (field(&map, "x"), field(&map, "y"));
+    if let (Some(x), Some(y)) = pos {
+        e.trans.pos = Vec2::new(x, y);
+    }
+    if let (Some(x), Some(y)) = (field(&map, "vx"), field(&map, "vy")) {
+        e.trans.vel = Vec2::new(x, y);
+    }
+    if let Some(rot) = field(&map, "rot") {
+        e.trans.rot = rot as f64;
+    }
+    if let Some(life) = field(&map, "life") {
+        e.life = life as i32;
+    }
+    let anim: Vec<i32> = match map.get("anim").and_then(|a| a.read_lock::<rhai::Array>()) {
+        Some(anim) => anim.iter().filter_map(number).map(|n| n as i32).collect(),
+        None => return,
+    };
+    if let [row, col, frames, fps] = anim[..] {
+        e.anim = Animation::construct(frames.max(1), row, col, Point::new(8, 8), fps.max(1), true);
+        e.look = Look::Scripted;
+    }
+}
+
+// What tick gets to look at; changes go through the registered functions
+pub fn world_map(world: &World) -> Dynamic {
+    let players: rhai::Array = world.players
+        .iter()
+        .map(|p| {
+            let mut map = entity_map(&p.ship).cast::<Map>();
+            map.insert("score".into(), Dynamic::from_int(p.score as i64));
+            map.insert("alive".into(), Dynamic::from_bool(p.alive()));
+            map.into()
+        })
+        .collect();
+    let mut map = Map::new();
+    map.insert("time".into(), Dynamic::from_int(world.ticks as i64));
+    map.insert("enemies".into(), Dynamic::from_int(world.enemies.len() as i64));
+    map.insert("powerups".into(), Dynamic::from_int(world.powerups.len() as i64));
+    map.insert("players".into(), players.into());
+    map.into()
+}
+
+// An event as it is described everywhere else, with its position as x
+// and y
+fn event_map(event: &GameEvent) -> Dynamic {
+    let (name, pos, fields) = event.describe();
+    let mut map = Map::new();
+    map.insert("type".into(), name.into());
+    if let Some(pos) = pos {
+        map.insert("x".into(), Dynamic::from_float(pos.x() as f64));
+        map.insert("y".into(), Dynamic::from_float(pos.y() as f64));
+    }
+    for (key, value) in fields {
+        let value = match value {
+            Json::Bool(b) => Dynamic::from_bool(b),
+            Json::String(text) => text.into(),
+            Json::Number(n) => n.parse::<i64>().map(Dynamic::from_int).unwrap_or_else(|_| Dynamic::from_float(n.parse().unwrap_or(0.0))),
+            _ => Dynamic::UNIT,
+        };
+        map.insert(key.into(), value);
+    }
+    map.into()
+}
+
+// Errors come out as file:line: message
+fn clamp_amount(amount: i64) -> i32 {
+    amount.clamp(-MAX_AMOUNT, MAX_AMOUNT) as i32
+}
+
+fn describe(path: &str, mut err: EvalAltResult) -> String {
+    let pos = err.take_position();
+    match pos.line() {
+        Some(line) => format!("{}:{}: {}", path, line, err),
+        None => format!("{}: {}", path, err),
+    }
+}
+
+struct ScriptFile {
+    path: String,
+    modified: Option<SystemTime>,
+    ast: Option<AST>,
+    // set after a runtime error so it isn't repeated every frame; cleared
+    // when the file is saved again
+    failed: bool,
+}
+
+impl ScriptFile {
+    fn defines(&self, name: &str) -> bool {
+        !self.failed && self.ast.as_ref().map_or(false, |ast| ast.iter_functions().any(|f| f.name == name))
+    }
+}
+
+pub struct Scripts {
+    engine: Engine,
+    vfs: Vfs,
+    files: RefCell<Vec<ScriptFile>>,
+    commands: Rc<RefCell<Vec<Command>>>,
+}
+
+impl Scripts {
+    pub fn new(vfs: Vfs) -> Self {
+        let scripts = Scripts::unloaded(vfs);
+        scripts.reload();
+        scripts
+    }
+
+    // Set up as new() sets it up but with nothing compiled yet, for check()
+    pub fn unloaded(vfs: Vfs) -> Self {
+        let mut engine = Engine::new();
+        // no imports, no eval, and bounded in time and memory
+        engine.set_module_resolver(DummyModuleResolver::new());
+        engine.disable_symbol("eval");
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(32);
+        engine.set_max_expr_depths(64, 32);
+        engine.set_max_string_size(4096);
+        engine.set_max_array_size(1024);
+        engine.set_max_map_size(256);
+        engine.on_print(|text| info!("{}", text));
+        engine.on_debug(|text, _, pos| debug!("{:?}: {}", pos, text));
+
+        let commands: Rc<RefCell<Vec<Command>>> = Rc::new(RefCell::new(Vec::new()));
+        let queue = commands.clone();
+        engine.register_fn("spawn_enemy", move |x: Dynamic, y: Dynamic, vx: Dynamic, vy: Dynamic| -> Result<(), Box<EvalAltResult>> {
+            let pos = Vec2::new(argument(&x, "x")?, argument(&y, "y")?);
+            let vel = Vec2::new(argument(&vx, "vx")?, argument(&vy, "vy")?);
+            queue.borrow_mut().push(Command::SpawnEnemy { pos, vel });
+            Ok(())
+        });
+        let queue = commands.clone();
+        engine.register_fn("spawn_powerup", move |kind: &str, x: Dynamic, y: Dynamic| -> Result<(), Box<EvalAltResult>> {
+            let typ = EntityType::powerup(kind).ok_or_else(|| format!("no powerup called {}", kind))?;
+            let pos = Vec2::new(argument(&x, "x")?, argument(&y, "y")?);
+            queue.borrow_mut().push(Command::SpawnPowerup { typ, pos });
+            Ok(())
+        });
+        let queue = commands.clone();
+        engine.register_fn("fire", move |x: Dynamic, y: Dynamic, tx: Dynamic, ty: Dynamic| -> Result<(), Box<EvalAltResult>> {
+            let from = Vec2::new(argument(&x, "x")?, argument(&y, "y")?);
+            let to = Vec2::new(argument(&tx, "target_x")?, argument(&ty, "target_y")?);
+            queue.borrow_mut().push(Command::Fire { from, to });
+            Ok(())
+        });
+        let queue = commands.clone();
+        engine.register_fn("heal", move |player: i64, amount: i64| {
+            queue.borrow_mut().push(Command::Heal { player: player.max(0) as usize, amount: clamp_amount(amount) });
+        });
+        let queue = commands.clone();
+        engine.register_fn("shield", move |player: i64, amount: i64| {
+            queue.borrow_mut().push(Command::Shield { player: player.max(0) as usize, amount: clamp_amount(amount) });
+        });
+        let queue = commands.clone();
+        engine.register_fn("add_score", move |player: i64, amount: i64| {
+            queue.borrow_mut().push(Command::Score { player: player.max(0) as usize, amount: clamp_amount(amount) });
+        });
+        let queue = commands.clone();
+        engine.register_fn("kill_enemies", move |by: i64| {
+            queue.borrow_mut().push(Command::KillEnemies { by: if by < 0 { None } else { Some(by as usize) } });
+        });
+
+        Scripts { engine, vfs, files: RefCell::new(Vec::new()), commands }
+    }
+
+    fn compile(&self, asset: &str) -> Result<AST, Error> {
+        let text = self.vfs.read_to_string(asset)?;
+        self.engine.compile(text).map_err(|e| {
+            let mut e: Box<EvalAltResult> = e.into();
+            let path = self.vfs.resolve(asset);
+            match e.take_position().line() {
+                Some(line) => Error::decode(format!("{}:{}", path, line), e),
+                None => Error::decode(path, e),
+            }
+        })
+    }
+
+    // Compiles the script at `asset` the way reload() would, without
+    // loading it
+    pub fn check(&self, asset: &str) -> Result<(), Error> {
+        self.compile(asset).map(|_| ())
+    }
+
+    // Picks up new, changed and removed files. Cheap enough to call every
+    // frame: it only compiles what changed.
+    pub fn reload(&self) {
+        // in a fixed order so every machine does the same thing, and named
+        // by where they're read from so errors point at the right file
+        let assets: Vec<String> = self.vfs.list(SCRIPTS_DIR).into_iter().filter(|p| p.ends_with(".rhai")).collect();
+        let paths: Vec<String> = assets.iter().map(|p| self.vfs.resolve(p)).collect();
+        let mut files = self.files.borrow_mut();
+        files.retain(|f| paths.contains(&f.path));
+        for (asset, path) in assets.iter().zip(paths) {
+            let modified = self.vfs.modified(asset);
+            let index = match files.iter().position(|f| f.path == path) {
+                Some(i) if files[i].modified == modified => continue,
+                Some(i) => i,
+                None => {
+                    files.push(ScriptFile { path: path.clone(), modified: None, ast: None, failed: false });
+                    files.len() - 1
+                }
+            };
+            let file = &mut files[index];
+            file.modified = modified;
+            file.failed = false;
+            match self.compile(asset) {
+                Ok(ast) => {
+                    info!("loaded script {}", path);
+                    file.ast = Some(ast);
+                }
+                // keep running the last version that compiled
+                Err(e) => error!("{}", e),
+            }
+        }
+    }
+
+    pub fn has(&self, hook: &str) -> bool {
+        self.files.borrow().iter().any(|f| f.defines(hook))
+    }
+
+    // Calls `hook` in every file that defines it; the last non-unit result
+    // wins
+    fn call(&self, hook: &str, this: Option<&mut Dynamic>, args: Vec<Dynamic>) -> Dynamic {
+        let mut files = self.files.borrow_mut();
+        let mut result = Dynamic::UNIT;
+        let mut this = this;
+        for file in files.iter_mut().filter(|f| f.defines(hook)) {
+            let ast = match &file.ast {
+                Some(ast) => ast,
+                None => continue,
+            };
+            let mut options = CallFnOptions::new().eval_ast(false);
+            if let Some(value) = this.as_deref_mut() {
+                options = options.bind_this_ptr(value);
+            }
+            match self.engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), ast, hook, args.clone()) {
+                Ok(value) if !value.is_unit() => result = value,
+                Ok(_) => {}
+                Err(e) => {
+                    error!("{} (in {})", describe(&file.path, *e), hook);
+                    file.failed = true;
+                }
+            }
+        }
+        result
+    }
+
+    pub fn enemy_update(&self, enemy: &mut Entity, target: Option<Vec2>) {
+        let mut this = entity_map(enemy);
+        let target = target.map_or(Dynamic::UNIT, vec2_map);
+        let _ = self.call("enemy_update", Some(&mut this), vec![target]);
+        update_entity(enemy, &this);
+    }
+
+    // True when a script took care of the effect
+    pub fn pickup(&self, typ: &EntityType, player: usize) -> bool {
+        let args = vec![typ.name().into(), Dynamic::from_int(player as i64)];
+        self.call("pickup", None, args).as_bool().unwrap_or(false)
+    }
+
+    pub fn tick(&self, world: Dynamic) {
+        let _ = self.call("tick", None, vec![world]);
+    }
+
+    pub fn on_events(&self, events: &[GameEvent]) {
+        for event in events {
+            let _ = self.call("on_event", None, vec![event_map(event)]);
+        }
+    }
+
+    pub fn take_commands(&self) -> Vec<Command> {
+        std::mem::take(&mut *self.commands.borrow_mut())
+    }
+}
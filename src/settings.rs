@@ -0,0 +1,177 @@
+//settings module - player settings persisted between runs
+
+use crate::error::Error;
+use crate::ini::Ini;
+use crate::juice::JuiceSettings;
+
+pub const SETTINGS_PATH: &str = "settings.ini";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowMode {
+    Windowed,
+    Borderless,
+}
+
+#[derive(Clone)]
+pub struct DisplaySettings {
+    pub mode: WindowMode,
+    pub width: u32,
+    pub height: u32,
+    pub integer_scale: bool,
+}
+
+// Player 1 plays on keyboard and mouse, everyone else gets a controller
+// in the order they were plugged in.
+#[derive(Clone)]
+pub struct CoopSettings {
+    pub players: usize,
+    pub friendly_fire: bool,
+}
+
+#[derive(Clone)]
+pub struct AudioSettings {
+    pub sound_effects: bool,
+    // 0 to 1
+    pub volume: f32,
+}
+
+#[derive(Clone)]
+pub struct Settings {
+    pub display: DisplaySettings,
+    pub audio: AudioSettings,
+    pub juice: JuiceSettings,
+    pub coop: CoopSettings,
+    // mod id and whether it's switched on; mods not listed are on
+    pub mods: Vec<(String, bool)>,
+}
+
+impl Settings {
+    pub fn new() -> Self {
+        Settings {
+            display: DisplaySettings {
+                mode: WindowMode::Windowed,
+                width: crate::config::SCREEN_WIDTH,
+                height: crate::config::SCREEN_HEIGHT,
+                integer_scale: false,
+            },
+            audio: AudioSettings {
+                sound_effects: true,
+                volume: 1.0,
+            },
+            juice: JuiceSettings::new(),
+            coop: CoopSettings {
+                players: 1,
+                friendly_fire: false,
+            },
+            mods: Vec::new(),
+        }
+    }
+
+    // A missing file just means defaults; a broken one is reported but
+    // shouldn't stop the game from starting.
+    pub fn load(path: &str) -> Self {
+        let mut settings = Settings::new();
+        let ini = match Ini::load(path) {
+            Ok(ini) => ini,
+            Err(e) => {
+                if std::path::Path::new(path).exists() {
+                    warn!("could not read settings, using defaults: {}", e);
+                }
+                return settings;
+            }
+        };
+        if let Err(e) = settings.apply(&ini) {
+            warn!("bad value in settings, using defaults for the rest: {}", e);
+        }
+        settings
+    }
+
+    fn apply(&mut self, ini: &Ini) -> Result<(), String> {
+        if let Some(display) = ini.section("display") {
+            let d = &mut self.display;
+            d.mode = match display.get("mode") {
+                Some("borderless") => WindowMode::Borderless,
+                _ => WindowMode::Windowed,
+            };
+            d.width = display.parse_or("width", d.width)?.max(320);
+            d.height = display.parse_or("height", d.height)?.max(240);
+            d.integer_scale = display.parse_or("integer_scale", d.integer_scale)?;
+        }
+        if let Some(audio) = ini.section("audio") {
+            self.audio.sound_effects = audio.parse_or("sound_effects", self.audio.sound_effects)?;
+            self.audio.volume = audio.parse_or("volume", self.audio.volume)?.clamp(0.0, 1.0);
+        }
+        if let Some(juice) = ini.section("accessibility") {
+            let j = &mut self.juice;
+            j.shake = juice.parse_or("screen_shake", j.shake)?;
+            j.shake_intensity = juice.parse_or("shake_intensity", j.shake_intensity)?;
+            j.flash = juice.parse_or("hit_flash", j.flash)?;
+            j.hit_stop = juice.parse_or("hit_stop", j.hit_stop)?;
+            j.vignette = juice.parse_or("damage_vignette", j.vignette)?;
+            j.rumble = juice.parse_or("rumble", j.rumble)?;
+        }
+        if let Some(coop) = ini.section("coop") {
+            let c = &mut self.coop;
+            c.players = coop.parse_or("players", c.players)?.clamp(1, crate::config::MAX_PLAYERS);
+            c.friendly_fire = coop.parse_or("friendly_fire", c.friendly_fire)?;
+        }
+        if let Some(mods) = ini.section("mods") {
+            for (id, _) in &mods.entries {
+                let on = mods.parse_or(id, true)?;
+                self.set_mod_enabled(id, on);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_ini(&self) -> Ini {
+        let mut ini = Ini::new();
+        let display = ini.section_mut("display");
+        display.set("mode", match self.display.mode {
+            WindowMode::Windowed => "windowed".to_string(),
+            WindowMode::Borderless => "borderless".to_string(),
+        });
+        display.set("width", self.display.width.to_string());
+        display.set("height", self.display.height.to_string());
+        display.set("integer_scale", self.display.integer_scale.to_string());
+
+        let audio = ini.section_mut("audio");
+        audio.set("sound_effects", self.audio.sound_effects.to_string());
+        audio.set("volume", self.audio.volume.to_string());
+
+        let juice = ini.section_mut("accessibility");
+        juice.set("screen_shake", self.juice.shake.to_string());
+        juice.set("shake_intensity", self.juice.shake_intensity.to_string());
+        juice.set("hit_flash", self.juice.flash.to_string());
+        juice.set("hit_stop", self.juice.hit_stop.to_string());
+        juice.set("damage_vignette", self.juice.vignette.to_string());
+        juice.set("rumble", self.juice.rumble.to_string());
+
+        let coop = ini.section_mut("coop");
+        coop.set("players", self.coop.players.to_string());
+        coop.set("friendly_fire", self.coop.friendly_fire.to_string());
+
+        if !self.mods.is_empty() {
+            let mods = ini.section_mut("mods");
+            for (id, on) in &self.mods {
+                mods.set(id, on.to_string());
+            }
+        }
+        ini
+    }
+
+    pub fn mod_enabled(&self, id: &str) -> bool {
+        self.mods.iter().find(|(m, _)| m == id).map_or(true, |(_, on)| *on)
+    }
+
+    pub fn set_mod_enabled(&mut self, id: &str, on: bool) {
+        match self.mods.iter_mut().find(|(m, _)| m == id) {
+            Some(entry) => entry.1 = on,
+            None => self.mods.push((id.to_string(), on)),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        self.to_ini().save(path)
+    }
+}
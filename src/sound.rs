@@ -0,0 +1,146 @@
+//sound module
+
+use crate::asset_manager::Fallback;
+use crate::error::Error;
+use crate::vfs::Vfs;
+
+use sdl2::audio::{AudioCVT, AudioCallback, AudioDevice, AudioFormat, AudioSpecDesired, AudioSpecWAV};
+use sdl2::rwops::RWops;
+use sdl2::AudioSubsystem;
+
+use std::sync::Arc;
+
+// Every clip the game plays, by the name it plays it by
+pub const CLIPS: [(&str, &str); 4] = [
+    ("shoot", "assets/sfx/LASERSHOOT.wav"),
+    ("explode", "assets/sfx/EXPLOSION.wav"),
+    ("powerup_spawn", "assets/sfx/POWERUP.wav"),
+    ("powerup_collect", "assets/sfx/POWER_UP3.wav"),
+];
+// clips playing at once; a new one past this cuts off the oldest
+const MAX_VOICES: usize = 16;
+
+// Reads and decodes the clip at `path`
+fn read_wav(vfs: &Vfs, path: &str) -> Result<AudioSpecWAV, Error> {
+    let fail = |e: String| Error::decode(vfs.resolve(path), e);
+    let bytes = vfs.read(path)?;
+    let mut rw = RWops::from_bytes(&bytes).map_err(fail)?;
+    AudioSpecWAV::load_wav_rw(&mut rw).map_err(fail)
+}
+
+pub fn check_wav(vfs: &Vfs, path: &str) -> Result<(), Error> {
+    read_wav(vfs, path).map(|_| ())
+}
+
+struct Voice {
+    samples: Arc<[f32]>,
+    at: usize,
+}
+
+// Runs on SDL's audio thread, adding up whatever is playing
+struct Mixer {
+    voices: Vec<Voice>,
+    volume: f32,
+}
+
+impl AudioCallback for Mixer {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        out.fill(0.0);
+        for voice in &mut self.voices {
+            let samples = &voice.samples[voice.at..];
+            for (o, s) in out.iter_mut().zip(samples) {
+                *o += s * self.volume;
+            }
+            voice.at += samples.len().min(out.len());
+        }
+        self.voices.retain(|v| v.at < v.samples.len());
+        for o in out {
+            *o = o.clamp(-1.0, 1.0);
+        }
+    }
+}
+
+// Plays clips through an SDL audio device at a volume, and does nothing
+// without one (headless captures on a build server). Can be muted without
+// touching every call.
+pub struct Sound {
+    device: Option<AudioDevice<Mixer>>,
+    // converted to the device's format when loaded; playing a name that
+    // isn't here does nothing
+    clips: Vec<(&'static str, Arc<[f32]>)>,
+    pub muted: bool,
+}
+
+impl Sound {
+    // `audio` is None to run silent
+    pub fn new(audio: Option<AudioSubsystem>) -> Self {
+        let desired = AudioSpecDesired { freq: Some(44_100), channels: Some(2), samples: Some(1024) };
+        let device = audio.and_then(|audio| {
+            match audio.open_playback(None, &desired, |_| Mixer { voices: Vec::new(), volume: 1.0 }) {
+                Ok(device) => {
+                    device.resume();
+                    Some(device)
+                }
+                Err(e) => {
+                    warn!("no sound: {}", e);
+                    None
+                }
+            }
+        });
+        Sound { device, clips: Vec::new(), muted: false }
+    }
+
+    // 0 is silent and 1 plays clips as recorded
+    pub fn set_volume(&mut self, volume: f32) {
+        if let Some(device) = &mut self.device {
+            device.lock().volume = volume.clamp(0.0, 1.0);
+        }
+    }
+
+    fn add(&mut self, vfs: &Vfs, name: &'static str, path: &str) -> Result<(), Error> {
+        let wav = read_wav(vfs, path)?;
+        let device = match &self.device {
+            Some(device) => device,
+            None => return Ok(()),
+        };
+        let spec = device.spec();
+        let cvt = AudioCVT::new(wav.format, wav.channels, wav.freq, AudioFormat::f32_sys(), spec.channels, spec.freq)
+            .map_err(|e| Error::decode(vfs.resolve(path), e))?;
+        let bytes = cvt.convert(wav.buffer().to_vec());
+        let samples: Arc<[f32]> = bytes.chunks_exact(4).map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]])).collect();
+        self.clips.push((name, samples));
+        Ok(())
+    }
+
+    // Adds the clip at `path` in the VFS. Under Fallback::Placeholder one
+    // that can't be found or decoded is logged and left silent.
+    pub fn load(&mut self, vfs: &Vfs, name: &'static str, path: &str, fallback: Fallback) -> Result<(), Error> {
+        match self.add(vfs, name, path) {
+            Ok(()) => Ok(()),
+            Err(e) if fallback == Fallback::Placeholder => {
+                warn!("{}; {} will be silent", e, name);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn play(&mut self, name: &str) {
+        if self.muted {
+            return;
+        }
+        let samples = match self.clips.iter().find(|(n, _)| *n == name) {
+            Some((_, samples)) => samples.clone(),
+            None => return,
+        };
+        if let Some(device) = &mut self.device {
+            let mut mixer = device.lock();
+            if mixer.voices.len() >= MAX_VOICES {
+                mixer.voices.remove(0);
+            }
+            mixer.voices.push(Voice { samples, at: 0 });
+        }
+    }
+}
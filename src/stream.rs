@@ -0,0 +1,539 @@
+//stream module - serves a running game over local TCP for spectators and
+//tools, records the same lines to a file as a replay, and the client side
+//that watches a stream or plays a replay back
+
+use crate::config::TUNING_NAMES;
+use crate::error::Error;
+use crate::events::GameEvent;
+use crate::input::PlayerInput;
+use crate::json::{num, object, string, Json};
+use crate::world::World;
+
+use std::fs::File;
+use std::io::{BufWriter, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use glam::Vec2;
+
+// One JSON object per line. A "world" line starts a new game from a seed,
+// "step" lines carry the inputs for each simulation step (enough to replay
+// the game exactly), "event" lines report what happened and "state" lines
+// summarise the world every STATE_INTERVAL steps for dashboards. A replay
+// file is the same lines with only "world" and "step" in it.
+pub const STREAM_PORT: u16 = 7780;
+const PROTOCOL_VERSION: u32 = 1;
+const STATE_INTERVAL: u32 = 10;
+// a live client this far behind is dropped rather than buffered forever
+const MAX_BACKLOG: usize = 4 * 1024 * 1024;
+// about half an hour of steps; past that late joiners wait for the next game
+const MAX_HISTORY: usize = 16 * 1024 * 1024;
+
+struct Client {
+    stream: TcpStream,
+    outbox: Vec<u8>,
+    // how far through the history a late joiner has been sent, None once
+    // it's caught up and getting lines as they happen
+    replayed: Option<usize>,
+}
+
+pub struct StreamServer {
+    listener: TcpListener,
+    clients: Vec<Client>,
+    // the "world" and "step" lines since the current world started, so late
+    // joiners can replay up to the present
+    history: Vec<u8>,
+    // set when the history outgrew MAX_HISTORY and was dropped
+    history_full: bool,
+    steps: u32,
+}
+
+impl StreamServer {
+    // Only listens on localhost; this is for tools on the same machine
+    pub fn start(port: u16) -> Result<Self, Error> {
+        let fail = |e: std::io::Error| Error::init("the stream server", format!("could not listen on port {}: {}", port, e));
+        let listener = TcpListener::bind(("127.0.0.1", port)).map_err(fail)?;
+        listener.set_nonblocking(true).map_err(fail)?;
+        info!("streaming on 127.0.0.1:{}", port);
+        Ok(StreamServer {
+            listener,
+            clients: Vec::new(),
+            history: Vec::new(),
+            history_full: false,
+            steps: 0,
+        })
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    // Called whenever a new World is created
+    pub fn new_world(&mut self, seed: u64, world: &World) {
+        self.history.clear();
+        self.history_full = false;
+        self.steps = 0;
+        // anyone still catching up starts again from this world
+        for client in &mut self.clients {
+            if client.replayed.is_some() {
+                client.replayed = Some(0);
+            }
+        }
+        self.send(world_json(seed, world), true);
+    }
+
+    pub fn step(&mut self, dt_ms: u32, inputs: &[PlayerInput]) {
+        if dt_ms == 0 {
+            return;
+        }
+        self.steps += 1;
+        self.send(step_json(dt_ms, inputs), true);
+    }
+
+    pub fn events(&mut self, world: &World, events: &[GameEvent]) {
+        for event in events {
+            let mut line = event_json(event);
+            if let Json::Object(entries) = &mut line {
+                entries.insert(1, ("tick".to_string(), num(world.ticks)));
+            }
+            self.send(line, false);
+        }
+    }
+
+    // Sent after the frame's steps so a replaying client can check itself
+    pub fn state(&mut self, world: &World) {
+        if self.steps % STATE_INTERVAL != 0 || self.steps == 0 {
+            return;
+        }
+        let players = world.players.iter().map(|p| object(vec![
+            ("pos", vec2_json(p.ship.trans.pos)),
+            ("life", num(p.ship.life)),
+            ("shield", num(p.ship.shield)),
+            ("score", num(p.score)),
+        ])).collect();
+        self.send(object(vec![
+            ("type", string("state")),
+            ("tick", num(world.ticks)),
+            ("checksum", num(world.checksum())),
+            ("players", Json::Array(players)),
+            ("enemies", num(world.enemies.len())),
+            ("bullets", num(world.bullets.len())),
+            ("powerups", num(world.powerups.len())),
+        ]), false);
+    }
+
+    // Accepts new clients and writes as much as each socket will take
+    pub fn flush(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    if stream.set_nonblocking(true).is_err() {
+                        continue;
+                    }
+                    let _ = stream.set_nodelay(true);
+                    info!("stream client connected from {}", addr);
+                    self.clients.push(Client { stream, outbox: Vec::new(), replayed: Some(0) });
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("stream accept failed: {}", e);
+                    break;
+                }
+            }
+        }
+        let (history, history_full) = (&self.history, self.history_full);
+        self.clients.retain_mut(|client| {
+            // a late joiner is fed straight from the history, which doesn't
+            // count against its backlog
+            if let Some(at) = client.replayed {
+                match write_some(&mut client.stream, &history[at..]) {
+                    Some(n) => client.replayed = Some(at + n),
+                    None => return false,
+                }
+                if client.replayed == Some(history.len()) && !history_full {
+                    client.replayed = None;
+                    client.outbox.extend_from_slice(format!("{}\n", object(vec![("type", string("live"))])).as_bytes());
+                }
+            }
+            match write_some(&mut client.stream, &client.outbox) {
+                Some(n) => {
+                    client.outbox.drain(..n);
+                }
+                None => return false,
+            }
+            if client.outbox.len() > MAX_BACKLOG {
+                warn!("dropping stream client that can't keep up");
+                return false;
+            }
+            true
+        });
+    }
+
+    // Lines that are `replayable` are kept for late joiners
+    fn send(&mut self, value: Json, replayable: bool) {
+        let line = format!("{}\n", value);
+        for client in self.clients.iter_mut().filter(|c| c.replayed.is_none()) {
+            client.outbox.extend_from_slice(line.as_bytes());
+        }
+        if !replayable || self.history_full {
+            return;
+        }
+        if self.history.len() + line.len() > MAX_HISTORY {
+            warn!("stream history full; new clients will join at the next game");
+            self.history.clear();
+            self.history_full = true;
+            for client in &mut self.clients {
+                if client.replayed.is_some() {
+                    client.replayed = Some(0);
+                }
+            }
+            return;
+        }
+        self.history.extend_from_slice(line.as_bytes());
+    }
+}
+
+// Writes as much of data as the socket takes without blocking. None once
+// the client has gone.
+fn write_some(stream: &mut TcpStream, data: &[u8]) -> Option<usize> {
+    let mut written = 0;
+    while written < data.len() {
+        match stream.write(&data[written..]) {
+            Ok(0) => return None,
+            Ok(n) => written += n,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(_) => return None,
+        }
+    }
+    Some(written)
+}
+
+// Writes a game's "world" and "step" lines to a file for --replay
+pub struct Recorder {
+    out: BufWriter<File>,
+    pub path: String,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> Result<Self, Error> {
+        let file = File::create(path).map_err(|e| Error::io(path, e))?;
+        info!("recording replay to {}", path);
+        Ok(Recorder { out: BufWriter::new(file), path: path.to_string() })
+    }
+
+    pub fn new_world(&mut self, seed: u64, world: &World) {
+        self.write(world_json(seed, world));
+    }
+
+    pub fn step(&mut self, dt_ms: u32, inputs: &[PlayerInput]) {
+        if dt_ms > 0 {
+            self.write(step_json(dt_ms, inputs));
+        }
+    }
+
+    fn write(&mut self, value: Json) {
+        if let Err(e) = writeln!(self.out, "{}", value) {
+            warn!("{}: {}", self.path, e);
+        }
+    }
+}
+
+// Everything needed to start the same game elsewhere
+fn world_json(seed: u64, world: &World) -> Json {
+    let tuning = TUNING_NAMES.iter().filter_map(|name| Some((*name, num(world.tuning.get(name)?)))).collect();
+    object(vec![
+        ("type", string("world")),
+        ("version", num(PROTOCOL_VERSION)),
+        ("seed", num(seed)),
+        ("players", num(world.players.len())),
+        ("friendly_fire", Json::Bool(world.friendly_fire)),
+        ("tuning", object(tuning)),
+    ])
+}
+
+fn step_json(dt_ms: u32, inputs: &[PlayerInput]) -> Json {
+    object(vec![
+        ("type", string("step")),
+        ("dt", num(dt_ms)),
+        ("inputs", Json::Array(inputs.iter().map(input_json).collect())),
+    ])
+}
+
+fn vec2_json(v: Vec2) -> Json {
+    Json::Array(vec![num(v.x()), num(v.y())])
+}
+
+fn json_vec2(value: Option<&Json>) -> Option<Vec2> {
+    let items = value?.as_array()?;
+    Some(Vec2::new(items.get(0)?.as_num()?, items.get(1)?.as_num()?))
+}
+
+fn input_json(input: &PlayerInput) -> Json {
+    object(vec![
+        ("move", vec2_json(input.movement)),
+        ("aim", input.aim.map_or(Json::Null, vec2_json)),
+        ("aim_dir", input.aim_dir.map_or(Json::Null, vec2_json)),
+        ("fire", Json::Bool(input.fire)),
+        ("fire_pressed", Json::Bool(input.fire_pressed)),
+        ("restart", Json::Bool(input.restart)),
+        ("bomb", Json::Bool(input.bomb)),
+    ])
+}
+
+fn json_input(value: &Json) -> Option<PlayerInput> {
+    let flag = |key| value.get(key).and_then(|v| v.as_bool()).unwrap_or(false);
+    Some(PlayerInput {
+        movement: json_vec2(value.get("move"))?,
+        aim: json_vec2(value.get("aim")),
+        aim_dir: json_vec2(value.get("aim_dir")),
+        fire: flag("fire"),
+        fire_pressed: flag("fire_pressed"),
+        restart: flag("restart"),
+        bomb: flag("bomb"),
+    })
+}
+
+pub fn event_json(event: &GameEvent) -> Json {
+    let (name, pos, fields) = event.describe();
+    let mut entries = vec![("type", string("event")), ("event", string(name))];
+    if let Some(pos) = pos {
+        entries.push(("pos", vec2_json(pos)));
+    }
+    entries.extend(fields);
+    object(entries)
+}
+
+// Watches a stream by replaying its steps into a local World, so the
+// spectator sees the real game with all its effects. A replay file is
+// watched the same way, at the speed it was recorded.
+pub struct Spectator {
+    source: Box<dyn Read>,
+    buffer: Vec<u8>,
+    // a replay plays steps as time passes instead of everything it has
+    pub replay: bool,
+    // time passed that the next replay step is still waiting on
+    pending_ms: u32,
+    // false while replaying the history sent on connect
+    pub live: bool,
+    pub desync: Option<u32>,
+    pub closed: bool,
+}
+
+impl Spectator {
+    pub fn connect(address: &str) -> Result<Self, Error> {
+        let with_port = if address.contains(':') { address.to_string() } else { format!("{}:{}", address, STREAM_PORT) };
+        let fail = |e: std::io::Error| Error::init("watching", format!("could not connect to {}: {}", with_port, e));
+        let stream = TcpStream::connect(&with_port).map_err(fail)?;
+        stream.set_nonblocking(true).map_err(fail)?;
+        Ok(Spectator {
+            source: Box::new(stream),
+            buffer: Vec::new(),
+            replay: false,
+            pending_ms: 0,
+            live: false,
+            desync: None,
+            closed: false,
+        })
+    }
+
+    pub fn replay(path: &str) -> Result<Self, Error> {
+        let file = File::open(path).map_err(|e| Error::io(path, e))?;
+        info!("playing replay {}", path);
+        Ok(Spectator {
+            source: Box::new(file),
+            buffer: Vec::new(),
+            replay: true,
+            pending_ms: 0,
+            // nothing to catch up on
+            live: true,
+            desync: None,
+            closed: false,
+        })
+    }
+
+    // Applies everything received so far, or for a replay every step that
+    // fits in the `frame_ms` that passed, and returns the game time played.
+    // Events from the catch-up replay are dropped so joining late doesn't
+    // set off every old explosion.
+    pub fn update(&mut self, world: &mut World, frame_ms: u32, events: &mut Vec<GameEvent>) -> Result<u32, String> {
+        let mut chunk = [0u8; 16 * 1024];
+        let mut ended = false;
+        loop {
+            match self.source.read(&mut chunk) {
+                Ok(0) => {
+                    ended = true;
+                    break;
+                }
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(format!("stream closed: {}", e)),
+            }
+        }
+        self.pending_ms = self.pending_ms.saturating_add(frame_ms);
+        let mut played = 0;
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let message = Json::parse(String::from_utf8_lossy(&self.buffer[..end]).trim())?;
+            if message.get("type").and_then(|t| t.as_str()) == Some("step") {
+                let dt: u32 = message.get("dt").and_then(|v| v.as_num()).ok_or("step without dt")?;
+                if self.replay {
+                    if dt > self.pending_ms {
+                        break;
+                    }
+                    self.pending_ms -= dt;
+                }
+                played += dt;
+            }
+            self.buffer.drain(..=end);
+            self.apply(&message, world, events)?;
+        }
+        // closed once everything that came before the end has been played
+        self.closed = ended && !self.buffer.contains(&b'\n');
+        if self.closed {
+            self.pending_ms = 0;
+        }
+        Ok(played)
+    }
+
+    fn apply(&mut self, message: &Json, world: &mut World, events: &mut Vec<GameEvent>) -> Result<(), String> {
+        match message.get("type").and_then(|t| t.as_str()) {
+            Some("world") => {
+                let version: u32 = message.get("version").and_then(|v| v.as_num()).unwrap_or(0);
+                if version != PROTOCOL_VERSION {
+                    return Err(format!("stream protocol {} not supported", version));
+                }
+                let seed = message.get("seed").and_then(|v| v.as_num()).ok_or("world without a seed")?;
+                let players = message.get("players").and_then(|v| v.as_num()).unwrap_or(1);
+                // the same scripts run here as where the game was played
+                let (scripts, mut tuning) = (world.scripts.take(), world.tuning);
+                for name in TUNING_NAMES {
+                    if let Some(value) = message.get("tuning").and_then(|t| t.get(name)).and_then(|v| v.as_num::<f32>()) {
+                        tuning.set(name, &value.to_string())?;
+                    }
+                }
+                *world = World::new(players, seed);
+                world.friendly_fire = message.get("friendly_fire").and_then(|v| v.as_bool()).unwrap_or(false);
+                world.scripts = scripts;
+                world.tuning = tuning;
+                self.desync = None;
+                events.push(GameEvent::Restarted);
+            }
+            Some("step") => {
+                let dt = message.get("dt").and_then(|v| v.as_num()).ok_or("step without dt")?;
+                let inputs: Vec<PlayerInput> = message
+                    .get("inputs")
+                    .and_then(|v| v.as_array())
+                    .ok_or("step without inputs")?
+                    .iter()
+                    .map(|i| json_input(i).ok_or("bad input in step"))
+                    .collect::<Result<_, _>>()?;
+                if self.live {
+                    world.update(&inputs, dt, events);
+                } else {
+                    world.update(&inputs, dt, &mut Vec::new());
+                }
+            }
+            Some("state") => {
+                let tick = message.get("tick").and_then(|v| v.as_num()).unwrap_or(0);
+                let sum: Option<u64> = message.get("checksum").and_then(|v| v.as_num());
+                if tick == world.ticks && sum != Some(world.checksum()) && self.desync.is_none() {
+                    warn!("spectator out of step with the stream at tick {}", tick);
+                    self.desync = Some(tick);
+                }
+            }
+            Some("live") => self.live = true,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rollback::TICK_MS;
+
+    // Plays steps into the server's own world the way the game does
+    fn play(server: &mut StreamServer, world: &mut World, steps: u32) {
+        for i in 0..steps {
+            let mut input = PlayerInput::new();
+            input.movement = Vec2::new((i % 7) as f32 / 7.0 - 0.5, 0.0);
+            input.aim_dir = Some(Vec2::new(1.0, (i % 11) as f32 / 11.0));
+            input.fire = i % 3 == 0;
+            world.update(&[input], TICK_MS, &mut Vec::new());
+            server.step(TICK_MS, &[input]);
+            server.state(world);
+        }
+    }
+
+    #[test]
+    fn late_joiner_catches_up_past_the_backlog_limit() {
+        let mut server = StreamServer::start(0).unwrap();
+        let port = server.listener.local_addr().unwrap().port();
+        let mut world = World::new(1, 5);
+        // an hour between spawns, the most a stream can carry
+        world.tuning.spawn_rate = 3_600_000;
+        server.new_world(5, &world);
+        play(&mut server, &mut world, 40_000);
+        // more than a live client may fall behind by
+        assert!(server.history.len() > MAX_BACKLOG);
+
+        // the client stand-in: a spectator on the other end of a real socket
+        let mut spectator = Spectator::connect(&format!("127.0.0.1:{}", port)).unwrap();
+        let mut watched = World::new(1, 0);
+        let mut events = Vec::new();
+        for _ in 0..10_000 {
+            server.flush();
+            spectator.update(&mut watched, TICK_MS, &mut events).unwrap();
+            if spectator.live {
+                break;
+            }
+        }
+        assert!(spectator.live);
+        assert_eq!(server.client_count(), 1);
+        assert_eq!(watched.ticks, world.ticks);
+
+        // and keeps in step once it's live
+        play(&mut server, &mut world, 100);
+        server.flush();
+        for _ in 0..100 {
+            spectator.update(&mut watched, TICK_MS, &mut events).unwrap();
+            if watched.ticks == world.ticks {
+                break;
+            }
+            server.flush();
+        }
+        assert_eq!(watched.checksum(), world.checksum());
+        assert_eq!(spectator.desync, None);
+    }
+
+    #[test]
+    fn replay_plays_at_recorded_speed_whatever_the_frame_rate() {
+        let path = std::env::temp_dir().join(format!("replay-speed-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        // a second of a game run at 144 Hz
+        let mut world = World::new(1, 9);
+        let mut recorder = Recorder::create(path).unwrap();
+        recorder.new_world(9, &world);
+        for i in 0..144 {
+            let mut input = PlayerInput::new();
+            input.movement = Vec2::new(((i % 5) as f32 - 2.0) / 2.0, 0.5);
+            input.fire = i % 4 == 0;
+            world.update(&[input], 7, &mut Vec::new());
+            recorder.step(7, &[input]);
+        }
+        drop(recorder);
+
+        // played back at 60 Hz it still takes about a second
+        let mut replay = Spectator::replay(path).unwrap();
+        let mut watched = World::new(1, 0);
+        let mut played = 0;
+        for frame in 1..=63 {
+            played += replay.update(&mut watched, 16, &mut Vec::new()).unwrap();
+            // never ahead of the clock, and never a whole step behind it
+            assert!(watched.ticks <= frame * 16 && watched.ticks + 7 > frame * 16);
+        }
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(played, 1008);
+        assert_eq!(watched.ticks, world.ticks);
+        assert_eq!(watched.checksum(), world.checksum());
+    }
+}
@@ -0,0 +1,248 @@
+//telemetry module - records what happens in a session to a JSON Lines or
+//CSV file for working out difficulty curves and causes of death afterwards
+
+use crate::error::Error;
+use crate::events::GameEvent;
+use crate::json::{num, object, string, Json};
+use crate::stream::event_json;
+use crate::world::World;
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const TELEMETRY_DIR: &str = "telemetry";
+// world milliseconds between score samples
+const SAMPLE_MS: u32 = 1000;
+// real milliseconds frame times are averaged over
+const FRAME_WINDOW_MS: u32 = 1000;
+
+// Every record shares these columns in CSV, empty where they don't apply
+const CSV_COLUMNS: [&str; 14] = [
+    "time_ms", "type", "player", "x", "y", "cause", "teammate", "powerup",
+    "score", "life", "shield", "enemies", "frame_ms_avg", "frame_ms_max",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    JsonLines,
+    Csv,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "jsonl" | "json" => Some(Format::JsonLines),
+            "csv" => Some(Format::Csv),
+            _ => None,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::JsonLines => "jsonl",
+            Format::Csv => "csv",
+        }
+    }
+}
+
+pub struct Telemetry {
+    format: Format,
+    out: BufWriter<File>,
+    pub path: String,
+    // games started this session, counting the first
+    games: u32,
+    last_ticks: u32,
+    next_sample: u32,
+    frame_total: u32,
+    frame_max: u32,
+    frame_count: u32,
+}
+
+impl Telemetry {
+    // One file per session, named after when it started
+    pub fn create(format: Format) -> Result<Self, Error> {
+        fs::create_dir_all(TELEMETRY_DIR).map_err(|e| Error::io(TELEMETRY_DIR, e))?;
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let path = format!("{}/session-{}.{}", TELEMETRY_DIR, started, format.extension());
+        let file = File::create(&path).map_err(|e| Error::io(&path, e))?;
+        let mut telemetry = Telemetry {
+            format,
+            out: BufWriter::new(file),
+            path,
+            games: 0,
+            last_ticks: 0,
+            next_sample: 0,
+            frame_total: 0,
+            frame_max: 0,
+            frame_count: 0,
+        };
+        if format == Format::Csv {
+            telemetry.line(&CSV_COLUMNS.join(","));
+        }
+        Ok(telemetry)
+    }
+
+    // Call once a frame with the events the world produced, before anything
+    // drains them
+    pub fn record(&mut self, world: &World, events: &[GameEvent], frame_ms: u32) {
+        let first = self.games == 0;
+        let restarted = events.iter().any(|e| matches!(e, GameEvent::Restarted));
+        // a world replaced without a restart event still shows as time
+        // going backwards
+        if first || (!restarted && world.ticks < self.last_ticks) {
+            self.new_game(world);
+        }
+        self.last_ticks = world.ticks;
+        for event in events {
+            match event {
+                GameEvent::Restarted if !first => self.new_game(world),
+                GameEvent::Restarted => {}
+                event => self.event(world.ticks, event),
+            }
+        }
+        if world.ticks >= self.next_sample {
+            self.sample(world);
+            self.next_sample = world.ticks - world.ticks % SAMPLE_MS + SAMPLE_MS;
+        }
+        self.frame(world.ticks, frame_ms);
+    }
+
+    fn new_game(&mut self, world: &World) {
+        self.games += 1;
+        self.next_sample = 0;
+        match self.format {
+            Format::JsonLines => self.json(object(vec![
+                ("time_ms", num(world.ticks)),
+                ("type", string("new_game")),
+                ("game", num(self.games)),
+                ("players", num(world.players.len())),
+            ])),
+            Format::Csv => self.csv(world.ticks, "new_game", &[]),
+        }
+    }
+
+    fn event(&mut self, time_ms: u32, event: &GameEvent) {
+        match self.format {
+            Format::JsonLines => {
+                let mut record = event_json(event);
+                if let Json::Object(entries) = &mut record {
+                    // "type" is the record kind here, the event name goes in its place
+                    entries.retain(|(k, _)| k != "type");
+                    if let Some(entry) = entries.iter_mut().find(|(k, _)| k == "event") {
+                        entry.0 = "type".to_string();
+                    }
+                    entries.insert(0, ("time_ms".to_string(), num(time_ms)));
+                }
+                self.json(record);
+            }
+            Format::Csv => {
+                let (name, pos, described) = event.describe();
+                let mut fields = Vec::new();
+                for (key, value) in described {
+                    let column = match key {
+                        // no column for the flag, so it goes in the name
+                        "shielded" => continue,
+                        "by" => "player",
+                        key => key,
+                    };
+                    match value {
+                        Json::Null => {}
+                        Json::String(text) => fields.push((column, text)),
+                        value => fields.push((column, value.to_string())),
+                    }
+                }
+                let shielded = matches!(event, GameEvent::PlayerHit { shielded: true, .. });
+                let name = if shielded { "shield_hit" } else { name };
+                if let Some(pos) = pos {
+                    fields.push(("x", format!("{:.1}", pos.x())));
+                    fields.push(("y", format!("{:.1}", pos.y())));
+                }
+                self.csv(time_ms, name, &fields);
+            }
+        }
+    }
+
+    // Score and health of every player, and how crowded the screen is
+    fn sample(&mut self, world: &World) {
+        for (i, p) in world.players.iter().enumerate() {
+            let fields = [
+                ("player", i.to_string()),
+                ("score", p.score.to_string()),
+                ("life", p.ship.life.to_string()),
+                ("shield", p.ship.shield.to_string()),
+                ("enemies", world.enemies.len().to_string()),
+            ];
+            match self.format {
+                Format::JsonLines => {
+                    let mut entries = vec![("time_ms", num(world.ticks)), ("type", string("sample"))];
+                    entries.extend(fields.iter().map(|(k, v)| (*k, Json::Number(v.clone()))));
+                    self.json(object(entries));
+                }
+                Format::Csv => self.csv(world.ticks, "sample", &fields),
+            }
+        }
+    }
+
+    fn frame(&mut self, time_ms: u32, frame_ms: u32) {
+        self.frame_total += frame_ms;
+        self.frame_max = self.frame_max.max(frame_ms);
+        self.frame_count += 1;
+        if self.frame_total < FRAME_WINDOW_MS {
+            return;
+        }
+        let average = self.frame_total as f32 / self.frame_count as f32;
+        let max = self.frame_max;
+        self.frame_total = 0;
+        self.frame_max = 0;
+        self.frame_count = 0;
+        match self.format {
+            Format::JsonLines => self.json(object(vec![
+                ("time_ms", num(time_ms)),
+                ("type", string("frames")),
+                ("frame_ms_avg", num(format!("{:.2}", average))),
+                ("frame_ms_max", num(max)),
+            ])),
+            Format::Csv => self.csv(time_ms, "frames", &[
+                ("frame_ms_avg", format!("{:.2}", average)),
+                ("frame_ms_max", max.to_string()),
+            ]),
+        }
+        // once a second is often enough to not lose much to a crash
+        self.flush();
+    }
+
+    fn json(&mut self, record: Json) {
+        self.line(&record.to_string());
+    }
+
+    fn csv(&mut self, time_ms: u32, kind: &str, fields: &[(&str, String)]) {
+        let row: Vec<String> = CSV_COLUMNS
+            .iter()
+            .map(|column| match *column {
+                "time_ms" => time_ms.to_string(),
+                "type" => kind.to_string(),
+                c => fields.iter().find(|(k, _)| *k == c).map_or(String::new(), |(_, v)| v.clone()),
+            })
+            .collect();
+        self.line(&row.join(","));
+    }
+
+    fn line(&mut self, line: &str) {
+        if let Err(e) = writeln!(self.out, "{}", line) {
+            warn!("telemetry: {}", e);
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if let Err(e) = self.out.flush() {
+            warn!("telemetry: {}", e);
+        }
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
@@ -0,0 +1,60 @@
+//title module - the screen the game starts on
+
+use crate::display::Anchor;
+use crate::ui::{Align, Nav, Ui};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TitleAction {
+    Play,
+    Online,
+    Mods,
+    Settings,
+    Quit,
+}
+
+const ITEMS: [(&str, TitleAction); 5] = [
+    ("PLAY", TitleAction::Play),
+    ("ONLINE CO-OP", TitleAction::Online),
+    ("MODS", TitleAction::Mods),
+    ("SETTINGS", TitleAction::Settings),
+    ("QUIT", TitleAction::Quit),
+];
+
+pub struct TitleMenu {
+    pub open: bool,
+}
+
+impl TitleMenu {
+    pub fn new() -> Self {
+        TitleMenu { open: true }
+    }
+
+    pub fn run(&mut self, ui: &mut Ui) -> Option<TitleAction> {
+        ui.begin_menu("title");
+        ui.begin_area(Anchor::Top, 0, 110, 400, 60, false);
+        ui.align(Align::Center);
+        ui.label("SPACE SHOOTER");
+        ui.end();
+
+        let mut chosen = None;
+        ui.begin_area(Anchor::Top, 0, 220, 300, 214, true);
+        for (label, action) in ITEMS.iter() {
+            if ui.button(label) {
+                chosen = Some(*action);
+            }
+        }
+        ui.end();
+        ui.end_menu();
+
+        if ui.pressed(Nav::Start) {
+            chosen = Some(TitleAction::Play);
+        }
+        else if ui.back() {
+            chosen = Some(TitleAction::Quit);
+        }
+        if chosen == Some(TitleAction::Play) {
+            self.open = false;
+        }
+        chosen
+    }
+}
@@ -0,0 +1,767 @@
+//ui module - immediate-mode widgets for the menus and HUD, skinned with
+//pieces of the UI sprite sheet
+
+use crate::config::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::display::{anchored, Anchor};
+use crate::error::Error;
+use crate::ini::{Ini, Section};
+use crate::vfs::Vfs;
+
+use sdl2::controller::Button;
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Texture, TextureCreator, WindowCanvas};
+use sdl2::ttf::Font;
+use sdl2::video::WindowContext;
+
+pub const UI_THEME_PATH: &str = "assets/ui.ini";
+
+pub const PADDING: i32 = 12;
+pub const SPACING: i32 = 4;
+const ITEM_HEIGHT: u32 = 34;
+// menus draw over the HUD whatever order they were built in
+const HUD_LAYER: u8 = 0;
+const MENU_LAYER: u8 = 1;
+const OVERLAY_LAYER: u8 = 2;
+
+// A rect on the sheet, stretched as a 9-slice when border > 0
+#[derive(Debug, Clone, Copy)]
+pub struct Piece {
+    pub src: Rect,
+    pub border: u32,
+}
+
+pub struct Theme {
+    pub sheet: String,
+    pub scale: u32,
+    pub text: Color,
+    pub text_focus: Color,
+    pub text_dim: Color,
+    pub overlay: Color,
+    pub panel: Piece,
+    pub button: Piece,
+    pub button_focus: Piece,
+    pub button_down: Piece,
+    pub field: Piece,
+    pub track: Piece,
+    pub fill: Piece,
+    pub toggle_off: Piece,
+    pub toggle_on: Piece,
+}
+
+fn numbers(section: &Section, key: &str) -> Result<Vec<u32>, String> {
+    section
+        .get(key)
+        .ok_or_else(|| format!("[{}] {}: missing", section.name, key))?
+        .split(',')
+        .map(|n| n.trim().parse::<u32>().map_err(|_| format!("[{}] {}: invalid value", section.name, key)))
+        .collect()
+}
+
+fn color(section: &Section, key: &str) -> Result<Color, String> {
+    let c = |v: u32| v.min(255) as u8;
+    match numbers(section, key)?[..] {
+        [r, g, b] => Ok(Color::RGB(c(r), c(g), c(b))),
+        [r, g, b, a] => Ok(Color::RGBA(c(r), c(g), c(b), c(a))),
+        _ => Err(format!("[{}] {}: expected r, g, b[, a]", section.name, key)),
+    }
+}
+
+impl Theme {
+    pub fn load(vfs: &Vfs, path: &str) -> Result<Self, Error> {
+        let ini = vfs.ini(path)?;
+        Theme::from_ini(&ini, &vfs.resolve(path))
+    }
+
+    // Every piece with the section it came from
+    pub fn pieces(&self) -> [(&'static str, Piece); 9] {
+        [
+            ("panel", self.panel),
+            ("button", self.button),
+            ("button_focus", self.button_focus),
+            ("button_down", self.button_down),
+            ("field", self.field),
+            ("track", self.track),
+            ("fill", self.fill),
+            ("toggle_off", self.toggle_off),
+            ("toggle_on", self.toggle_on),
+        ]
+    }
+
+    // `path` is the file the ini came from, for errors
+    fn from_ini(ini: &Ini, path: &str) -> Result<Self, Error> {
+        Theme::parse(ini).map_err(|e| Error::config(path, e))
+    }
+
+    fn parse(ini: &Ini) -> Result<Self, String> {
+        let section = |name: &str| ini.section(name).ok_or_else(|| format!("missing [{}]", name));
+        let piece = |name: &str| -> Result<Piece, String> {
+            let section = section(name)?;
+            match numbers(section, "rect")?[..] {
+                [x, y, w, h] => Ok(Piece {
+                    src: Rect::new(x as i32, y as i32, w, h),
+                    border: section.parse_or("border", 0)?,
+                }),
+                _ => Err(format!("[{}] rect: expected x, y, w, h", name)),
+            }
+        };
+        let theme = section("theme")?;
+        Ok(Theme {
+            sheet: theme.get("sheet").ok_or("[theme] sheet: missing")?.to_string(),
+            scale: theme.parse_or("scale", 1)?,
+            text: color(theme, "text")?,
+            text_focus: color(theme, "text_focus")?,
+            text_dim: color(theme, "text_dim")?,
+            overlay: color(theme, "overlay")?,
+            panel: piece("panel")?,
+            button: piece("button")?,
+            button_focus: piece("button_focus")?,
+            button_down: piece("button_down")?,
+            field: piece("field")?,
+            track: piece("track")?,
+            fill: piece("fill")?,
+            toggle_off: piece("toggle_off")?,
+            toggle_on: piece("toggle_on")?,
+        })
+    }
+}
+
+// Which texture an image comes from: the theme's sheet or the game's icons
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sheet {
+    Theme,
+    Icons,
+}
+
+enum Command {
+    Fill { rect: Rect, color: Color },
+    Image { sheet: Sheet, src: Rect, dst: Rect },
+    Text { text: String, x: i32, y: i32, color: Color },
+}
+
+// Keyboard and pad buttons all boil down to these
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Nav {
+    Up,
+    Down,
+    Left,
+    Right,
+    Next,
+    Accept,
+    Back,
+    Start,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+    Start,
+    Center,
+    End,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Direction {
+    Column,
+    Row,
+}
+
+struct Layout {
+    direction: Direction,
+    x: i32,
+    y: i32,
+    // columns stretch their items to this width
+    width: u32,
+    align: Align,
+    cursor: (i32, i32),
+    used: (u32, u32),
+    nested: bool,
+}
+
+impl Layout {
+    fn new(direction: Direction, x: i32, y: i32, width: u32, nested: bool) -> Self {
+        Layout {
+            direction,
+            x,
+            y,
+            width,
+            align: Align::Start,
+            cursor: (x, y),
+            used: (0, 0),
+            nested,
+        }
+    }
+}
+
+// What happened to an interactive widget this frame
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Response {
+    pub focused: bool,
+    pub activated: bool,
+    // -1 or 1 when left/right was pressed on it
+    pub step: i32,
+}
+
+// Widgets are functions called every frame: they lay themselves out, react
+// to the input gathered since the last frame and queue what to draw. Only
+// focus and drag state live from one frame to the next.
+pub struct Ui<'a> {
+    font: &'a Font<'a, 'a>,
+    pub theme: Theme,
+    pointer: (i32, i32),
+    pointer_moved: bool,
+    clicked: bool,
+    mouse_down: bool,
+    nav: Vec<Nav>,
+    typed: String,
+    backspace: bool,
+    // focus is remembered per menu page and forgotten once it closes
+    scope: String,
+    ran_menu: bool,
+    focus: usize,
+    focus_count: usize,
+    next_id: usize,
+    dragging: Option<usize>,
+    layouts: Vec<Layout>,
+    layer: u8,
+    commands: Vec<(u8, Command)>,
+}
+
+impl<'a> Ui<'a> {
+    pub fn new(font: &'a Font<'a, 'a>, theme: Theme) -> Self {
+        Ui {
+            font,
+            theme,
+            pointer: (0, 0),
+            pointer_moved: false,
+            clicked: false,
+            mouse_down: false,
+            nav: Vec::new(),
+            typed: String::new(),
+            backspace: false,
+            scope: String::new(),
+            ran_menu: false,
+            focus: 0,
+            focus_count: 0,
+            next_id: 0,
+            dragging: None,
+            layouts: Vec::new(),
+            layer: HUD_LAYER,
+            commands: Vec::new(),
+        }
+    }
+
+    pub fn begin_frame(&mut self) {
+        self.pointer_moved = false;
+        self.clicked = false;
+        self.nav.clear();
+        self.typed.clear();
+        self.backspace = false;
+        if !self.ran_menu {
+            self.scope.clear();
+        }
+        self.ran_menu = false;
+        self.layouts.clear();
+        self.layer = HUD_LAYER;
+        self.commands.clear();
+    }
+
+    // Mouse positions are in logical coordinates, as SDL reports them once
+    // a logical size is set
+    pub fn handle_event(&mut self, event: &Event) {
+        let nav = match event {
+            Event::MouseMotion { x, y, .. } => {
+                self.pointer = (*x, *y);
+                self.pointer_moved = true;
+                None
+            }
+            Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } => {
+                self.pointer = (*x, *y);
+                self.pointer_moved = true;
+                self.clicked = true;
+                self.mouse_down = true;
+                None
+            }
+            Event::MouseButtonUp { mouse_btn: MouseButton::Left, .. } => {
+                self.mouse_down = false;
+                self.dragging = None;
+                None
+            }
+            Event::TextInput { text, .. } => {
+                self.typed.push_str(text);
+                None
+            }
+            Event::KeyDown { keycode: Some(key), .. } => match key {
+                Keycode::Up => Some(Nav::Up),
+                Keycode::Down => Some(Nav::Down),
+                Keycode::Left => Some(Nav::Left),
+                Keycode::Right => Some(Nav::Right),
+                Keycode::Tab => Some(Nav::Next),
+                Keycode::Return | Keycode::KpEnter => Some(Nav::Accept),
+                Keycode::Escape => Some(Nav::Back),
+                Keycode::Backspace => {
+                    self.backspace = true;
+                    None
+                }
+                _ => None,
+            },
+            Event::ControllerButtonDown { button, .. } => match button {
+                Button::DPadUp => Some(Nav::Up),
+                Button::DPadDown => Some(Nav::Down),
+                Button::DPadLeft => Some(Nav::Left),
+                Button::DPadRight => Some(Nav::Right),
+                Button::A => Some(Nav::Accept),
+                Button::B => Some(Nav::Back),
+                Button::Start => Some(Nav::Start),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(nav) = nav {
+            self.nav.push(nav);
+        }
+    }
+
+    pub fn pressed(&self, nav: Nav) -> bool {
+        self.nav.contains(&nav)
+    }
+
+    pub fn back(&self) -> bool {
+        self.pressed(Nav::Back)
+    }
+
+    // Starts a page of widgets. Up/down move focus through the widgets the
+    // page had last frame.
+    pub fn begin_menu(&mut self, scope: &str) {
+        if self.scope != scope {
+            self.scope = scope.to_string();
+            self.focus = 0;
+            self.focus_count = 0;
+        }
+        let count = self.focus_count;
+        if count > 0 {
+            for nav in &self.nav {
+                match nav {
+                    Nav::Up => self.focus = (self.focus + count - 1) % count,
+                    Nav::Down | Nav::Next => self.focus = (self.focus + 1) % count,
+                    _ => {}
+                }
+            }
+            self.focus = self.focus.min(count - 1);
+        }
+        self.ran_menu = true;
+        self.next_id = 0;
+        self.layer = MENU_LAYER;
+        let overlay = self.theme.overlay;
+        self.fill(Rect::new(0, 0, SCREEN_WIDTH, SCREEN_HEIGHT), overlay);
+    }
+
+    pub fn end_menu(&mut self) {
+        self.focus_count = self.next_id;
+        self.layouts.clear();
+        self.layer = HUD_LAYER;
+    }
+
+    // Developer tools draw over everything, menus included
+    pub fn begin_overlay(&mut self) {
+        self.layer = OVERLAY_LAYER;
+    }
+
+    pub fn end_overlay(&mut self) {
+        self.layouts.clear();
+        self.layer = HUD_LAYER;
+    }
+
+    // Layout
+
+    // A column placed on the screen, optionally drawn as a panel
+    pub fn begin_area(&mut self, anchor: Anchor, x: i32, y: i32, w: u32, h: u32, panel: bool) {
+        let rect = anchored(anchor, x, y, w, h);
+        if panel {
+            let piece = self.theme.panel;
+            self.piece(piece, rect);
+        }
+        let width = (w as i32 - PADDING * 2).max(0) as u32;
+        self.layouts.push(Layout::new(Direction::Column, rect.x() + PADDING, rect.y() + PADDING, width, false));
+    }
+
+    pub fn begin_row(&mut self) {
+        let (x, y) = self.layouts.last().map_or((0, 0), |l| l.cursor);
+        self.layouts.push(Layout::new(Direction::Row, x, y, 0, true));
+    }
+
+    pub fn begin_column(&mut self, width: u32) {
+        let (x, y) = self.layouts.last().map_or((0, 0), |l| l.cursor);
+        self.layouts.push(Layout::new(Direction::Column, x, y, width, true));
+    }
+
+    // Closes the innermost area, row or column; nested ones take up their
+    // used space in the parent
+    pub fn end(&mut self) {
+        if let Some(done) = self.layouts.pop() {
+            if done.nested {
+                self.allocate(done.used.0, done.used.1);
+            }
+        }
+    }
+
+    // How items narrower than the current column sit in it
+    pub fn align(&mut self, align: Align) {
+        if let Some(layout) = self.layouts.last_mut() {
+            layout.align = align;
+        }
+    }
+
+    pub fn space(&mut self, h: u32) {
+        self.allocate(0, h);
+    }
+
+    fn item_width(&self, natural: u32) -> u32 {
+        match self.layouts.last() {
+            Some(l) if l.direction == Direction::Column && l.width > 0 => l.width,
+            _ => natural,
+        }
+    }
+
+    // Also for callers drawing something of their own with fill
+    pub fn allocate(&mut self, w: u32, h: u32) -> Rect {
+        let layout = match self.layouts.last_mut() {
+            Some(l) => l,
+            None => return Rect::new(0, 0, w.max(1), h.max(1)),
+        };
+        let (mut x, y) = layout.cursor;
+        if layout.direction == Direction::Column && layout.width > w {
+            let slack = (layout.width - w) as i32;
+            x += match layout.align {
+                Align::Start => 0,
+                Align::Center => slack / 2,
+                Align::End => slack,
+            };
+        }
+        match layout.direction {
+            Direction::Column => layout.cursor.1 += h as i32 + SPACING,
+            Direction::Row => layout.cursor.0 += w as i32 + SPACING,
+        }
+        layout.used.0 = layout.used.0.max((x + w as i32 - layout.x) as u32);
+        layout.used.1 = layout.used.1.max((y + h as i32 - layout.y) as u32);
+        Rect::new(x, y, w.max(1), h.max(1))
+    }
+
+    // Focus and activation shared by every interactive widget
+
+    fn interact(&mut self, rect: Rect) -> (usize, Response) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let hovered = rect.contains_point(self.pointer);
+        if hovered && self.pointer_moved {
+            self.focus = id;
+        }
+        let focused = self.focus == id;
+        let mut response = Response {
+            focused,
+            activated: (focused && self.pressed(Nav::Accept)) || (hovered && self.clicked),
+            step: 0,
+        };
+        if focused {
+            for nav in &self.nav {
+                match nav {
+                    Nav::Left => response.step -= 1,
+                    Nav::Right => response.step += 1,
+                    _ => {}
+                }
+            }
+        }
+        (id, response)
+    }
+
+    fn item_piece(&self, response: &Response, rect: Rect) -> Piece {
+        if response.focused && self.mouse_down && rect.contains_point(self.pointer) {
+            self.theme.button_down
+        } else if response.focused {
+            self.theme.button_focus
+        } else {
+            self.theme.button
+        }
+    }
+
+    fn text_color(&self, focused: bool) -> Color {
+        if focused { self.theme.text_focus } else { self.theme.text }
+    }
+
+    // Widgets
+
+    pub fn label(&mut self, text: &str) {
+        let color = self.theme.text;
+        self.label_colored(text, color);
+    }
+
+    pub fn label_colored(&mut self, text: &str, color: Color) {
+        let (w, h) = self.text_size(text);
+        let rect = self.allocate(w, h);
+        self.text(text, rect.x(), rect.y(), color);
+    }
+
+    pub fn button(&mut self, text: &str) -> bool {
+        let (w, _) = self.text_size(text);
+        let width = self.item_width(w + PADDING as u32 * 2);
+        let rect = self.allocate(width, ITEM_HEIGHT);
+        let (_, response) = self.interact(rect);
+        let piece = self.item_piece(&response, rect);
+        self.piece(piece, rect);
+        let color = self.text_color(response.focused);
+        self.text_in(text, rect, Align::Center, color);
+        response.activated
+    }
+
+    // A row with a name on the left and its current value on the right
+    pub fn item(&mut self, label: &str, value: &str) -> Response {
+        let natural = self.text_size(label).0 + self.text_size(value).0 + PADDING as u32 * 4;
+        let width = self.item_width(natural);
+        let rect = self.allocate(width, ITEM_HEIGHT);
+        let (_, response) = self.interact(rect);
+        let piece = self.item_piece(&response, rect);
+        self.piece(piece, rect);
+        let color = self.text_color(response.focused);
+        self.text_in(label, rect, Align::Start, color);
+        self.text_in(value, rect, Align::End, color);
+        response
+    }
+
+    // Items to pick from; `selected` follows focus, returns the one chosen
+    pub fn list(&mut self, items: &[(String, String)], selected: &mut usize) -> Option<usize> {
+        let mut chosen = None;
+        for (i, (label, value)) in items.iter().enumerate() {
+            let response = self.item(label, value);
+            if response.focused {
+                *selected = i;
+            }
+            if response.activated {
+                chosen = Some(i);
+            }
+        }
+        chosen
+    }
+
+    pub fn toggle(&mut self, label: &str, value: &mut bool) -> bool {
+        let width = self.item_width(self.text_size(label).0 + PADDING as u32 * 4 + 24);
+        let rect = self.allocate(width, ITEM_HEIGHT);
+        let (_, response) = self.interact(rect);
+        let changed = response.activated || response.step != 0;
+        if changed {
+            *value = !*value;
+        }
+        let piece = self.item_piece(&response, rect);
+        self.piece(piece, rect);
+        let color = self.text_color(response.focused);
+        self.text_in(label, rect, Align::Start, color);
+        let check = if *value { self.theme.toggle_on } else { self.theme.toggle_off };
+        let size = check.src.height() * self.theme.scale;
+        let box_rect = Rect::new(rect.right() - PADDING - size as i32, rect.y() + (rect.height() as i32 - size as i32) / 2, size, size);
+        self.piece(check, box_rect);
+        changed
+    }
+
+    // Left/right move by step; the track can also be clicked or dragged
+    pub fn slider(&mut self, label: &str, value: &mut f32, min: f32, max: f32, step: f32) -> bool {
+        let width = self.item_width(self.text_size(label).0 + PADDING as u32 * 4 + 120);
+        let rect = self.allocate(width, ITEM_HEIGHT);
+        let (id, response) = self.interact(rect);
+        let track_w = (rect.width() / 2).saturating_sub(PADDING as u32).max(1);
+        let track_h = rect.height() / 2;
+        let track = Rect::new(rect.right() - PADDING - track_w as i32, rect.y() + (rect.height() - track_h) as i32 / 2, track_w, track_h);
+        if self.clicked && track.contains_point(self.pointer) {
+            self.dragging = Some(id);
+        }
+        let old = *value;
+        if self.dragging == Some(id) && self.mouse_down {
+            let t = (self.pointer.0 - track.x()) as f32 / track_w as f32;
+            let raw = min + t.clamp(0.0, 1.0) * (max - min);
+            *value = min + ((raw - min) / step).round() * step;
+        }
+        *value = (*value + response.step as f32 * step).clamp(min, max);
+
+        let piece = self.item_piece(&response, rect);
+        self.piece(piece, rect);
+        let color = self.text_color(response.focused);
+        self.text_in(label, rect, Align::Start, color);
+        let (track_piece, fill_piece) = (self.theme.track, self.theme.fill);
+        self.piece(track_piece, track);
+        let t = if max > min { (*value - min) / (max - min) } else { 0.0 };
+        let fill_w = (track_w as f32 * t) as u32;
+        if fill_w > 0 {
+            self.piece(fill_piece, Rect::new(track.x(), track.y(), fill_w, track_h));
+        }
+        *value != old
+    }
+
+    // Typed text goes to whichever field has focus. True when confirmed.
+    pub fn text_input(&mut self, label: &str, value: &mut String, max_len: usize) -> bool {
+        let width = self.item_width(self.text_size(label).0 + PADDING as u32 * 4 + 200);
+        let rect = self.allocate(width, ITEM_HEIGHT);
+        let (_, response) = self.interact(rect);
+        if response.focused {
+            for c in self.typed.chars().filter(|c| !c.is_control() && !c.is_whitespace()) {
+                if value.chars().count() < max_len {
+                    value.push(c);
+                }
+            }
+            if self.backspace {
+                value.pop();
+            }
+        }
+        let piece = if response.focused { self.theme.button_focus } else { self.theme.field };
+        self.piece(piece, rect);
+        let color = self.text_color(response.focused);
+        self.text_in(label, rect, Align::Start, color);
+        let shown = if response.focused { format!("{}_", value) } else { value.clone() };
+        self.text_in(&shown, rect, Align::End, color);
+        response.activated
+    }
+
+    pub fn icon(&mut self, sheet: Sheet, src: Rect, w: u32, h: u32) {
+        let rect = self.allocate(w, h);
+        self.image(sheet, src, rect);
+    }
+
+    // `count` copies of an icon, wrapping after `per_row`
+    pub fn icons(&mut self, sheet: Sheet, src: Rect, count: u32, size: u32, per_row: u32) {
+        if count == 0 {
+            return;
+        }
+        let cols = count.min(per_row);
+        let rows = (count + per_row - 1) / per_row;
+        let block = self.allocate(cols * size, rows * size);
+        for i in 0..count {
+            let x = block.x() + ((i % per_row) * size) as i32;
+            let y = block.y() + ((i / per_row) * size) as i32;
+            self.image(sheet, src, Rect::new(x, y, size, size));
+        }
+    }
+
+    // A progress bar at a fixed spot, e.g. over something in the world
+    pub fn bar(&mut self, rect: Rect, fraction: f32) {
+        let (track, fill) = (self.theme.track, self.theme.fill);
+        self.piece(track, rect);
+        let w = (rect.width() as f32 * fraction.clamp(0.0, 1.0)) as u32;
+        if w > 0 {
+            self.piece(fill, Rect::new(rect.x(), rect.y(), w, rect.height()));
+        }
+    }
+
+    // One bar per value, scaled so `max` fills the height
+    pub fn graph(&mut self, values: &[f32], max: f32, w: u32, h: u32, color: Color) {
+        let rect = self.allocate(w, h);
+        self.fill(rect, Color::RGBA(0, 0, 0, 160));
+        if values.is_empty() || max <= 0.0 {
+            return;
+        }
+        let bar_w = (w / values.len() as u32).max(1);
+        for (i, value) in values.iter().enumerate() {
+            let bar_h = ((value / max).clamp(0.0, 1.0) * h as f32) as u32;
+            if bar_h > 0 {
+                let x = rect.x() + (i as u32 * bar_w) as i32;
+                self.fill(Rect::new(x, rect.bottom() - bar_h as i32, bar_w, bar_h), color);
+            }
+        }
+    }
+
+    pub fn fill(&mut self, rect: Rect, color: Color) {
+        self.commands.push((self.layer, Command::Fill { rect, color }));
+    }
+
+    // Drawing
+
+    pub fn text_size(&self, text: &str) -> (u32, u32) {
+        if text.is_empty() {
+            return (0, self.font.height().max(0) as u32);
+        }
+        self.font.size_of(text).unwrap_or((0, 0))
+    }
+
+    fn text(&mut self, text: &str, x: i32, y: i32, color: Color) {
+        if !text.is_empty() {
+            self.commands.push((self.layer, Command::Text { text: text.to_string(), x, y, color }));
+        }
+    }
+
+    fn text_in(&mut self, text: &str, rect: Rect, align: Align, color: Color) {
+        let (w, h) = self.text_size(text);
+        let x = match align {
+            Align::Start => rect.x() + PADDING,
+            Align::Center => rect.x() + (rect.width() as i32 - w as i32) / 2,
+            Align::End => rect.right() - PADDING - w as i32,
+        };
+        self.text(text, x, rect.y() + (rect.height() as i32 - h as i32) / 2, color);
+    }
+
+    fn image(&mut self, sheet: Sheet, src: Rect, dst: Rect) {
+        self.commands.push((self.layer, Command::Image { sheet, src, dst }));
+    }
+
+    // Corners keep their size (times the theme scale), edges stretch one
+    // way and the middle both
+    fn piece(&mut self, piece: Piece, dst: Rect) {
+        let b = piece.border as i32;
+        if b == 0 {
+            self.image(Sheet::Theme, piece.src, dst);
+            return;
+        }
+        let d = (b * self.theme.scale as i32).min(dst.width() as i32 / 2).min(dst.height() as i32 / 2);
+        let src = piece.src;
+        let sx = [src.x(), src.x() + b, src.right() - b, src.right()];
+        let sy = [src.y(), src.y() + b, src.bottom() - b, src.bottom()];
+        let dx = [dst.x(), dst.x() + d, dst.right() - d, dst.right()];
+        let dy = [dst.y(), dst.y() + d, dst.bottom() - d, dst.bottom()];
+        for row in 0..3 {
+            for col in 0..3 {
+                let (sw, sh) = (sx[col + 1] - sx[col], sy[row + 1] - sy[row]);
+                let (w, h) = (dx[col + 1] - dx[col], dy[row + 1] - dy[row]);
+                if sw > 0 && sh > 0 && w > 0 && h > 0 {
+                    self.image(
+                        Sheet::Theme,
+                        Rect::new(sx[col], sy[row], sw as u32, sh as u32),
+                        Rect::new(dx[col], dy[row], w as u32, h as u32),
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn render(&mut self, canvas: &mut WindowCanvas, texture_creator: &TextureCreator<WindowContext>, sheet: &Texture, icons: &Texture) -> Result<(), Error> {
+        profile!("ui");
+        // stable, so each layer keeps the order it was built in
+        self.commands.sort_by_key(|(layer, _)| *layer);
+        canvas.set_blend_mode(BlendMode::Blend);
+        // stops at the first failure but still leaves the canvas and the
+        // command list ready for the next frame
+        let result = self.commands.iter().try_for_each(|(_, command)| self.draw(command, canvas, texture_creator, sheet, icons));
+        canvas.set_blend_mode(BlendMode::None);
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.commands.clear();
+        result
+    }
+
+    fn draw(&self, command: &Command, canvas: &mut WindowCanvas, texture_creator: &TextureCreator<WindowContext>, sheet: &Texture, icons: &Texture) -> Result<(), Error> {
+        match command {
+            Command::Fill { rect, color } => {
+                canvas.set_draw_color(*color);
+                canvas.fill_rect(*rect).map_err(Error::render)
+            }
+            Command::Image { sheet: which, src, dst } => {
+                let texture = match which {
+                    Sheet::Theme => sheet,
+                    Sheet::Icons => icons,
+                };
+                canvas.copy(texture, *src, *dst).map_err(Error::render)
+            }
+            Command::Text { text, x, y, color } => {
+                profile!("text");
+                let surface = self.font.render(text).solid(*color).map_err(Error::render)?;
+                let texture = texture_creator.create_texture_from_surface(&surface).map_err(Error::render)?;
+                canvas.copy(&texture, None, Rect::new(*x, *y, surface.width(), surface.height())).map_err(Error::render)
+            }
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//validate module - checks the game's assets without opening a window:
+//everything the game and its data files refer to is read and decoded, every
+//sprite rect is checked against its sheet, particle presets are checked as
+//loading checks them, scripts are compiled, and files under assets/ nothing
+//refers to are listed
+//
+//  game-rs --validate-assets
+//
+//Broken assets are errors and make the game exit with one, so a content
+//pipeline can stop on them. Unused files are only reported. Animations set
+//from scripts depend on the script running and aren't checked.
+
+use crate::asset_manager::{FontDetails, Pixels};
+use crate::config::*;
+use crate::entity::{self, Entity, EntityType, Look, Sprites, ENEMY_VARIANTS, POWERUPS, SPRITES_PATH};
+use crate::error::Error;
+use crate::loading::{Manifest, SCENES_DIR};
+use crate::particles::{ParticlePreset, PARTICLES_PATH};
+use crate::script::{Scripts, SCRIPTS_DIR};
+use crate::sound::{self, CLIPS};
+use crate::ui::{Theme, UI_THEME_PATH};
+use crate::vfs::{Vfs, ASSETS_DIR};
+use crate::world::Player;
+
+use glam::Vec2;
+use sdl2::image::InitFlag;
+use sdl2::rect::Rect;
+use sdl2::rwops::RWops;
+use sdl2::ttf::Sdl2TtfContext;
+
+use std::collections::HashMap;
+use std::fmt::Display;
+
+struct Report<'a> {
+    vfs: &'a Vfs,
+    // paths as the game asks for them
+    used: Vec<String>,
+    // each sheet is decoded once; None if it wouldn't
+    sizes: HashMap<String, Option<(u32, u32)>>,
+    problems: Vec<String>,
+    checked: usize,
+}
+
+impl<'a> Report<'a> {
+    fn problem(&mut self, e: impl Display) {
+        self.problems.push(e.to_string());
+    }
+
+    fn uses(&mut self, path: &str) {
+        if !self.used.iter().any(|p| p == path) {
+            self.used.push(path.to_string());
+        }
+    }
+
+    fn image(&mut self, path: &str) -> Option<(u32, u32)> {
+        self.uses(path);
+        if let Some(size) = self.sizes.get(path) {
+            return *size;
+        }
+        self.checked += 1;
+        let size = match Pixels::read(self.vfs, path) {
+            Ok(pixels) => Some((pixels.width, pixels.height)),
+            Err(e) => {
+                self.problem(e);
+                None
+            }
+        };
+        self.sizes.insert(path.to_string(), size);
+        size
+    }
+
+    // `what` is how the rect is named in the message
+    fn rect(&mut self, sheet: &str, rect: Rect, what: &str) {
+        let (w, h) = match self.image(sheet) {
+            Some(size) => size,
+            None => return,
+        };
+        self.checked += 1;
+        let inside = rect.x() >= 0 && rect.y() >= 0 && rect.right() as u32 <= w && rect.bottom() as u32 <= h;
+        if !inside {
+            self.problem(format!(
+                "{}: {} at {},{} {}x{} is outside the {}x{} sheet",
+                self.vfs.resolve(sheet),
+                what,
+                rect.x(),
+                rect.y(),
+                rect.width(),
+                rect.height(),
+                w,
+                h
+            ));
+        }
+    }
+
+    fn font(&mut self, ttf: &Sdl2TtfContext, font: &FontDetails) {
+        self.uses(&font.path);
+        self.checked += 1;
+        let fail = |e: String| Error::decode(self.vfs.resolve(&font.path), e);
+        let result = self.vfs.read(&font.path).and_then(|bytes| {
+            let rw = RWops::from_bytes(&bytes).map_err(fail)?;
+            ttf.load_font_from_rwops(rw, font.size).map(|_| ()).map_err(fail)
+        });
+        if let Err(e) = result {
+            self.problem(e);
+        }
+    }
+
+    fn sound(&mut self, path: &str) {
+        self.uses(path);
+        self.checked += 1;
+        if let Err(e) = sound::check_wav(self.vfs, path) {
+            self.problem(e);
+        }
+    }
+}
+
+// One of every entity the game spawns, named for messages
+fn entities() -> Vec<(String, Entity)> {
+    let mut all = Vec::new();
+    for index in 0..MAX_PLAYERS {
+        all.push((format!("player {}", index + 1), Player::new(index, MAX_PLAYERS).ship));
+    }
+    for row in 0..ENEMY_VARIANTS {
+        for col in 0..ENEMY_VARIANTS {
+            let mut enemy = Entity::new(EntityType::Enemy);
+            enemy.anim = entity::stock_animation();
+            enemy.look = Look::Enemy(row, col);
+            all.push((format!("enemy {},{}", row, col), enemy));
+        }
+    }
+    for typ in POWERUPS {
+        all.push((typ.name().to_string(), entity::make_powerup(typ, Vec2::zero())));
+    }
+    let ship = Player::new(0, 1).ship;
+    let target = ship.trans.pos + Vec2::new(1.0, 0.0);
+    all.push(("bullet".to_string(), entity::spawn_bullet(&ship, &target, BULLET_SPEED)));
+    all.push(("enemy bullet".to_string(), entity::spawn_enemy_bullet(&ship, &target, BULLET_SPEED)));
+    all
+}
+
+// Checks everything, prints what it found and fails if anything is broken
+pub fn run(vfs: &Vfs) -> Result<(), Error> {
+    let _image_context = sdl2::image::init(InitFlag::PNG | InitFlag::JPG).map_err(|e| Error::init("SDL_image", e))?;
+    let ttf_context = sdl2::ttf::init().map_err(|e| Error::init("SDL_ttf", e))?;
+    let mut report = Report { vfs, used: Vec::new(), sizes: HashMap::new(), problems: Vec::new(), checked: 0 };
+
+    report.uses(UI_THEME_PATH);
+    match Theme::load(vfs, UI_THEME_PATH) {
+        Ok(theme) => {
+            for (name, piece) in theme.pieces() {
+                report.rect(&theme.sheet, piece.src, &format!("[{}] rect", name));
+            }
+        }
+        Err(e) => report.problem(e),
+    }
+
+    let mut fonts = vec![FontDetails { path: FONT_PATH.to_string(), size: FONT_SIZE }];
+    for path in vfs.list(SCENES_DIR).iter().filter(|p| p.ends_with(".ini")) {
+        report.uses(path);
+        let scene = path.trim_start_matches(SCENES_DIR).trim_start_matches('/').trim_end_matches(".ini");
+        match Manifest::load(vfs, scene) {
+            Ok(manifest) => {
+                for texture in &manifest.textures {
+                    report.image(texture);
+                }
+                for font in manifest.fonts {
+                    if !fonts.contains(&font) {
+                        fonts.push(font);
+                    }
+                }
+            }
+            Err(e) => report.problem(e),
+        }
+    }
+
+    report.image(BACKGROUND_SHEET);
+    report.uses(SPRITES_PATH);
+    match Sprites::load(vfs, SPRITES_PATH) {
+        Ok(sprites) => {
+            for (name, entity) in entities() {
+                for (frame, rect) in sprites.place(&entity).frame_rects().into_iter().enumerate() {
+                    report.rect(entity::sheet(&entity.typ), rect, &format!("{} frame {}", name, frame));
+                }
+            }
+        }
+        Err(e) => report.problem(e),
+    }
+
+    report.uses(PARTICLES_PATH);
+    // each preset on its own, so one with a bad range doesn't hide the rest
+    match vfs.ini(PARTICLES_PATH) {
+        Ok(ini) => {
+            for section in &ini.sections {
+                report.checked += 1;
+                match ParticlePreset::from_section(section, &vfs.resolve(PARTICLES_PATH)) {
+                    Ok(preset) => {
+                        for frame in 0..preset.frames {
+                            report.rect(MISC_SHEET, preset.frame_rect(frame), &format!("[{}] frame {}", preset.name, frame));
+                        }
+                    }
+                    Err(e) => report.problem(e),
+                }
+            }
+        }
+        Err(e) => report.problem(e),
+    }
+
+    for font in &fonts {
+        report.font(&ttf_context, font);
+    }
+    for (_, path) in CLIPS {
+        report.sound(path);
+    }
+    // every script in the directory is loaded, so every one has to compile
+    let scripts = Scripts::unloaded(vfs.clone());
+    for path in vfs.list(SCRIPTS_DIR).iter().filter(|p| p.ends_with(".rhai")) {
+        report.uses(path);
+        report.checked += 1;
+        if let Err(e) = scripts.check(path) {
+            report.problem(e);
+        }
+    }
+
+    let unused: Vec<String> = vfs.files(ASSETS_DIR).into_iter().filter(|f| !report.used.contains(f)).collect();
+    for path in &unused {
+        println!("unused: {}", vfs.resolve(path));
+    }
+    for problem in &report.problems {
+        println!("error: {}", problem);
+    }
+    println!("checked {} assets and sprite rects: {} problems, {} unused files", report.checked, report.problems.len(), unused.len());
+    if report.problems.is_empty() {
+        Ok(())
+    }
+    else {
+        Err(Error::Other(format!("{} problems with the game's assets", report.problems.len())))
+    }
+}
@@ -0,0 +1,334 @@
+//vfs module - asset paths looked up through layers: the game's own files
+//at the bottom, then every enabled mod in priority order on top
+//
+//The game's own files are assets/ next to the executable (or in the working
+//directory when running from a checkout), packed into assets.pak, or both,
+//in which case loose files win so a single file can be swapped for testing.
+//
+//A mod is a directory under MODS_DIR holding a mod.ini and files laid out
+//like the game's, e.g. mods/big_ships/assets/SpaceShooterAssetPack_Ships.png
+//replaces the ship sheet and mods/big_ships/assets/scripts/boss.rhai adds a
+//script. mod.ini looks like
+//
+//  [mod]
+//  name = Big Ships
+//  version = 1.0
+//  author = someone
+//  description = Everything is bigger
+//  priority = 10
+//
+//  [config]
+//  enemy_speed = 150
+//
+//where higher priorities win and [config] takes the same names as the
+//console's set command.
+
+use crate::config::Tuning;
+use crate::error::Error;
+use crate::ini::Ini;
+use crate::pack::{walk, Archive, PACK_PATH};
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+pub const MODS_DIR: &str = "mods";
+pub const MANIFEST: &str = "mod.ini";
+pub const ASSETS_DIR: &str = "assets";
+// where files out of the archive go for things that can only open a path
+const EXTRACT_DIR: &str = "game-rs-assets";
+
+#[derive(Debug, Clone)]
+pub struct ModInfo {
+    // the directory name, which is what settings remember it by
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub author: String,
+    pub description: String,
+    pub priority: i32,
+    pub enabled: bool,
+    pub config: Vec<(String, String)>,
+    // paths as the game asks for them
+    pub files: Vec<String>,
+    // files another enabled mod also provides
+    pub conflicts: Vec<String>,
+}
+
+impl ModInfo {
+    fn load(id: &str, root: &Path) -> Result<Self, Error> {
+        let manifest = root.join(MANIFEST);
+        let ini = Ini::load(&manifest.to_string_lossy())?;
+        let bad = |e: String| Error::config(manifest.display(), e);
+        let section = ini.section("mod").ok_or_else(|| bad("no [mod] section".to_string()))?;
+        let text = |key: &str| section.get(key).unwrap_or("").to_string();
+        let mut files = Vec::new();
+        walk(root, root, &mut files);
+        files.retain(|f| f != MANIFEST);
+        files.sort();
+        Ok(ModInfo {
+            id: id.to_string(),
+            name: section.get("name").unwrap_or(id).to_string(),
+            version: text("version"),
+            author: text("author"),
+            description: text("description"),
+            priority: section.parse_or("priority", 0).map_err(bad)?,
+            enabled: false,
+            config: ini.section("config").map_or(Vec::new(), |c| c.entries.clone()),
+            files,
+            conflicts: Vec::new(),
+        })
+    }
+}
+
+// The directory the game's files are in: the working directory when it has
+// them, otherwise the executable's directory or one above it, so both an
+// installed copy and `cargo run` from anywhere find them
+pub fn find_root() -> PathBuf {
+    let has_assets = |dir: &Path| dir.join(ASSETS_DIR).is_dir() || dir.join(PACK_PATH).is_file();
+    let mut candidates = vec![PathBuf::from(".")];
+    if let Ok(exe) = env::current_exe() {
+        // target/debug/game-rs is three up from the checkout
+        candidates.extend(exe.ancestors().skip(1).take(3).map(Path::to_path_buf));
+    }
+    match candidates.iter().find(|dir| has_assets(dir)) {
+        Some(dir) => dir.clone(),
+        None => {
+            warn!("no {}/ or {} in the working directory or next to the executable", ASSETS_DIR, PACK_PATH);
+            PathBuf::from(".")
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Layer {
+    Dir(PathBuf),
+    Archive(Archive),
+}
+
+impl Layer {
+    fn has(&self, path: &str) -> bool {
+        match self {
+            Layer::Dir(root) => root.join(path).is_file(),
+            Layer::Archive(archive) => archive.contains(path),
+        }
+    }
+
+    fn location(&self) -> String {
+        match self {
+            Layer::Dir(root) => format!("{}/", root.display()),
+            Layer::Archive(archive) => archive.path.display().to_string(),
+        }
+    }
+
+    fn name(&self, path: &str) -> String {
+        match self {
+            Layer::Dir(root) if root == Path::new(".") => path.to_string(),
+            Layer::Dir(root) => root.join(path).to_string_lossy().replace('\\', "/"),
+            Layer::Archive(archive) => format!("{}:{}", archive.path.display(), path),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Vfs {
+    // searched from the end, so later layers win
+    layers: Vec<Layer>,
+    // every mod found, enabled or not, in the order they are layered
+    pub mods: Vec<ModInfo>,
+}
+
+impl Vfs {
+    // Just the game's own files under root: the archive if there is one,
+    // with loose files over it
+    pub fn new(root: &Path) -> Self {
+        let mut layers = Vec::new();
+        let pack = root.join(PACK_PATH);
+        if pack.is_file() {
+            match Archive::open(&pack) {
+                Ok(archive) => {
+                    info!("mounted {}", pack.display());
+                    layers.push(Layer::Archive(archive));
+                }
+                Err(e) => error!("{}", e),
+            }
+        }
+        layers.push(Layer::Dir(root.to_path_buf()));
+        Vfs {
+            layers,
+            mods: Vec::new(),
+        }
+    }
+
+    // Finds everything under MODS_DIR and layers the ones `enabled` says
+    // yes to. A mod that can't be read is reported and left out.
+    pub fn with_mods(root: &Path, enabled: impl Fn(&str) -> bool) -> Self {
+        let mut vfs = Vfs::new(root);
+        let mods_dir = root.join(MODS_DIR);
+        let mut ids: Vec<String> = match fs::read_dir(&mods_dir) {
+            Ok(dir) => dir
+                .filter_map(|e| e.ok())
+                .filter(|e| e.path().join(MANIFEST).is_file())
+                .map(|e| e.file_name().to_string_lossy().to_string())
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        ids.sort();
+        for id in ids {
+            match ModInfo::load(&id, &mods_dir.join(&id)) {
+                Ok(mut info) => {
+                    info.enabled = enabled(&id);
+                    vfs.mods.push(info);
+                }
+                Err(e) => warn!("skipping mod {}: {}", id, e),
+            }
+        }
+        // stable, so equal priorities stay in name order
+        vfs.mods.sort_by_key(|m| m.priority);
+
+        for i in 0..vfs.mods.len() {
+            if !vfs.mods[i].enabled {
+                continue;
+            }
+            let conflicts: Vec<String> = vfs.mods[i]
+                .files
+                .iter()
+                .filter(|f| vfs.mods.iter().enumerate().any(|(j, m)| j != i && m.enabled && m.files.contains(f)))
+                .cloned()
+                .collect();
+            let info = &mut vfs.mods[i];
+            info!("mod {} {} enabled, {} files", info.name, info.version, info.files.len());
+            info.conflicts = conflicts;
+            vfs.layers.push(Layer::Dir(mods_dir.join(&info.id)));
+        }
+        // each clash once, naming everyone involved and who wins
+        let mut reported: Vec<&String> = Vec::new();
+        for file in vfs.mods.iter().flat_map(|m| m.conflicts.iter()) {
+            if reported.contains(&file) {
+                continue;
+            }
+            reported.push(file);
+            let providers: Vec<&str> = vfs.mods.iter().filter(|m| m.enabled && m.files.contains(file)).map(|m| m.name.as_str()).collect();
+            warn!("{} is in mods {}; using {}", file, providers.join(", "), providers.last().unwrap_or(&""));
+        }
+        vfs
+    }
+
+    fn layer_for(&self, path: &str) -> Result<&Layer, Error> {
+        self.layers.iter().rev().find(|layer| layer.has(path)).ok_or_else(|| Error::NotFound {
+            path: path.to_string(),
+            searched: self.layers.iter().rev().map(Layer::location).collect(),
+        })
+    }
+
+    // Where `path` is read from, for messages. Paths nothing provides are
+    // handed back as they are.
+    pub fn resolve(&self, path: &str) -> String {
+        match self.layer_for(path) {
+            Ok(layer) => layer.name(path),
+            Err(_) => path.to_string(),
+        }
+    }
+
+    pub fn read(&self, path: &str) -> Result<Vec<u8>, Error> {
+        match self.layer_for(path)? {
+            Layer::Dir(root) => fs::read(root.join(path)).map_err(|e| Error::io(root.join(path).display(), e)),
+            Layer::Archive(archive) => archive.read(path),
+        }
+    }
+
+    pub fn read_to_string(&self, path: &str) -> Result<String, Error> {
+        String::from_utf8(self.read(path)?).map_err(|_| Error::decode(self.resolve(path), "not UTF-8 text"))
+    }
+
+    pub fn ini(&self, path: &str) -> Result<Ini, Error> {
+        Ini::parse(&self.read_to_string(path)?, &self.resolve(path))
+    }
+
+    // A real file holding `path`, for things that will only open a path
+    // themselves. Files from the archive are copied out to a temporary
+    // directory first.
+    pub fn file(&self, path: &str) -> Result<PathBuf, Error> {
+        match self.layer_for(path)? {
+            Layer::Dir(root) => Ok(root.join(path)),
+            Layer::Archive(archive) => {
+                let file = env::temp_dir().join(EXTRACT_DIR).join(path);
+                if let Some(dir) = file.parent() {
+                    fs::create_dir_all(dir).map_err(|e| Error::io(dir.display(), e))?;
+                }
+                fs::write(&file, archive.read(path)?).map_err(|e| Error::io(file.display(), e))?;
+                Ok(file)
+            }
+        }
+    }
+
+    // When `path` last changed, for loose files only; the archive only
+    // changes between runs
+    pub fn modified(&self, path: &str) -> Option<SystemTime> {
+        match self.layer_for(path).ok()? {
+            Layer::Dir(root) => fs::metadata(root.join(path)).and_then(|m| m.modified()).ok(),
+            Layer::Archive(_) => None,
+        }
+    }
+
+    // Files directly in `dir` from every layer, each named once
+    pub fn list(&self, dir: &str) -> Vec<String> {
+        let dir = dir.trim_end_matches('/');
+        let mut names: Vec<String> = Vec::new();
+        for layer in &self.layers {
+            let found: Vec<String> = match layer {
+                Layer::Dir(root) => match fs::read_dir(root.join(dir)) {
+                    Ok(entries) => entries
+                        .filter_map(|e| e.ok())
+                        .filter(|e| e.path().is_file())
+                        .map(|e| format!("{}/{}", dir, e.file_name().to_string_lossy()))
+                        .collect(),
+                    Err(_) => Vec::new(),
+                },
+                Layer::Archive(archive) => archive
+                    .names()
+                    .filter(|name| name.strip_prefix(dir).and_then(|rest| rest.strip_prefix('/')).is_some_and(|rest| !rest.contains('/')))
+                    .cloned()
+                    .collect(),
+            };
+            for name in found {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        names.sort();
+        names
+    }
+
+    // Every file anywhere under `dir` from every layer, each named once
+    pub fn files(&self, dir: &str) -> Vec<String> {
+        let dir = dir.trim_end_matches('/');
+        let mut names = Vec::new();
+        for layer in &self.layers {
+            match layer {
+                Layer::Dir(root) => walk(root, &root.join(dir), &mut names),
+                Layer::Archive(archive) => names.extend(
+                    archive.names().filter(|name| name.strip_prefix(dir).is_some_and(|rest| rest.starts_with('/'))).cloned(),
+                ),
+            }
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    // Default tuning with each enabled mod's [config] applied in order
+    pub fn tuning(&self) -> Tuning {
+        let mut tuning = Tuning::new();
+        for info in self.mods.iter().filter(|m| m.enabled) {
+            for (name, value) in &info.config {
+                if let Err(e) = tuning.set(name, value) {
+                    warn!("mod {}: {}", info.name, e);
+                }
+            }
+        }
+        tuning
+    }
+}
@@ -0,0 +1,486 @@
+//world module - the game simulation, independent of input devices and rendering
+
+use crate::config::*;
+use crate::entity::*;
+use crate::events::{Cause, GameEvent};
+use crate::input::PlayerInput;
+use crate::profiler::Scope;
+use crate::script::{world_map, Command, Scripts, MAX_SPAWNS};
+
+use glam::Vec2;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use std::rc::Rc;
+
+#[derive(Clone)]
+pub struct Player {
+    pub ship: Entity,
+    pub score: i32,
+    // milliseconds a teammate has spent next to this player while they are down
+    pub revive: u32,
+}
+
+impl Player {
+    pub fn new(index: usize, count: usize) -> Self {
+        let mut ship = Entity::new(EntityType::Player);
+        ship.trans.scale = Vec2::new(24.0,24.0); //size = 24;
+        ship.trans.pos = spawn_point(index, count);
+        // each player flies a different ship
+        ship.anim = stock_animation();
+        ship.look = Look::Player(index);
+        Player {
+            ship,
+            score: 0,
+            revive: 0,
+        }
+    }
+
+    pub fn alive(&self) -> bool {
+        self.ship.life > 0
+    }
+}
+
+// players start side by side around the middle of the screen
+fn spawn_point(index: usize, count: usize) -> Vec2 {
+    let offset = index as f32 - (count - 1) as f32 / 2.0;
+    Vec2::new(400.0 + offset * 60.0, 300.0)
+}
+
+// Everything here is a pure function of the seed and the inputs, so two
+// machines fed the same inputs stay in step and a copy can be rewound.
+#[derive(Clone)]
+pub struct World {
+    pub players: Vec<Player>,
+    pub enemies: Vec<Entity>,
+    pub bullets: Vec<Entity>,
+    pub powerups: Vec<Entity>,
+    pub friendly_fire: bool,
+    pub tuning: Tuning,
+    // nobody takes damage; for testing from the debug console
+    pub god: bool,
+    // only set for local games, since peers can't be assumed to have the
+    // same scripts
+    pub scripts: Option<Rc<Scripts>>,
+    // simulated milliseconds since the world was created
+    pub ticks: u32,
+    time_elapsed: u32,
+    time_powerup: u32,
+    rng: StdRng,
+}
+
+impl World {
+    pub fn new(player_count: usize, seed: u64) -> Self {
+        let count = player_count.clamp(1, MAX_PLAYERS);
+        let mut world = World {
+            players: (0..count).map(|i| Player::new(i, count)).collect(),
+            enemies: Vec::new(),
+            bullets: Vec::new(),
+            powerups: Vec::new(),
+            friendly_fire: false,
+            tuning: Tuning::new(),
+            god: false,
+            scripts: None,
+            ticks: 0,
+            time_elapsed: 0,
+            time_powerup: 0,
+            rng: StdRng::seed_from_u64(seed),
+        };
+        world.spawn_enemies(3);
+        world
+    }
+
+    pub fn restart(&mut self, events: &mut Vec<GameEvent>) {
+        let count = self.players.len();
+        self.players = (0..count).map(|i| Player::new(i, count)).collect();
+        self.enemies.clear();
+        self.powerups.clear();
+        self.spawn_enemies(3);
+        events.push(GameEvent::Restarted);
+    }
+
+    pub fn spawn_enemies(&mut self, count: u32) {
+        for _ in 0..count {
+            self.enemies.push(spawn_enemy(&mut self.rng, self.tuning.enemy_speed));
+        }
+    }
+
+    // Enemy bullets from anywhere on screen heading every which way, for
+    // stress testing
+    pub fn spawn_bullets(&mut self, count: u32) {
+        let mut source = Entity::new(EntityType::Enemy);
+        for _ in 0..count {
+            source.trans.pos = Vec2::new(self.rng.gen_range(0.0..SCREEN_WIDTH as f32), self.rng.gen_range(0.0..SCREEN_HEIGHT as f32));
+            let angle = self.rng.gen_range(0.0..std::f32::consts::TAU);
+            let target = source.trans.pos + Vec2::new(angle.cos(), angle.sin());
+            self.bullets.push(spawn_enemy_bullet(&source, &target, self.tuning.bullet_speed));
+        }
+    }
+
+    // Drops a powerup right on top of the first living player, who picks
+    // it up on the next step
+    pub fn give(&mut self, typ: EntityType) {
+        if let Some(player) = self.players.iter().find(|p| p.alive()) {
+            self.powerups.push(make_powerup(typ, player.ship.trans.pos));
+        }
+    }
+
+    // The game is only over once nobody is left to revive anyone.
+    pub fn game_over(&self) -> bool {
+        self.players.iter().all(|p| !p.alive())
+    }
+
+    // FNV-1a over the state that matters for play. Peers compare these to
+    // notice when their simulations have drifted apart.
+    pub fn checksum(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut mix = |value: u32| {
+            for byte in value.to_le_bytes().iter() {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+        mix(self.ticks);
+        for p in &self.players {
+            mix(p.score as u32);
+            mix(p.revive);
+        }
+        let ships = self.players.iter().map(|p| &p.ship);
+        for e in ships.chain(&self.enemies).chain(&self.bullets).chain(&self.powerups) {
+            mix(e.trans.pos.x().to_bits());
+            mix(e.trans.pos.y().to_bits());
+            mix(e.life as u32);
+            mix(e.shield as u32);
+        }
+        hash
+    }
+
+    // Advances the game by dt_ms, with one input per player (missing ones
+    // count as idle). A step of 0 (hit-stop, pause) does nothing at all, so
+    // nothing keyed off the tick count fires repeatedly.
+    pub fn update(&mut self, inputs: &[PlayerInput], dt_ms: u32, events: &mut Vec<GameEvent>) {
+        if dt_ms == 0 {
+            return;
+        }
+        profile!("world");
+        if self.game_over() && inputs.iter().any(|i| i.restart) {
+            self.restart(events);
+            return;
+        }
+        let delta_time = dt_ms as f32 / 1000.0;
+        let first_event = events.len();
+        self.time_elapsed += dt_ms;
+        self.time_powerup += dt_ms;
+        self.ticks += dt_ms;
+        let ticks = self.ticks;
+
+        let players = &mut self.players;
+        let enemies = &mut self.enemies;
+        let bullets = &mut self.bullets;
+        let powerups = &mut self.powerups;
+        let rng = &mut self.rng;
+        let tuning = self.tuning;
+        let god = self.god;
+        let scripts = self.scripts.clone();
+        let scripted_ai = scripts.as_ref().map_or(false, |s| s.has("enemy_update"));
+
+        // each phase is timed on its own for the profiler
+        let phase = Scope::new("input");
+        let idle = PlayerInput::new();
+        for (index, player) in players.iter_mut().enumerate() {
+            let input = inputs.get(index).unwrap_or(&idle);
+            if !player.alive() {
+                // downed ships stay where they are so a teammate can reach them
+                player.ship.update_position(Vec2::zero(), tuning.player_speed, delta_time);
+                continue;
+            }
+            let ship = &mut player.ship;
+            if let Some(aim) = input.aim {
+                ship.trans.rotate_to_vec2(aim);
+            }
+            if let Some(dir) = input.aim_dir {
+                ship.trans.rotate_to_dir(dir);
+            }
+            if input.fire_pressed || (input.fire && ticks % 450 < 4) {
+                // without an aim point shoot the way the ship is facing
+                let target = input.aim.unwrap_or_else(|| {
+                    let rot = (ship.trans.rot as f32).to_radians();
+                    ship.trans.pos + Vec2::new(rot.sin(), -rot.cos())
+                });
+                let mut bullet = spawn_bullet(ship, &target, tuning.bullet_speed);
+                bullet.owner = Some(index);
+                bullets.push(bullet);
+                events.push(GameEvent::Shot { pos: ship.trans.center() });
+            }
+            if input.bomb && ship.shield > 0 {
+                ship.shield -= 1;
+                let center = ship.trans.center();
+                let near = |e: &Entity| (e.trans.center() - center).length_squared() < BOMB_RADIUS * BOMB_RADIUS;
+                for e in enemies.iter_mut().filter(|e| e.life > 0 && near(e)) {
+                    e.life = 0;
+                    events.push(GameEvent::EnemyKilled { pos: e.trans.center(), by: Some(index) });
+                }
+                for b in bullets.iter_mut().filter(|b| b.typ == EntityType::EnemyBullet && near(b)) {
+                    b.life = 0;
+                }
+            }
+
+            ship.update_position(input.movement, tuning.player_speed, delta_time);
+            // everyone shares one screen, so nobody may fly off it
+            let max = Vec2::new(SCREEN_WIDTH as f32, SCREEN_HEIGHT as f32) - ship.trans.scale;
+            ship.trans.pos = ship.trans.pos.max(Vec2::zero()).min(max);
+        }
+        drop(phase);
+
+        let phase = Scope::new("spawn");
+        if self.time_elapsed > tuning.spawn_rate {
+            let e = spawn_enemy(rng, tuning.enemy_speed);
+            events.push(GameEvent::EnemySpawned { pos: e.trans.center() });
+            enemies.push(e);
+            self.time_elapsed = 0;
+        }
+
+        if self.time_powerup > tuning.powerup_rate {
+            let p = spawn_powerup(rng);
+            events.push(GameEvent::PowerupSpawned { pos: p.trans.center() });
+            powerups.push(p);
+            self.time_powerup = 0;
+        }
+        drop(phase);
+
+        let phase = Scope::new("ai");
+        for e in enemies.iter_mut() {
+            e.update(delta_time);
+            // go after whichever living player is closest
+            let target = players
+                .iter()
+                .filter(|p| p.alive())
+                .map(|p| p.ship.trans.pos)
+                .min_by(|a, b| {
+                    (*a - e.trans.pos).length_squared()
+                        .total_cmp(&(*b - e.trans.pos).length_squared())
+                });
+            if let Some(s) = scripts.as_ref().filter(|_| scripted_ai) {
+                s.enemy_update(e, target);
+                continue;
+            }
+            if let Some(target) = target {
+                let dist = e.trans.pos - target;
+                if dist.length_squared() < tuning.aggro_radius * tuning.aggro_radius {
+                    e.trans.rotate_to_vec2(target);
+                    if ticks % 800 < 2 {
+                        bullets.push(spawn_enemy_bullet(e, &target, tuning.bullet_speed));
+                        events.push(GameEvent::Shot { pos: e.trans.center() });
+                    }
+                }
+            }
+        }
+        drop(phase);
+
+        let phase = Scope::new("movement");
+        for e in bullets.iter_mut() {
+            e.update(delta_time);
+        }
+        drop(phase);
+
+        let phase = Scope::new("collision");
+        for (index, player) in players.iter_mut().enumerate() {
+            let p_rect = player.ship.get_rect();
+            for p in powerups.iter_mut() {
+                let e_rect = p.get_rect();
+                if p_rect.has_intersection(e_rect) && player.alive() && p.life > 0 {
+                    p.life = 0;
+                    let scripted = scripts.as_ref().map_or(false, |s| s.pickup(&p.typ, index));
+                    match p.typ {
+                        // a script has already done whatever this powerup does
+                        _ if scripted => {}
+                        EntityType::PowerupHealth => {
+                            player.ship.life += 1;
+                        }
+                        EntityType::PowerupShield => {
+                            player.ship.shield += 1;
+                        }
+                        EntityType::PowerupNuke => {
+                            for e in enemies.iter_mut() {
+                                e.life = 0;
+                                events.push(GameEvent::EnemyKilled { pos: e.trans.center(), by: Some(index) });
+                            }
+                        }
+                        EntityType::PowerupBulletSpeed => {
+                            for r in (0..=360).step_by(20) {
+                                let mut v = Vec2::new(
+                                    p.trans.pos.x() * f32::cos(f32::to_radians(r as f32)),
+                                    p.trans.pos.y() * f32::sin(f32::to_radians(r as f32))
+                                );
+                                v *= tuning.bullet_speed;
+                                let mut bullet = spawn_bullet(p, &v, tuning.bullet_speed);
+                                bullet.owner = Some(index);
+                                bullets.push(bullet);
+                            }
+                            events.push(GameEvent::Shot { pos: p.trans.center() });
+                        }
+                        _ => {}
+                    }
+                    events.push(GameEvent::PowerupCollected { pos: p.trans.center(), typ: p.typ.clone() });
+                }
+            }
+
+            for e in enemies.iter_mut() {
+                if e.life > 0 && player.alive() && p_rect.has_intersection(e.get_rect()) {
+                    e.life = 0;
+                    hurt(&mut player.ship, index, Cause::Enemy, god, events);
+                    events.push(GameEvent::EnemyKilled { pos: e.trans.center(), by: Some(index) });
+                }
+            }
+
+            for b in bullets.iter_mut() {
+                let cause = match (&b.typ, b.owner) {
+                    (EntityType::EnemyBullet, _) => Some(Cause::EnemyBullet),
+                    (EntityType::Bullet, Some(owner)) if self.friendly_fire && owner != index => Some(Cause::Teammate(owner)),
+                    _ => None,
+                };
+                if let Some(cause) = cause {
+                    if b.life > 0 && player.alive() && b.trans.get_rect().has_intersection(p_rect) {
+                        b.life = 0;
+                        hurt(&mut player.ship, index, cause, god, events);
+                    }
+                }
+            }
+        }
+
+        for e in enemies.iter_mut() {
+            let e_rect = e.get_rect();
+            for b in bullets.iter_mut() {
+                let b_rect = b.trans.get_rect();
+                // Check if the rectangles collide
+                if b.typ == EntityType::Bullet && b.life > 0 && b_rect.has_intersection(e_rect) {
+                    e.life = 0;
+                    b.life = 0;
+                    events.push(GameEvent::EnemyKilled { pos: e.trans.center(), by: b.owner });
+                    if let Some(owner) = b.owner.and_then(|i| players.get_mut(i)) {
+                        owner.score += 500;
+                    }
+                }
+            }
+        }
+
+        revive(players, dt_ms, events);
+        drop(phase);
+
+        let phase = Scope::new("cleanup");
+        enemies.retain(|e| e.life > 0);
+        bullets.retain(|e| e.life > 0);
+        powerups.retain(|e| e.life > 0);
+        drop(phase);
+
+        if let Some(s) = scripts {
+            profile!("scripts");
+            s.tick(world_map(self));
+            // anything the commands cause isn't fed back, so scripts can't
+            // set each other off forever
+            s.on_events(&events[first_event..]);
+            self.run_commands(s.take_commands(), events);
+        }
+    }
+
+    fn run_commands(&mut self, commands: Vec<Command>, events: &mut Vec<GameEvent>) {
+        let tuning = self.tuning;
+        let mut spawns = 0;
+        for command in commands {
+            if matches!(command, Command::SpawnEnemy { .. } | Command::SpawnPowerup { .. } | Command::Fire { .. }) {
+                spawns += 1;
+                if spawns > MAX_SPAWNS {
+                    continue;
+                }
+            }
+            match command {
+                Command::SpawnEnemy { pos, vel } => {
+                    let mut e = spawn_enemy(&mut self.rng, tuning.enemy_speed);
+                    e.trans.pos = pos;
+                    if vel != Vec2::zero() {
+                        e.trans.vel = vel;
+                    }
+                    events.push(GameEvent::EnemySpawned { pos: e.trans.center() });
+                    self.enemies.push(e);
+                }
+                Command::SpawnPowerup { typ, pos } => {
+                    let p = make_powerup(typ, pos);
+                    events.push(GameEvent::PowerupSpawned { pos: p.trans.center() });
+                    self.powerups.push(p);
+                }
+                Command::Fire { from, to } if from != to => {
+                    let mut source = Entity::new(EntityType::Enemy);
+                    source.trans.pos = from;
+                    self.bullets.push(spawn_enemy_bullet(&source, &to, tuning.bullet_speed));
+                    events.push(GameEvent::Shot { pos: from });
+                }
+                Command::Fire { .. } => {}
+                // only the living can be topped up; reviving is for teammates
+                Command::Heal { player, amount } => {
+                    if let Some(p) = self.players.get_mut(player).filter(|p| p.alive()) {
+                        p.ship.life = p.ship.life.saturating_add(amount.max(0));
+                    }
+                }
+                Command::Shield { player, amount } => {
+                    if let Some(p) = self.players.get_mut(player).filter(|p| p.alive()) {
+                        p.ship.shield = p.ship.shield.saturating_add(amount.max(0));
+                    }
+                }
+                Command::Score { player, amount } => {
+                    if let Some(p) = self.players.get_mut(player) {
+                        p.score = p.score.saturating_add(amount);
+                    }
+                }
+                Command::KillEnemies { by } => {
+                    for e in self.enemies.drain(..) {
+                        events.push(GameEvent::EnemyKilled { pos: e.trans.center(), by });
+                    }
+                }
+            }
+        }
+        if spawns > MAX_SPAWNS {
+            warn!("scripts spawned {} things in one step; only the first {} were added", spawns, MAX_SPAWNS);
+        }
+    }
+}
+
+fn hurt(ship: &mut Entity, player: usize, cause: Cause, god: bool, events: &mut Vec<GameEvent>) {
+    events.push(GameEvent::PlayerHit { pos: ship.trans.center(), shielded: ship.shield > 0, player, cause });
+    if god {
+        return;
+    }
+    if ship.shield > 0 {
+        ship.shield -= 1;
+    }
+    else {
+        ship.life -= 1;
+    }
+    if ship.life == 0 {
+        events.push(GameEvent::PlayerKilled { pos: ship.trans.center(), player, cause });
+    }
+}
+
+// A downed player comes back once a living teammate has stayed close to
+// them for REVIVE_TIME; moving away resets the progress.
+fn revive(players: &mut [Player], dt_ms: u32, events: &mut Vec<GameEvent>) {
+    let helpers: Vec<Vec2> = players.iter().filter(|p| p.alive()).map(|p| p.ship.trans.center()).collect();
+    for (index, player) in players.iter_mut().enumerate() {
+        if player.alive() {
+            player.revive = 0;
+            continue;
+        }
+        let pos = player.ship.trans.center();
+        if helpers.iter().any(|h| (*h - pos).length_squared() < REVIVE_RADIUS * REVIVE_RADIUS) {
+            player.revive += dt_ms;
+        }
+        else {
+            player.revive = 0;
+        }
+        if player.revive >= REVIVE_TIME {
+            player.revive = 0;
+            player.ship.life = REVIVE_LIFE;
+            player.ship.shield = 0;
+            events.push(GameEvent::PlayerRevived { pos, player: index });
+        }
+    }
+}